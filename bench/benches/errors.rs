@@ -0,0 +1,32 @@
+//! Benchmarks for error creation with and without populated messages
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use pam_client::conv_null::Conversation;
+use pam_client::Context;
+
+/// Invalid item type, so each lookup fails with `BAD_ITEM`
+const INVALID_ITEM: libc::c_int = 9999;
+
+fn bench_errors(c: &mut Criterion) {
+	let mut context = Context::new("test", None, Conversation::new()).unwrap();
+
+	c.bench_function("error with message", |b| {
+		b.iter(|| black_box(context.get_item(black_box(INVALID_ITEM)).unwrap_err()))
+	});
+
+	context.set_populate_messages(false);
+	c.bench_function("error without message", |b| {
+		b.iter(|| black_box(context.get_item(black_box(INVALID_ITEM)).unwrap_err()))
+	});
+}
+
+criterion_group!(benches, bench_errors);
+criterion_main!(benches);
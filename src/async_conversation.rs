@@ -0,0 +1,349 @@
+//! Asynchronous conversation trait and blocking adapter
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+use crate::error::ErrorCode;
+use crate::{BinaryType, ConversationHandler};
+
+use std::ffi::{CStr, CString};
+use std::future::Future;
+use std::mem::ManuallyDrop;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, RawWaker, RawWakerVTable, Waker};
+use std::thread::{self, Thread};
+
+/// Boxed future returned by the methods of [`AsyncConversationHandler`]
+pub type ConvFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Asynchronous variant of [`ConversationHandler`]
+///
+/// Lets asynchronous user interfaces answer PAM prompts with futures.
+/// PAM itself is blocking, so a handler implementing this trait is used
+/// through a [`BlockingAdapter`], which waits for each future on the
+/// thread running the PAM operation.
+///
+/// The methods correspond to the ones of [`ConversationHandler`], see
+/// there for the expected error codes.
+pub trait AsyncConversationHandler {
+	/// Called when a context takes ownership of the handler, see
+	/// [`ConversationHandler::init()`].
+	///
+	/// The default implementation does nothing.
+	fn init(&mut self, _default_user: Option<&str>) {}
+
+	/// Obtains a string whilst echoing text (e.g. username)
+	fn prompt_echo_on<'a>(
+		&'a mut self,
+		prompt: &'a CStr,
+	) -> ConvFuture<'a, Result<CString, ErrorCode>>;
+
+	/// Obtains a string without echoing any text (e.g. password)
+	fn prompt_echo_off<'a>(
+		&'a mut self,
+		prompt: &'a CStr,
+	) -> ConvFuture<'a, Result<CString, ErrorCode>>;
+
+	/// Displays some text.
+	fn text_info<'a>(&'a mut self, msg: &'a CStr) -> ConvFuture<'a, ()>;
+
+	/// Displays an error message.
+	fn error_msg<'a>(&'a mut self, msg: &'a CStr) -> ConvFuture<'a, ()>;
+
+	/// Obtains a yes/no answer (Linux specific).
+	///
+	/// The default implementation calls `prompt_echo_on` and maps any answer
+	/// starting with 'y' or 'j' to "yes" and everything else to "no".
+	fn radio_prompt<'a>(&'a mut self, prompt: &'a CStr) -> ConvFuture<'a, Result<bool, ErrorCode>> {
+		Box::pin(async move {
+			let prompt = [prompt.to_bytes(), b" [y/N]\0"].concat();
+			let prompt = CStr::from_bytes_with_nul(&prompt).map_err(|_| ErrorCode::BUF_ERR)?;
+			self.prompt_echo_on(prompt)
+				.await
+				.map(|s| matches!(s.as_bytes_with_nul()[0], b'Y' | b'y' | b'j' | b'J'))
+		})
+	}
+
+	/// Exchanges binary data (Linux specific, experimental).
+	///
+	/// The default implementation returns a conversation error.
+	fn binary_prompt<'a>(
+		&'a mut self,
+		_type: BinaryType,
+		_data: &'a [u8],
+	) -> ConvFuture<'a, Result<(BinaryType, Vec<u8>), ErrorCode>> {
+		Box::pin(async { Err(ErrorCode::CONV_ERR) })
+	}
+
+	/// Maximum payload size in bytes accepted for binary messages, see
+	/// [`ConversationHandler::max_binary_len()`].
+	fn max_binary_len(&self) -> usize {
+		crate::DEFAULT_MAX_BINARY_LEN
+	}
+
+	/// Checks that the handler is ready to answer prompts, see
+	/// [`ConversationHandler::self_test()`].
+	///
+	/// The default implementation succeeds without prompting, as the
+	/// prompts of asynchronous handlers are usually shown to a user.
+	fn self_test(&mut self) -> ConvFuture<'_, Result<(), ErrorCode>> {
+		Box::pin(async { Ok(()) })
+	}
+}
+
+/// Internal: Waker vtable unparking the thread stored in an `Arc<Thread>`
+static THREAD_WAKER_VTABLE: RawWakerVTable =
+	RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+/// Internal: Clones a thread waker.
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+	// Safety: `data` came from `Arc::into_raw()` and stays owned by the
+	// original waker
+	let thread = ManuallyDrop::new(Arc::from_raw(data.cast::<Thread>()));
+	let cloned = Arc::clone(&thread);
+	RawWaker::new(Arc::into_raw(cloned).cast(), &THREAD_WAKER_VTABLE)
+}
+
+/// Internal: Wakes and consumes a thread waker.
+unsafe fn wake(data: *const ()) {
+	Arc::from_raw(data.cast::<Thread>()).unpark();
+}
+
+/// Internal: Wakes a thread waker without consuming it.
+unsafe fn wake_by_ref(data: *const ()) {
+	(*data.cast::<Thread>()).unpark();
+}
+
+/// Internal: Drops a thread waker.
+unsafe fn drop_waker(data: *const ()) {
+	drop(Arc::from_raw(data.cast::<Thread>()));
+}
+
+/// Runs `future` to completion on the current thread.
+///
+/// Parks the thread while the future is pending and polls again when it
+/// is woken.
+fn block_on<F: Future>(future: F) -> F::Output {
+	let thread = Arc::into_raw(Arc::new(thread::current()));
+	// Safety: the vtable functions match the `Arc<Thread>` data pointer
+	let waker = unsafe { Waker::from_raw(RawWaker::new(thread.cast(), &THREAD_WAKER_VTABLE)) };
+	let mut cx = TaskContext::from_waker(&waker);
+	let mut future = Box::pin(future);
+	loop {
+		match future.as_mut().poll(&mut cx) {
+			Poll::Ready(output) => return output,
+			// Spurious wakeups just poll the future again
+			Poll::Pending => thread::park(),
+		}
+	}
+}
+
+/// Adapter running an [`AsyncConversationHandler`] as a
+/// [`ConversationHandler`]
+///
+/// Each callback blocks the thread running the PAM operation until the
+/// future returned by the wrapped handler completes, using a minimal
+/// executor that parks the thread while the future is pending.
+///
+/// # Executor requirement
+///
+/// The futures are polled outside of any async runtime, so they must be
+/// woken by other threads, e.g. by a channel whose sending end is used by
+/// the user interface running on its own runtime. Futures that rely on
+/// the reactor or timers of a specific runtime (like I/O or sleep futures
+/// of Tokio) fail or never complete here. Run PAM operations on a thread
+/// dedicated to blocking work (e.g. `spawn_blocking`), never on a runtime
+/// worker thread.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{AsyncConversationHandler, BlockingAdapter, ConvFuture, ErrorCode};
+/// use std::ffi::{CStr, CString};
+///
+/// struct Fixed;
+///
+/// impl AsyncConversationHandler for Fixed {
+///     fn prompt_echo_on<'a>(&'a mut self, _: &'a CStr) -> ConvFuture<'a, Result<CString, ErrorCode>> {
+///         Box::pin(async { Ok(CString::new("user").unwrap()) })
+///     }
+///     fn prompt_echo_off<'a>(&'a mut self, _: &'a CStr) -> ConvFuture<'a, Result<CString, ErrorCode>> {
+///         Box::pin(async { Ok(CString::new("secret").unwrap()) })
+///     }
+///     fn text_info<'a>(&'a mut self, _: &'a CStr) -> ConvFuture<'a, ()> {
+///         Box::pin(async {})
+///     }
+///     fn error_msg<'a>(&'a mut self, _: &'a CStr) -> ConvFuture<'a, ()> {
+///         Box::pin(async {})
+///     }
+/// }
+///
+/// let handler = BlockingAdapter::new(Fixed);
+/// # let context = pam_client::Context::new("test", None, handler).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BlockingAdapter<H> {
+	handler: H,
+}
+
+impl<H> BlockingAdapter<H> {
+	/// Wraps an asynchronous conversation handler
+	#[must_use]
+	pub const fn new(handler: H) -> Self {
+		Self { handler }
+	}
+
+	/// Returns a reference to the wrapped handler
+	#[must_use]
+	pub const fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the wrapped handler
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Unwraps the wrapped handler
+	#[must_use]
+	pub fn into_inner(self) -> H {
+		self.handler
+	}
+}
+
+impl<H: AsyncConversationHandler> ConversationHandler for BlockingAdapter<H> {
+	fn init(&mut self, default_user: Option<&str>) {
+		self.handler.init(default_user);
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		block_on(self.handler.prompt_echo_on(prompt))
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		block_on(self.handler.prompt_echo_off(prompt))
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		block_on(self.handler.text_info(msg));
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		block_on(self.handler.error_msg(msg));
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		block_on(self.handler.radio_prompt(prompt))
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		block_on(self.handler.binary_prompt(type_, data))
+	}
+
+	fn max_binary_len(&self) -> usize {
+		self.handler.max_binary_len()
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		block_on(self.handler.self_test())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::mpsc::{channel, Receiver, TryRecvError};
+	use std::sync::Mutex;
+
+	/// Future completing with a value sent from another thread
+	struct Remote {
+		receiver: Receiver<CString>,
+		waker: Arc<Mutex<Option<Waker>>>,
+	}
+
+	impl Future for Remote {
+		type Output = Result<CString, ErrorCode>;
+
+		fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+			*self.waker.lock().unwrap() = Some(cx.waker().clone());
+			match self.receiver.try_recv() {
+				Ok(value) => Poll::Ready(Ok(value)),
+				Err(TryRecvError::Empty) => Poll::Pending,
+				Err(TryRecvError::Disconnected) => Poll::Ready(Err(ErrorCode::CONV_ERR)),
+			}
+		}
+	}
+
+	/// Handler answering prompts from another thread
+	#[derive(Default)]
+	struct Handler {
+		messages: Vec<CString>,
+	}
+
+	impl AsyncConversationHandler for Handler {
+		fn prompt_echo_on<'a>(
+			&'a mut self,
+			prompt: &'a CStr,
+		) -> ConvFuture<'a, Result<CString, ErrorCode>> {
+			let (sender, receiver) = channel();
+			let waker = Arc::new(Mutex::new(None::<Waker>));
+			let remote_waker = Arc::clone(&waker);
+			let answer = prompt.to_owned();
+			thread::spawn(move || {
+				thread::sleep(std::time::Duration::from_millis(10));
+				sender.send(answer).unwrap();
+				if let Some(waker) = remote_waker.lock().unwrap().take() {
+					waker.wake();
+				}
+			});
+			Box::pin(Remote { receiver, waker })
+		}
+
+		fn prompt_echo_off<'a>(
+			&'a mut self,
+			_prompt: &'a CStr,
+		) -> ConvFuture<'a, Result<CString, ErrorCode>> {
+			Box::pin(async { Err(ErrorCode::CONV_ERR) })
+		}
+
+		fn text_info<'a>(&'a mut self, msg: &'a CStr) -> ConvFuture<'a, ()> {
+			Box::pin(async move { self.messages.push(msg.to_owned()) })
+		}
+
+		fn error_msg<'a>(&'a mut self, msg: &'a CStr) -> ConvFuture<'a, ()> {
+			self.text_info(msg)
+		}
+	}
+
+	#[test]
+	fn test_adapter() {
+		let text = CString::new("test").unwrap();
+		let yes = CString::new("yes").unwrap();
+		let mut adapter = BlockingAdapter::new(Handler::default());
+		adapter.init(None);
+		assert_eq!(adapter.prompt_echo_on(&text), Ok(text.clone()));
+		assert_eq!(adapter.prompt_echo_off(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(adapter.radio_prompt(&yes), Ok(true));
+		assert_eq!(adapter.radio_prompt(&text), Ok(false));
+		assert_eq!(
+			adapter.binary_prompt(BinaryType::Ok, b""),
+			Err(ErrorCode::CONV_ERR)
+		);
+		assert_eq!(adapter.max_binary_len(), crate::DEFAULT_MAX_BINARY_LEN);
+		assert_eq!(adapter.self_test(), Ok(()));
+		adapter.text_info(&text);
+		adapter.error_msg(&text);
+		assert_eq!(adapter.handler().messages.len(), 2);
+		adapter.handler_mut().messages.clear();
+		assert!(adapter.into_inner().messages.is_empty());
+	}
+}
@@ -0,0 +1,87 @@
+//! Audit records of PAM transactions
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::conv_mock::LogEntry;
+use crate::error::ErrorCode;
+use crate::Flag;
+use std::time::Duration;
+
+/// Summary of a PAM operation for audit logs
+///
+/// Created with [`Context::audit_record()`][`crate::Context::audit_record()`].
+/// Only holds non-secret PAM items, so authentication tokens and
+/// conversation contents can never end up in an audit log.
+///
+/// With the `"serde"` feature this struct is serializable, e.g. to write
+/// one JSON object per line to an audit log file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditRecord {
+	/// The service name
+	pub service: String,
+	/// The target user, if known
+	pub user: Option<String>,
+	/// The remote host, if set
+	pub rhost: Option<String>,
+	/// The terminal name, if set
+	pub tty: Option<String>,
+	/// The error code of the operation or `None` on success
+	pub outcome: Option<ErrorCode>,
+	/// Creation time in seconds since the Unix epoch
+	pub timestamp: u64,
+}
+
+impl AuditRecord {
+	/// Returns whether the recorded operation succeeded.
+	#[inline]
+	#[must_use]
+	pub fn is_success(&self) -> bool {
+		self.outcome.is_none()
+	}
+}
+
+/// Complete record of an authentication attempt
+///
+/// Created by [`Context::authenticate_attempt()`][`crate::Context::authenticate_attempt()`]
+/// and [`verify_password_attempt()`][`crate::verify_password_attempt()`].
+/// In addition to the outcome it holds the flags, the duration and the
+/// messages the PAM modules sent during the attempt. Responses to prompts
+/// are never recorded, so passwords and other secrets can't end up in the
+/// record.
+///
+/// With the `"serde"` feature this struct is serializable, e.g. to send
+/// attempts to an audit pipeline as structured records.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuthAttempt {
+	/// The service name
+	pub service: String,
+	/// The target user, if known
+	pub user: Option<String>,
+	/// The flags passed to the authentication
+	pub flags: Flag,
+	/// The error code of the attempt or `None` on success
+	pub outcome: Option<ErrorCode>,
+	/// Time spent authenticating, including the conversation
+	pub duration: Duration,
+	/// Info, error and binary messages sent by the PAM modules
+	pub log: Vec<LogEntry>,
+}
+
+impl AuthAttempt {
+	/// Returns whether the recorded attempt succeeded.
+	#[inline]
+	#[must_use]
+	pub fn is_success(&self) -> bool {
+		self.outcome.is_none()
+	}
+}
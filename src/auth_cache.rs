@@ -0,0 +1,40 @@
+//! Caching of recent authentications
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+/// Storage of recent successful authentications
+///
+/// Used by
+/// [`Context::authenticate_unless_cached()`][`crate::Context::authenticate_unless_cached()`]
+/// to skip re-authentication within a time window, like the timestamps
+/// of `sudo`. The application provides the storage, e.g. timestamp files
+/// or an in-memory map, and decides how long an entry stays fresh.
+///
+/// # Security considerations
+///
+/// A cached authentication lets anyone acting under the same key pass
+/// without proving their identity again. Implementations should:
+/// - use keys that are specific enough, e.g. include the user *and* the
+///   terminal or login session, so another session of the same user
+///   can't reuse the entry,
+/// - keep the window short and use a monotonic clock, so changing the
+///   system time doesn't extend it,
+/// - protect persistent storage against modification by the users,
+/// - remove entries when the user logs out or explicitly asks for it
+///   (like `sudo -k`).
+pub trait AuthCache {
+	/// Returns whether a successful authentication for `key` was recorded
+	/// recently enough to be reused.
+	fn is_fresh(&self, key: &str) -> bool;
+
+	/// Records a successful authentication for `key`.
+	fn record(&self, key: &str);
+}
@@ -8,10 +8,22 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
  ***********************************************************************/
 
+use crate::audit::{AuditRecord, AuthAttempt};
+use crate::auth_cache::AuthCache;
+use crate::conv_foreign::Conversation as ForeignConversation;
+use crate::conv_mock;
+use crate::conversation::ProviderConversation;
+use crate::encoding::EncodingPolicy;
 use crate::env_list::EnvList;
 use crate::error::{Error, ErrorCode};
-use crate::ffi::{from_pam_conv, into_pam_conv};
-use crate::session::{Session, SessionToken};
+use crate::fail_delay::{DelayJitter, DelaySink};
+use crate::ffi::{
+	from_pam_conv, into_pam_conv, raw_pam_conv, take_fail_delay, take_handler_error,
+	take_user_cancelled, EncodingPolicyGuard,
+};
+use crate::privileges;
+use crate::session::{Session, SessionToken, SessionTransaction};
+use crate::snapshot::ItemsSnapshot;
 use crate::{char_ptr_to_str, ConversationHandler};
 extern crate libc;
 extern crate pam_sys;
@@ -26,16 +38,20 @@ use pam_sys::{
 	pam_getenv, pam_getenvlist, pam_open_session, pam_putenv, pam_set_item, pam_setcred, pam_start,
 };
 use std::cell::Cell;
-use std::ffi::{CStr, CString, OsStr};
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::marker::PhantomData;
-use std::mem::ManuallyDrop;
+use std::mem::{self, ManuallyDrop};
+use std::net::SocketAddr;
 use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::ptr::NonNull;
-use std::{ptr, slice};
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{ptr, slice, thread};
 
 /// Internal: Builds getters/setters for string-typed PAM items.
 macro_rules! impl_pam_str_item {
-	($name:ident, $set_name:ident, $item_type:expr$(, $doc:literal$(, $extdoc:literal)?)?$(,)?) => {
+	($name:ident, $set_name:ident, $item_type:expr$(, $doc:literal$(, $extdoc:literal$(, $setdoc:literal)?)?)?$(,)?) => {
 		$(#[doc = "Returns "]#[doc = $doc]$(#[doc = "\n\n"]#[doc = $extdoc])?)?
 		pub fn $name(&self) -> Result<String> {
 			let ptr = self.get_item($item_type as c_int)?;
@@ -46,7 +62,7 @@ macro_rules! impl_pam_str_item {
 			return Ok(string);
 		}
 
-		$(#[doc = "Sets "]#[doc = $doc])?
+		$(#[doc = "Sets "]#[doc = $doc]$($(#[doc = "\n\n"]#[doc = $setdoc])?)?)?
 		pub fn $set_name(&mut self, value: Option<&str>) -> Result<()> {
 			match value {
 				None => unsafe { self.set_item($item_type as c_int, ptr::null()) },
@@ -112,14 +128,134 @@ struct XAuthData {
 	pub data: *const c_char,
 }
 
+/// Item type of `PAM_REPOSITORY` (Solaris specific)
+#[cfg(any(target_os = "solaris", target_os = "illumos", doc))]
+const PAM_REPOSITORY: c_int = 10;
+
+/// Special struct for the `PAM_REPOSITORY` pam item (Solaris specific)
+///
+/// Uses const pointers for the same reasons as [`XAuthData`].
+#[cfg(any(target_os = "solaris", target_os = "illumos", doc))]
+#[repr(C)]
+#[derive(Debug)]
+struct RepositoryData {
+	/// Name of the repository as a null terminated string
+	pub type_: *const c_char,
+	/// Repository-specific scope data
+	pub scope: *const c_void,
+	/// Length of `scope` in bytes
+	pub scope_len: libc::size_t,
+}
+
+/// Outcome of an account validation with [`Context::authorize_only()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccountStatus {
+	/// The account is valid and access is permitted
+	Valid,
+	/// Access is permitted, but the password has expired and must be
+	/// changed with [`Context::chauthtok()`]
+	NewAuthtokRequired,
+	/// The account has expired
+	Expired,
+	/// Access is denied, e.g. because of time or host restrictions
+	Denied,
+}
+
+/// Outcome of an authentication with [`Context::authenticate_outcome()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuthOutcome {
+	/// The user was authenticated
+	Authenticated,
+	/// The user was authenticated, but the password has expired and must
+	/// be changed with [`Context::chauthtok()`] before continuing
+	PasswordChangeRequired,
+}
+
+/// Step of [`Context::authenticate_and_check()`] that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuthStep {
+	/// Authentication with [`Context::authenticate()`]
+	Authenticate,
+	/// Account validation with [`Context::acct_mgmt()`]
+	AcctMgmt,
+}
+
+/// PAM items for [`Context::set_items()`] and [`Context::item_bytes()`]
+///
+/// All items except the binary [`Item::XAuthData`] are strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Item {
+	/// The service name, see [`Context::set_service()`]
+	Service,
+	/// The username, see [`Context::set_user()`]
+	User,
+	/// The prompt for the username, see [`Context::set_user_prompt()`]
+	UserPrompt,
+	/// The terminal name, see [`Context::set_tty()`]
+	Tty,
+	/// The requesting user name, see [`Context::set_ruser()`]
+	Ruser,
+	/// The requesting hostname, see [`Context::set_rhost()`]
+	Rhost,
+	/// The default password type (Linux specific), see
+	/// [`Context::set_authtok_type()`]
+	AuthtokType,
+	/// The name of the X display (Linux specific), see
+	/// [`Context::set_xdisplay()`]
+	XDisplay,
+	/// X authentication data (Linux specific, binary), see
+	/// [`Context::xauthdata()`]
+	XAuthData,
+}
+
 /// Main struct for PAM interaction
 ///
 /// Manages a PAM context holding the transaction state.
 ///
 /// See the [crate documentation][`crate`] for examples.
+///
+/// # Thread safety
+///
+/// A context is [`Send`] if its conversation handler is, so it can be
+/// created on one thread and used on another. All conversation handlers of
+/// this crate are `Send`. PAM itself doesn't bind a handle to the thread
+/// that created it; it only forbids concurrent calls on the same handle.
+///
+/// A context is never [`Sync`], as the PAM library and the modules mutate
+/// the handle state even in functions taking `&self`:
+///
+/// ```compile_fail
+/// fn assert_sync<T: Sync>() {}
+/// assert_sync::<pam_client::Context<pam_client::conv_null::Conversation>>();
+/// ```
+///
+/// To run authentications in parallel use one context per transaction,
+/// e.g. with a [`ThreadedAuthenticator`][`crate::ThreadedAuthenticator`].
+/// Sharing a context between threads requires a [`Mutex`][`std::sync::Mutex`].
+///
+/// Some PAM modules are not thread-safe themselves (e.g. ones relying on
+/// non-reentrant NSS functions); consider running such policies on a
+/// single worker thread.
 pub struct Context<ConvT> {
 	handle: PamHandle,
 	last_status: Cell<c_int>,
+	conversation_error: Cell<Option<ErrorCode>>,
+	session_open: bool,
+	delay_sink: Option<Box<dyn DelaySink>>,
+	delay_jitter: Option<Box<dyn DelayJitter>>,
+	populate_messages: bool,
+	init_groups: bool,
+	encoding_policy: EncodingPolicy,
+	attempted_users: Vec<CString>,
+	credentials_established: bool,
+	confdir: Option<PathBuf>,
+	borrowed: bool,
+	released: bool,
+	detached_conversation: Option<NonNull<ConvT>>,
 	_conversation: PhantomData<ConvT>,
 }
 
@@ -163,6 +299,28 @@ where
 		username: Option<&str>,
 		boxed_conv: Box<ConvT>,
 	) -> Result<Self> {
+		Self::start(service, username, boxed_conv, None)
+	}
+
+	/// Internal: Creates a PAM context, optionally with an alternative
+	/// configuration directory.
+	///
+	/// `pam_start_confdir` is only available in Linux-PAM 1.4 and later, so
+	/// it is looked up at runtime to keep linking with older versions.
+	pub(crate) fn start(
+		service: &str,
+		username: Option<&str>,
+		boxed_conv: Box<ConvT>,
+		confdir: Option<&Path>,
+	) -> Result<Self> {
+		type StartConfdirFn = unsafe extern "C" fn(
+			*const c_char,
+			*const c_char,
+			*const PamConversation,
+			*const c_char,
+			*mut *mut RawPamHandle,
+		) -> c_int;
+
 		let mut handle: *mut RawPamHandle = ptr::null_mut();
 
 		let c_service = CString::new(service).map_err(|_| Error::from(ErrorCode::BUF_ERR))?;
@@ -170,18 +328,42 @@ where
 			None => None,
 			Some(name) => Some(CString::new(name).map_err(|_| Error::from(ErrorCode::BUF_ERR))?),
 		};
+		let start_confdir = match confdir {
+			None => None,
+			Some(path) => {
+				let c_confdir = CString::new(path.as_os_str().as_bytes())
+					.map_err(|_| Error::from(ErrorCode::BUF_ERR))?;
+				let symbol = unsafe {
+					libc::dlsym(libc::RTLD_DEFAULT, b"pam_start_confdir\0".as_ptr().cast())
+				};
+				if symbol.is_null() {
+					return Err(Error::with_message(
+						ErrorCode::SYMBOL_ERR,
+						"pam_start_confdir is not supported by the PAM library",
+					));
+				}
+				// Safety: the symbol has the signature of `StartConfdirFn`
+				let func = unsafe { mem::transmute::<*mut c_void, StartConfdirFn>(symbol) };
+				Some((func, c_confdir))
+			}
+		};
 
 		// Create callback struct for C code
 		let pam_conv = into_pam_conv(boxed_conv);
 
 		// Start the PAM context
 		match unsafe {
-			pam_start(
-				c_service.as_ptr(),
-				c_username.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
-				&pam_conv,
-				&mut handle,
-			)
+			let c_username = c_username.as_ref().map_or(ptr::null(), |s| s.as_ptr());
+			match &start_confdir {
+				None => pam_start(c_service.as_ptr(), c_username, &pam_conv, &mut handle),
+				Some((func, c_confdir)) => func(
+					c_service.as_ptr(),
+					c_username,
+					&pam_conv,
+					c_confdir.as_ptr(),
+					&mut handle,
+				),
+			}
 		} {
 			PAM_SUCCESS => {
 				// A null pointer should never happen on PAM_SUCCESS, but we need to check to make sure.
@@ -191,6 +373,19 @@ where
 				let mut result = Self {
 					handle,
 					last_status: Cell::new(PAM_SUCCESS),
+					conversation_error: Cell::new(None),
+					session_open: false,
+					delay_sink: None,
+					delay_jitter: None,
+					populate_messages: true,
+					init_groups: true,
+					encoding_policy: EncodingPolicy::Lossy,
+					attempted_users: Vec::new(),
+					credentials_established: false,
+					confdir: confdir.map(Path::to_path_buf),
+					borrowed: false,
+					released: false,
+					detached_conversation: None,
 					_conversation: PhantomData,
 				};
 				// Initialize the conversation handler
@@ -230,7 +425,590 @@ where
 	///   again after the asynchronous conversation finished.
 	#[rustversion::attr(since(1.48), doc(alias = "pam_authenticate"))]
 	pub fn authenticate(&mut self, flags: Flag) -> Result<()> {
-		self.wrap_pam_return(unsafe { pam_authenticate(self.handle().into(), flags.bits()) })
+		self.wrap_operation("authenticate", || {
+			self.wrap_pam_return(unsafe { pam_authenticate(self.handle().into(), flags.bits()) })
+		})
+	}
+
+	/// Authenticates a user and measures how long it took.
+	///
+	/// Works like [`authenticate()`][`Self::authenticate()`], but also
+	/// returns the wall-clock time spent in the call, e.g. for metrics.
+	/// The duration includes the time spent in the conversation handler
+	/// and any fail delay imposed by the modules, and is returned on
+	/// failure as well.
+	pub fn authenticate_timed(&mut self, flags: Flag) -> (Result<()>, Duration) {
+		let start = Instant::now();
+		let result = self.authenticate(flags);
+		(result, start.elapsed())
+	}
+
+	/// Authenticates the user unless a recent authentication is cached,
+	/// then validates the account.
+	///
+	/// If `cache` reports a fresh entry for `key`, the authentication is
+	/// skipped, otherwise [`authenticate()`][`Self::authenticate()`] is
+	/// called. In both cases [`acct_mgmt()`][`Self::acct_mgmt()`] runs
+	/// afterwards, so locked or expired accounts are rejected even within
+	/// the cache window. After both steps succeeded the authentication is
+	/// recorded in `cache` again.
+	///
+	/// Returns `true` if the authentication was skipped because of the
+	/// cache. See [`AuthCache`] for the security considerations of caching
+	/// authentications.
+	///
+	/// ```rust
+	/// use pam_client::{AuthCache, Context, Flag};
+	/// # use pam_client::conv_mock::Conversation;
+	/// use std::cell::RefCell;
+	/// use std::collections::HashMap;
+	/// use std::time::{Duration, Instant};
+	///
+	/// struct Timestamps(RefCell<HashMap<String, Instant>>);
+	///
+	/// impl AuthCache for Timestamps {
+	///     fn is_fresh(&self, key: &str) -> bool {
+	///         matches!(self.0.borrow().get(key),
+	///                  Some(time) if time.elapsed() < Duration::from_secs(300))
+	///     }
+	///     fn record(&self, key: &str) {
+	///         self.0.borrow_mut().insert(key.to_owned(), Instant::now());
+	///     }
+	/// }
+	///
+	/// let cache = Timestamps(RefCell::new(HashMap::new()));
+	/// # let mut context = Context::new("test", Some("user"), Conversation::with_credentials("user", "pass")).unwrap();
+	/// match context.authenticate_unless_cached(&cache, "user@/dev/pts/0", Flag::NONE) {
+	///     Ok(true) => println!("Recently authenticated"),
+	///     Ok(false) => println!("Authenticated"),
+	///     Err(e) => println!("Access denied: {}", e),
+	/// }
+	/// ```
+	///
+	/// # Errors
+	/// See [`authenticate()`][`Self::authenticate()`] and
+	/// [`acct_mgmt()`][`Self::acct_mgmt()`].
+	pub fn authenticate_unless_cached(
+		&mut self,
+		cache: &impl AuthCache,
+		key: &str,
+		flags: Flag,
+	) -> Result<bool> {
+		let cached = cache.is_fresh(key);
+		if !cached {
+			self.authenticate(flags)?;
+		}
+		self.acct_mgmt(flags)?;
+		cache.record(key);
+		Ok(cached)
+	}
+
+	/// Authenticates a user and validates the account in one step.
+	///
+	/// Performs [`authenticate()`][`Self::authenticate()`] followed by
+	/// [`acct_mgmt()`][`Self::acct_mgmt()`]. Authentication alone doesn't
+	/// check whether the account is expired, locked or restricted, so
+	/// skipping the second step is a common security mistake.
+	///
+	/// Relevant `flags` are [`Flag::NONE`], [`Flag::SILENT`] and
+	/// [`Flag::DISALLOW_NULL_AUTHTOK`] (used for both steps).
+	///
+	/// # Errors
+	/// See [`authenticate()`][`Self::authenticate()`] and
+	/// [`acct_mgmt()`][`Self::acct_mgmt()`]. The error payload tells which
+	/// step failed; account validation isn't attempted if authentication
+	/// failed.
+	///
+	/// An expired password is reported by account validation with
+	/// `NEW_AUTHTOK_REQD`, which callers usually want to handle with
+	/// [`chauthtok()`][`Self::chauthtok()`] instead of denying access.
+	pub fn authenticate_and_check(&mut self, flags: Flag) -> ExtResult<(), AuthStep> {
+		self.authenticate(flags)
+			.map_err(|e| e.into_with_payload(AuthStep::Authenticate))?;
+		self.acct_mgmt(flags)
+			.map_err(|e| e.into_with_payload(AuthStep::AcctMgmt))
+	}
+
+	/// Authenticates the already known user again with a custom prompt,
+	/// like `sudo` does.
+	///
+	/// Sets the [user prompt][`Self::set_user_prompt()`] to `prompt` with
+	/// all occurrences of `%u` replaced by the current [user][`Self::user()`]
+	/// and then calls [`authenticate()`][`Self::authenticate()`]. The prompt
+	/// stays set afterwards.
+	///
+	/// ```rust,no_run
+	/// # use pam_client::{Context, Flag};
+	/// # use pam_client::conv_mock::Conversation;
+	/// let mut context = Context::new("sudo", Some("alice"), Conversation::new())?;
+	/// context.reauthenticate_sudo_style("[sudo] password for %u: ", Flag::NONE)?;
+	/// # Ok::<(), pam_client::Error>(())
+	/// ```
+	///
+	/// Please note that modules only use the user prompt when they ask for
+	/// a username. Most modules choose the password prompt themselves
+	/// (e.g. with the `authtok_prompt` option of some modules), so a
+	/// conversation handler may additionally need to replace the prompts it
+	/// receives for the customized text to actually show up.
+	///
+	/// Relevant `flags` are [`Flag::NONE`], [`Flag::SILENT`] and
+	/// [`Flag::DISALLOW_NULL_AUTHTOK`].
+	///
+	/// # Errors
+	/// - `USER_UNKNOWN` – No user is set on the context.
+	///
+	/// Additionally all errors of [`set_user_prompt()`][`Self::set_user_prompt()`]
+	/// and [`authenticate()`][`Self::authenticate()`].
+	pub fn reauthenticate_sudo_style(&mut self, prompt: &str, flags: Flag) -> Result<()> {
+		let user = match self.user() {
+			Ok(user) if !user.is_empty() => user,
+			_ => return Err(Error::new(self.handle(), ErrorCode::USER_UNKNOWN)),
+		};
+		self.set_user_prompt(Some(&prompt.replace("%u", &user)))?;
+		self.authenticate(flags)
+	}
+
+	/// Continues an authentication that returned `INCOMPLETE`.
+	///
+	/// Event-driven modules (e.g. waiting for a hardware token) and
+	/// conversation handlers returning `CONV_AGAIN` make
+	/// [`authenticate()`][`Self::authenticate()`] return `INCOMPLETE`. The
+	/// PAM library keeps the state of the module stack in this case, and
+	/// calling `pam_authenticate` again after the external event re-enters
+	/// the stack at the module that returned, instead of starting over.
+	/// Pass the same `flags` as to the original call.
+	///
+	/// This differs from retrying after a failed authentication (see
+	/// [`authenticate_retry()`][`Self::authenticate_retry()`]), which
+	/// starts the stack from the beginning, and from `PAM_TRY_AGAIN`, which
+	/// only occurs in the preliminary check of a password change and can't
+	/// be continued.
+	///
+	/// Fails without calling the PAM library if the last PAM call of this
+	/// context didn't return `INCOMPLETE`, so an accidental call can't
+	/// start a new authentication.
+	///
+	/// # Errors
+	/// Same as [`authenticate()`][`Self::authenticate()`]; `INCOMPLETE` may
+	/// be returned again if the event hasn't occurred yet. Additionally:
+	/// - `ABORT` – There is no incomplete authentication to continue
+	#[rustversion::attr(since(1.48), doc(alias = "pam_authenticate"))]
+	pub fn continue_authentication(&mut self, flags: Flag) -> Result<()> {
+		if self.last_status.get() != ErrorCode::INCOMPLETE.repr() {
+			return Err(Error::with_message(
+				ErrorCode::ABORT,
+				"no incomplete authentication to continue",
+			));
+		}
+		self.wrap_operation("continue_authentication", || {
+			self.wrap_pam_return(unsafe { pam_authenticate(self.handle().into(), flags.bits()) })
+		})
+	}
+
+	/// Authenticates a user, reporting required password changes.
+	///
+	/// Like [`authenticate()`][`Self::authenticate()`], but some PAM stacks
+	/// already report an expired password during authentication with
+	/// `NEW_AUTHTOK_REQD` instead of (only) in
+	/// [`acct_mgmt()`][`Self::acct_mgmt()`]. This is returned as
+	/// [`AuthOutcome::PasswordChangeRequired`], so callers can easily route
+	/// to [`chauthtok()`][`Self::chauthtok()`] instead of treating it as a
+	/// failure.
+	///
+	/// # Errors
+	/// Same as [`authenticate()`][`Self::authenticate()`], except
+	/// `NEW_AUTHTOK_REQD`.
+	pub fn authenticate_outcome(&mut self, flags: Flag) -> Result<AuthOutcome> {
+		match self.authenticate(flags) {
+			Ok(()) => Ok(AuthOutcome::Authenticated),
+			Err(e) if e.code() == ErrorCode::NEW_AUTHTOK_REQD => {
+				Ok(AuthOutcome::PasswordChangeRequired)
+			}
+			Err(e) => Err(e),
+		}
+	}
+
+	/// Authenticates a user, never accepting empty passwords.
+	///
+	/// Like [`authenticate()`][`Self::authenticate()`], but
+	/// [`Flag::DISALLOW_NULL_AUTHTOK`] is always added to `flags`. With this
+	/// flag, modules fail with `AUTH_ERR` instead of succeeding for users
+	/// with an empty password (e.g. `pam_unix` without `nullok`, regardless
+	/// of its configuration). Recommended for network-facing services.
+	///
+	/// Please note that the flag only applies to this call. Pass it to
+	/// [`acct_mgmt()`][`Self::acct_mgmt()`] too, to also reject accounts
+	/// with empty passwords there.
+	///
+	/// # Errors
+	/// Same as [`authenticate()`][`Self::authenticate()`].
+	pub fn authenticate_strict(&mut self, flags: Flag) -> Result<()> {
+		self.authenticate(flags | Flag::DISALLOW_NULL_AUTHTOK)
+	}
+
+	/// Authenticates a user with an upper bound on the total time.
+	///
+	/// Like [`authenticate()`][`Self::authenticate()`], but runs on a worker
+	/// thread and gives up after `timeout`. As PAM modules cannot be
+	/// interrupted, the worker thread is detached on timeout: it keeps
+	/// running until the modules return (which may be never, e.g. on a hung
+	/// network connection) and then ends the PAM transaction. Each timeout
+	/// therefore potentially leaks a thread and the resources held by it.
+	///
+	/// The context is consumed and handed back on completion.
+	///
+	/// # Errors
+	/// Same as [`authenticate()`][`Self::authenticate()`] with the context
+	/// in the error payload. On timeout `ABORT` is returned without payload,
+	/// as the context stays with the detached thread.
+	pub fn authenticate_timeout(self, flags: Flag, timeout: Duration) -> ExtResult<Self, Self>
+	where
+		ConvT: Send + 'static,
+	{
+		let (sender, receiver) = mpsc::channel();
+		thread::spawn(move || {
+			let mut context = self;
+			let result = context.authenticate(flags);
+			// If the receiver timed out, the context is dropped here
+			let _ = sender.send((context, result));
+		});
+		match receiver.recv_timeout(timeout) {
+			Ok((context, Ok(()))) => Ok(context),
+			Ok((context, Err(e))) => Err(e.into_with_payload(context)),
+			Err(_) => Err(Error::with_message(
+				ErrorCode::ABORT,
+				"authentication timed out; the PAM transaction was abandoned",
+			)
+			.into()),
+		}
+	}
+
+	/// Authenticates a user, retrying on failed attempts.
+	///
+	/// Calls [`authenticate()`][`Self::authenticate()`] up to `max_attempts`
+	/// times while it fails with `AUTH_ERR`, `USER_UNKNOWN` or
+	/// `CRED_INSUFFICIENT`. Other errors (including `MAXTRIES`) end the loop
+	/// immediately. Especially `AUTHINFO_UNAVAIL` (see
+	/// [`Error::is_backend_unavailable()`]) isn't retried, as it indicates
+	/// an unreachable backend instead of wrong credentials.
+	///
+	/// After each attempt the current username is recorded and can be
+	/// retrieved with [`attempted_users()`][`Self::attempted_users()`], e.g.
+	/// for audit logging. If no username was set before the first attempt,
+	/// it is reset after each failed attempt, so the conversation handler is
+	/// asked again and mistyped names can be corrected.
+	///
+	/// # Errors
+	/// Same as [`authenticate()`][`Self::authenticate()`]; the error of the
+	/// last attempt is returned.
+	pub fn authenticate_retry(&mut self, flags: Flag, max_attempts: usize) -> Result<()> {
+		self.attempted_users.clear();
+		let preset_user = self.user().is_ok();
+		let mut result = Err(Error::from(ErrorCode::MAXTRIES));
+		for _ in 0..max_attempts {
+			result = self.authenticate(flags);
+			if let Ok(ptr) = self.get_item(pam_sys::PAM_USER as c_int) {
+				if !ptr.is_null() {
+					let user = unsafe { CStr::from_ptr(ptr.cast()) };
+					self.attempted_users.push(user.to_owned());
+				}
+			}
+			match &result {
+				Err(e)
+					if matches!(
+						e.code(),
+						ErrorCode::AUTH_ERR
+							| ErrorCode::USER_UNKNOWN
+							| ErrorCode::CRED_INSUFFICIENT
+					) =>
+				{
+					if !preset_user {
+						self.set_user(None)?;
+					}
+				}
+				_ => break,
+			}
+		}
+		result
+	}
+
+	/// Returns the usernames recorded by the last call to
+	/// [`authenticate_retry()`][`Self::authenticate_retry()`].
+	///
+	/// Contains one entry per attempt in which a username was known, so the
+	/// same name may occur multiple times.
+	#[must_use]
+	pub fn attempted_users(&self) -> &[CString] {
+		&self.attempted_users
+	}
+
+	/// Authenticates a user, asking a closure for the password on demand.
+	///
+	/// A more secure alternative to pre-storing the password in a
+	/// conversation handler: `provider` is called with the prompt text
+	/// only when a PAM module asks for a secret, so the plaintext exists
+	/// for as short a time as possible. The handler of the context is
+	/// temporarily replaced for the duration of the call.
+	///
+	/// The closure is single-use. If the modules ask for more than one
+	/// secret (e.g. a password and a one-time token), later prompts fail
+	/// with `CONV_ERR`. Username prompts are answered with the current
+	/// target user (failing if none is set), messages are discarded.
+	///
+	/// The copy of the returned secret made for PAM is owned by the PAM
+	/// library; the `CString` itself is zeroed after it was copied.
+	///
+	/// # Errors
+	/// Same as [`authenticate()`][`Self::authenticate()`]. Errors returned
+	/// by `provider` are passed to the PAM library and usually result in
+	/// `AUTH_ERR` or `CONV_ERR`.
+	/// `BUF_ERR` is also returned if the original conversation handler
+	/// can't be reinstalled afterwards. All further operations on the
+	/// context then fail with `ABORT`.
+	pub fn authenticate_with_provider<F>(&mut self, provider: F, flags: Flag) -> Result<()>
+	where
+		F: FnOnce(&CStr) -> std::result::Result<CString, ErrorCode>,
+	{
+		let username = match self.get_item(pam_sys::PAM_USER as c_int) {
+			Ok(ptr) if !ptr.is_null() => Some(unsafe { CStr::from_ptr(ptr.cast()) }.to_owned()),
+			_ => None,
+		};
+		let handler = ProviderConversation::new(username, provider);
+		self.with_temporary_conversation(handler, |context| {
+			context.wrap_operation("authenticate", || {
+				context.wrap_pam_return(unsafe {
+					pam_authenticate(context.handle().into(), flags.bits())
+				})
+			})
+		})
+	}
+
+	/// Authenticates a user with the given credentials.
+	///
+	/// Convenience variant of [`authenticate()`][`Self::authenticate()`] for
+	/// when username and password are already known. Sets the target user to
+	/// `username`, temporarily installs a
+	/// [`conv_mock::Conversation`][`crate::conv_mock::Conversation`] with the
+	/// given credentials for the duration of the call and restores the
+	/// previous conversation handler afterwards.
+	///
+	/// Messages the PAM modules send during authentication are discarded.
+	///
+	/// # Errors
+	/// Same as [`authenticate()`][`Self::authenticate()`]. Additionally
+	/// `BUF_ERR` is returned if `username` contains a null byte.
+	/// `BUF_ERR` is also returned if the original conversation handler
+	/// can't be reinstalled, see
+	/// [`authenticate_with_provider()`][`Self::authenticate_with_provider()`].
+	pub fn authenticate_with(&mut self, username: &str, password: &str, flags: Flag) -> Result<()> {
+		self.set_user(Some(username))?;
+		let handler = conv_mock::Conversation::with_credentials(username, password);
+		self.with_temporary_conversation(handler, |context| {
+			context.wrap_operation("authenticate", || {
+				context.wrap_pam_return(unsafe {
+					pam_authenticate(context.handle().into(), flags.bits())
+				})
+			})
+		})
+	}
+
+	/// Authenticates the user and runs `func` with their privileges.
+	///
+	/// Intended for setuid helpers running as root. Performs
+	/// [`authenticate()`][`Self::authenticate()`],
+	/// [`acct_mgmt()`][`Self::acct_mgmt()`] and
+	/// [`open_session()`][`Self::open_session()`], then switches the
+	/// effective user and group id and the supplementary groups to the ones
+	/// of the authenticated user and calls `func` with the user id, the
+	/// primary group id and the PAM environment.
+	///
+	/// After `func` returns, the previous privileges are restored and the
+	/// session is closed. This also happens if `func` panics; the panic is
+	/// then propagated. If the privileges cannot be restored, the process is
+	/// aborted, as continuing with the wrong privileges isn't safe.
+	///
+	/// The supplementary groups are initialized with `initgroups()`, unless
+	/// disabled with [`set_init_groups()`][`Self::set_init_groups()`].
+	///
+	/// Only the *effective* ids are changed, so `func` could regain the
+	/// privileges. To run untrusted code, spawn a child process that
+	/// permanently changes its ids instead.
+	///
+	/// Please note that user and group ids are process-wide, so other
+	/// threads run with the privileges of the user while `func` runs.
+	///
+	/// Relevant `flags` are [`Flag::NONE`], [`Flag::SILENT`] and
+	/// [`Flag::DISALLOW_NULL_AUTHTOK`].
+	///
+	/// # Errors
+	/// Errors of the individual steps are passed through. Additionally:
+	/// - `USER_UNKNOWN` – The authenticated user is not in the password
+	///   database.
+	/// - `BUF_ERR` – The name of the authenticated user contains a null byte.
+	/// - `PERM_DENIED` – Changing the privileges failed, e.g. because the
+	///   process isn't running as root.
+	pub fn run_as_authenticated_user<R>(
+		&mut self,
+		flags: Flag,
+		func: impl FnOnce(libc::uid_t, libc::gid_t, &EnvList) -> R,
+	) -> Result<R> {
+		self.authenticate(flags)?;
+		self.acct_mgmt(flags)?;
+		let user = self.user()?;
+		let (uid, gid) = privileges::lookup_user(&user)?;
+		let init_groups = self.init_groups;
+		let session = self.open_session(flags & Flag::SILENT)?;
+		let env = session.envlist();
+		// The guard is dropped before the session, so the session is closed
+		// with the original privileges, also when unwinding.
+		let guard = privileges::PrivilegeGuard::drop_to(&user, uid, gid, init_groups)?;
+		let result = func(uid, gid, &env);
+		drop(guard);
+		drop(session);
+		Ok(result)
+	}
+
+	/// Reinstalls the conversation handler of this context in the PAM handle.
+	///
+	/// Sets `PAM_CONV` again from the handler owned by this context. This
+	/// repairs the item if it was replaced behind the back of this crate,
+	/// e.g. by raw [`set_item()`][`Self::set_item()`] calls or after
+	/// process state changes in designs that fork and change the user id.
+	///
+	/// # Fork and exec
+	/// After `fork()`, the child has a copy of the context and of the
+	/// handler at the same addresses, so the handler pointer stays valid in
+	/// both processes, but their handler states diverge from that point on.
+	/// Only one of the processes should end the PAM transaction; the other
+	/// one should [`std::mem::forget()`] the context. After `exec()`, the
+	/// PAM transaction is lost entirely and can't be reattached.
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `BAD_ITEM` – The context wraps a [foreign handle][`Context::from_raw_handle()`]
+	/// - `BUF_ERR` – Memory buffer error
+	pub fn set_conversation_on_handle(&mut self) -> Result<()> {
+		if self.borrowed {
+			return Err(Error::from(ErrorCode::BAD_ITEM));
+		}
+		let pam_conv = raw_pam_conv(self.conversation_raw());
+		unsafe {
+			self.set_item(
+				pam_sys::PAM_CONV as c_int,
+				&pam_conv as *const _ as *const _,
+			)
+		}
+	}
+
+	/// Checks whether a user is permitted to use the service.
+	///
+	/// Sets the target user to `user` and performs only the account
+	/// validation ([`acct_mgmt()`][`Self::acct_mgmt()`]) without
+	/// authentication, e.g. to check whether an already authenticated user
+	/// may access a service. A [null conversation handler][`crate::conv_null::Conversation`]
+	/// is installed for the duration of the call, so modules asking for
+	/// input fail instead of prompting the user.
+	///
+	/// Please note that some PAM stacks require a prior authentication in
+	/// the same transaction for account validation (e.g. to check a one-time
+	/// password) and will always fail here, usually with `AUTH_ERR`.
+	///
+	/// Relevant `flags` are [`Flag::NONE`], [`Flag::SILENT`] and
+	/// [`Flag::DISALLOW_NULL_AUTHTOK`].
+	///
+	/// # Errors
+	/// Outcomes of the account validation are returned as [`AccountStatus`].
+	/// Other error codes include:
+	/// - `AUTH_ERR` – Authentication failure, e.g. because the stack
+	///   requires prior authentication.
+	/// - `USER_UNKNOWN` – User not known
+	/// - `BUF_ERR` – Memory allocation error or `user` contains a null byte.
+	///   Also returned if the original conversation handler can't be
+	///   reinstalled, see
+	///   [`authenticate_with_provider()`][`Self::authenticate_with_provider()`].
+	pub fn authorize_only(&mut self, user: &str, flags: Flag) -> Result<AccountStatus> {
+		self.set_user(Some(user))?;
+		let handler = crate::conv_null::Conversation::new();
+		let result = self.with_temporary_conversation(handler, |context| {
+			context.wrap_operation("acct_mgmt", || {
+				context.wrap_pam_return(unsafe {
+					pam_acct_mgmt(context.handle().into(), flags.bits())
+				})
+			})
+		});
+		match result {
+			Ok(()) => Ok(AccountStatus::Valid),
+			Err(e) => match e.code() {
+				ErrorCode::NEW_AUTHTOK_REQD => Ok(AccountStatus::NewAuthtokRequired),
+				ErrorCode::ACCT_EXPIRED => Ok(AccountStatus::Expired),
+				ErrorCode::PERM_DENIED => Ok(AccountStatus::Denied),
+				_ => Err(e),
+			},
+		}
+	}
+
+	/// Internal: Runs `func` with a temporarily installed conversation handler.
+	///
+	/// The original handler is restored afterwards and `handler` is dropped.
+	/// The last status is preserved across restoring.
+	///
+	/// If restoring fails (only on memory allocation errors), the error of
+	/// `pam_set_item` is returned instead of the result of `func`. The
+	/// handle then still references the temporary handler, which is leaked
+	/// to keep it valid, and the context is poisoned: the original handler
+	/// is kept in `detached_conversation` and all further operations fail.
+	fn with_temporary_conversation<T, R>(
+		&mut self,
+		handler: T,
+		func: impl FnOnce(&Self) -> Result<R>,
+	) -> Result<R>
+	where
+		T: ConversationHandler,
+	{
+		// Save the current conversation struct for restoring it later
+		let old_conv = {
+			let ptr = self
+				.get_item(pam_sys::PAM_CONV as c_int)?
+				.cast::<PamConversation>();
+			let conv = unsafe { ptr.as_ref() }
+				.ok_or_else(|| Error::new(self.handle(), ErrorCode::ABORT))?;
+			PamConversation {
+				conv: conv.conv,
+				appdata_ptr: conv.appdata_ptr,
+			}
+		};
+
+		// Install the temporary handler
+		let pam_conv = into_pam_conv(Box::new(handler));
+		if let Err(e) = unsafe {
+			self.set_item(
+				pam_sys::PAM_CONV as c_int,
+				&pam_conv as *const _ as *const _,
+			)
+		} {
+			drop(unsafe { Box::from_raw(from_pam_conv::<T>(&pam_conv)) });
+			return Err(e);
+		}
+
+		let result = func(self);
+		let status = self.last_status.get();
+
+		// Restore the original handler
+		if let Err(e) = unsafe {
+			self.set_item(
+				pam_sys::PAM_CONV as c_int,
+				&old_conv as *const _ as *const _,
+			)
+		} {
+			self.detached_conversation = NonNull::new(from_pam_conv::<ConvT>(&old_conv));
+			return Err(e);
+		}
+		// Safety: the temporary handler isn't referenced by PAM anymore after
+		// the original one was reinstalled.
+		drop(unsafe { Box::from_raw(from_pam_conv::<T>(&pam_conv)) });
+
+		self.last_status.set(status);
+		result
 	}
 
 	/// Validates user account authorization.
@@ -257,7 +1035,9 @@ where
 	/// [`chauthtok()`]: `Self::chauthtok`
 	#[rustversion::attr(since(1.48), doc(alias = "pam_acct_mgmt"))]
 	pub fn acct_mgmt(&mut self, flags: Flag) -> Result<()> {
-		self.wrap_pam_return(unsafe { pam_acct_mgmt(self.handle().into(), flags.bits()) })
+		self.wrap_operation("acct_mgmt", || {
+			self.wrap_pam_return(unsafe { pam_acct_mgmt(self.handle().into(), flags.bits()) })
+		})
 	}
 
 	/// Fully reinitializes the user's credentials (if established).
@@ -276,12 +1056,76 @@ where
 	/// - `CRED_UNAVAIL` – Failed to retrieve credentials
 	/// - `SYSTEM_ERR` – Other system error
 	/// - `USER_UNKNOWN` – User not known
+	///
+	/// `IGNORE` from the module stack counts as success.
 	pub fn reinitialize_credentials(&mut self, flags: Flag) -> Result<()> {
-		self.wrap_pam_return(unsafe {
-			pam_setcred(
-				self.handle().into(),
-				(Flag::REINITIALIZE_CRED | flags).bits(),
-			)
+		self.wrap_operation("reinitialize_credentials", || {
+			self.wrap_pam_return_ignorable(unsafe {
+				pam_setcred(
+					self.handle().into(),
+					(Flag::REINITIALIZE_CRED | flags).bits(),
+				)
+			})
+		})
+	}
+
+	/// Establishes the user's credentials without authentication.
+	///
+	/// For flows where the user is already trusted and only the credentials
+	/// (e.g. Kerberos tickets) need to be set up or renewed, like cron jobs or
+	/// daemons refreshing credentials. Neither
+	/// [`authenticate()`][`Self::authenticate()`] nor a session is required.
+	///
+	/// Modules that obtain their credentials during authentication (e.g.
+	/// `pam_krb5` without a cached ticket or modules deriving keys from the
+	/// password) have nothing to establish and fail with `CRED_UNAVAIL` or
+	/// `CRED_ERR`. Modules only reading stored credentials work fine.
+	///
+	/// The credentials are deleted again with
+	/// [`delete_credentials()`][`Self::delete_credentials()`] or, at the
+	/// latest, when the context is dropped. Use
+	/// [`open_pseudo_session()`][`Self::open_pseudo_session()`] instead to
+	/// tie them to a [`Session`].
+	///
+	/// Relevant `flags` are [`Flag::NONE`] and [`Flag::SILENT`].
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `BUF_ERR` – Memory allocation error
+	/// - `CRED_ERR` – Setting credentials failed
+	/// - `CRED_UNAVAIL` – Failed to retrieve credentials
+	/// - `SYSTEM_ERR` – Other system error
+	/// - `USER_UNKNOWN` – User not known
+	pub fn establish_credentials_only(&mut self, flags: Flag) -> Result<()> {
+		self.wrap_operation("establish_credentials_only", || {
+			self.wrap_pam_return(unsafe {
+				pam_setcred(self.handle().into(), (Flag::ESTABLISH_CRED | flags).bits())
+			})
+		})?;
+		self.credentials_established = true;
+		Ok(())
+	}
+
+	/// Deletes credentials established with
+	/// [`establish_credentials_only()`][`Self::establish_credentials_only()`].
+	///
+	/// Does nothing if no credentials were established.
+	///
+	/// Relevant `flags` are [`Flag::NONE`] and [`Flag::SILENT`].
+	///
+	/// # Errors
+	/// Same as [`establish_credentials_only()`][`Self::establish_credentials_only()`].
+	/// The credentials count as deleted even on error. `IGNORE` from the
+	/// module stack counts as success.
+	pub fn delete_credentials(&mut self, flags: Flag) -> Result<()> {
+		if !self.credentials_established {
+			return Ok(());
+		}
+		self.credentials_established = false;
+		self.wrap_operation("delete_credentials", || {
+			self.wrap_pam_return_ignorable(unsafe {
+				pam_setcred(self.handle().into(), (Flag::DELETE_CRED | flags).bits())
+			})
 		})
 	}
 
@@ -294,6 +1138,18 @@ where
 	/// [`Flag::CHANGE_EXPIRED_AUTHTOK`] (only initiate change for
 	/// expired passwords).
 	///
+	/// # Validating new passwords in advance
+	///
+	/// PAM runs password changes in two phases: a preliminary check
+	/// (`PAM_PRELIM_CHECK`) and the actual update (`PAM_UPDATE_AUTHTOK`).
+	/// Both flags are reserved for the PAM library calling the modules;
+	/// Linux-PAM and OpenPAM reject them when passed by applications, and
+	/// Linux-PAM doesn't allow applications to set `PAM_AUTHTOK` either.
+	/// So there is no way to only validate a candidate password against the
+	/// rules of modules like `pam_pwquality`. Password change forms have to
+	/// call this method and report the messages the modules send through
+	/// the conversation handler instead.
+	///
 	/// # Errors
 	/// Expected error codes include:
 	/// - `AUTHTOK_ERR` – Unable to obtain the new password
@@ -309,7 +1165,31 @@ where
 	///   again after the asynchronous conversation finished.
 	#[rustversion::attr(since(1.48), doc(alias = "pam_chauthtok"))]
 	pub fn chauthtok(&mut self, flags: Flag) -> Result<()> {
-		self.wrap_pam_return(unsafe { pam_chauthtok(self.handle().into(), flags.bits()) })
+		self.wrap_operation("chauthtok", || {
+			self.wrap_pam_return(unsafe { pam_chauthtok(self.handle().into(), flags.bits()) })
+		})
+	}
+
+	/// Changes a users password only if it has expired.
+	///
+	/// Like [`chauthtok()`][`Self::chauthtok()`], but always adds
+	/// [`Flag::CHANGE_EXPIRED_AUTHTOK`] to `flags`. This is the usual
+	/// login-time case after [`acct_mgmt()`][`Self::acct_mgmt()`] returned
+	/// `NEW_AUTHTOK_REQD`, while a plain `chauthtok()` changes the password
+	/// unconditionally (e.g. for `passwd(1)`).
+	///
+	/// The flag is honored by modules managing password aging, like
+	/// `pam_unix`, `pam_sss` and `pam_krb5`, which skip the change for
+	/// unexpired passwords. Modules without a concept of expiry (e.g.
+	/// `pam_pwquality`, which only checks new passwords) ignore it and act
+	/// as usual in the stack.
+	///
+	/// Relevant `flags` are [`Flag::NONE`] and [`Flag::SILENT`].
+	///
+	/// # Errors
+	/// Same as [`chauthtok()`][`Self::chauthtok()`].
+	pub fn change_expired_authtok(&mut self, flags: Flag) -> Result<()> {
+		self.chauthtok(flags | Flag::CHANGE_EXPIRED_AUTHTOK)
 	}
 
 	/// Sets up a user session.
@@ -328,6 +1208,22 @@ where
 	///
 	/// Relevant `flags` are [`Flag::NONE`] and [`Flag::SILENT`].
 	///
+	/// # Module messages
+	///
+	/// Messages of session modules, like the message of the day of
+	/// `pam_motd` or the mail notice of `pam_mail`, are passed to the
+	/// conversation handler as info messages. With a handler like
+	/// [`conv_mock::Conversation`][`crate::conv_mock::Conversation`] they
+	/// end up in its log instead of on a terminal, which daemons without a
+	/// controlling terminal should prefer. With [`Flag::SILENT`] the modules
+	/// don't send these messages at all.
+	///
+	/// The library can't capture output that modules write directly to the
+	/// terminal or to the inherited standard streams, e.g. commands run by
+	/// `pam_exec` without its `stdout` option. Avoid such modules in
+	/// policies for daemons or redirect the standard streams of the
+	/// process.
+	///
 	/// # Errors
 	/// Expected error codes include:
 	/// - `ABORT` – Serious failure; the application should exit
@@ -346,32 +1242,81 @@ where
 	pub fn open_session(&mut self, flags: Flag) -> Result<Session<ConvT>> {
 		let bits = flags.bits();
 		let handle = self.handle().as_ptr();
-		self.wrap_pam_return(unsafe {
-			pam_setcred(handle, (Flag::ESTABLISH_CRED | flags).bits())
+		self.wrap_operation("open_session", || {
+			self.wrap_pam_return(unsafe {
+				pam_setcred(handle, (Flag::ESTABLISH_CRED | flags).bits())
+			})?;
+
+			if let Err(e) = self.wrap_pam_return(unsafe { pam_open_session(handle, bits) }) {
+				let _ = self.wrap_pam_return(unsafe {
+					pam_setcred(handle, (Flag::DELETE_CRED | flags).bits())
+				});
+				return Err(e);
+			}
+
+			// Reinitialize credentials after session opening. With this we try
+			// to circumvent different assumptions of PAM modules about when
+			// `setcred` is called, as the documentations of different PAM
+			// implementations differ. (OpenSSH does something similar too).
+			if let Err(e) = self.wrap_pam_return(unsafe {
+				pam_setcred(handle, (Flag::REINITIALIZE_CRED | flags).bits())
+			}) {
+				let _ = self.wrap_pam_return(unsafe { pam_close_session(handle, bits) });
+				let _ = self.wrap_pam_return(unsafe {
+					pam_setcred(handle, (Flag::DELETE_CRED | flags).bits())
+				});
+				return Err(e);
+			}
+			Ok(())
 		})?;
 
-		if let Err(e) = self.wrap_pam_return(unsafe { pam_open_session(handle, bits) }) {
-			let _ = self.wrap_pam_return(unsafe {
-				pam_setcred(handle, (Flag::DELETE_CRED | flags).bits())
-			});
-			return Err(e);
-		}
+		Ok(Session::new(self, true))
+	}
 
-		// Reinitialize credentials after session opening. With this we try
-		// to circumvent different assumptions of PAM modules about when
-		// `setcred` is called, as the documentations of different PAM
-		// implementations differ. (OpenSSH does something similar too).
-		if let Err(e) = self.wrap_pam_return(unsafe {
-			pam_setcred(handle, (Flag::REINITIALIZE_CRED | flags).bits())
-		}) {
-			let _ = self.wrap_pam_return(unsafe { pam_close_session(handle, bits) });
-			let _ = self.wrap_pam_return(unsafe {
-				pam_setcred(handle, (Flag::DELETE_CRED | flags).bits())
-			});
-			return Err(e);
-		}
+	/// Sets up a user session without messages from the modules.
+	///
+	/// Same as [`open_session()`][`Self::open_session()`] with
+	/// [`Flag::SILENT`]. Modules like `pam_motd`, `pam_lastlog` and
+	/// `pam_mail` then skip the message of the day, the last login time and
+	/// the mail notice. Suitable for non-interactive services like file
+	/// transfer or automation daemons.
+	///
+	/// # Errors
+	/// See [`open_session()`][`Self::open_session()`].
+	pub fn open_session_silent(&mut self) -> Result<Session<ConvT>> {
+		self.open_session(Flag::SILENT)
+	}
 
-		Ok(Session::new(self, true))
+	/// Sets up a user session showing messages from the modules.
+	///
+	/// Same as [`open_session()`][`Self::open_session()`] with
+	/// [`Flag::NONE`]. Messages like the message of the day of `pam_motd`
+	/// are passed to the conversation handler, as expected for interactive
+	/// logins.
+	///
+	/// # Errors
+	/// See [`open_session()`][`Self::open_session()`].
+	pub fn open_session_verbose(&mut self) -> Result<Session<ConvT>> {
+		self.open_session(Flag::NONE)
+	}
+
+	/// Validates the account and sets up a user session in one step.
+	///
+	/// Performs [`acct_mgmt()`][`Self::acct_mgmt()`] followed by
+	/// [`open_session()`][`Self::open_session()`]. If any step fails,
+	/// established credentials are deleted and a partially opened session is
+	/// closed again before the error is returned. The returned
+	/// [`SessionTransaction`] closes the session and deletes the credentials
+	/// on drop.
+	///
+	/// Relevant `flags` are [`Flag::NONE`], [`Flag::SILENT`] and
+	/// [`Flag::DISALLOW_NULL_AUTHTOK`] (only used for account validation).
+	///
+	/// # Errors
+	/// See [`acct_mgmt()`][`Self::acct_mgmt()`] and
+	/// [`open_session()`][`Self::open_session()`].
+	pub fn open_session_transaction(&mut self, flags: Flag) -> Result<SessionTransaction<ConvT>> {
+		SessionTransaction::begin(self, flags)
 	}
 
 	/// Maintains user credentials but don't set up a full user session.
@@ -397,8 +1342,10 @@ where
 	/// [authenticated]: Self::authenticate()
 	/// [authorized]: Self::acct_mgmt()
 	pub fn open_pseudo_session(&mut self, flags: Flag) -> Result<Session<ConvT>> {
-		self.wrap_pam_return(unsafe {
-			pam_setcred(self.handle().into(), (Flag::ESTABLISH_CRED | flags).bits())
+		self.wrap_operation("open_pseudo_session", || {
+			self.wrap_pam_return(unsafe {
+				pam_setcred(self.handle().into(), (Flag::ESTABLISH_CRED | flags).bits())
+			})
 		})?;
 
 		Ok(Session::new(self, false))
@@ -410,6 +1357,102 @@ where
 	}
 }
 
+impl Context<ForeignConversation> {
+	/// Wraps a PAM handle owned by C code.
+	///
+	/// Allows PAM modules written in Rust to use the safe item and
+	/// environment wrappers on the handle libpam passes to their `pam_sm_*`
+	/// functions, without calling `pam_start`.
+	///
+	/// The returned context borrows the handle: on drop it neither deletes
+	/// credentials nor calls `pam_end`, and [`end()`][`Self::end()`] does
+	/// nothing. The conversation function of the handle belongs to its owner
+	/// and is represented by the [placeholder][`ForeignConversation`];
+	/// [`set_conversation_on_handle()`][`Self::set_conversation_on_handle()`]
+	/// and [`replace_conversation()`][`Self::replace_conversation()`] fail
+	/// with `BAD_ITEM`.
+	///
+	/// # Safety
+	/// `handle` must be null or a valid PAM handle created by `pam_start`.
+	/// The handle must stay valid for the whole lifetime of the returned
+	/// context; for modules this means the context must not outlive the
+	/// `pam_sm_*` call that received the handle. The owner of the handle must
+	/// not use it concurrently and must eventually call `pam_end` itself.
+	///
+	/// Please note that libpam rejects some operations from within modules,
+	/// e.g. Linux-PAM fails nested [`authenticate()`][`Self::authenticate()`]
+	/// calls with `SYSTEM_ERR`.
+	///
+	/// # Errors
+	/// Fails with `SYSTEM_ERR` if `handle` is null.
+	pub unsafe fn from_raw_handle(handle: *mut RawPamHandle) -> Result<Self> {
+		let handle = PamHandle::new(handle).ok_or_else(|| Error::from(ErrorCode::SYSTEM_ERR))?;
+		Ok(Self {
+			handle,
+			last_status: Cell::new(PAM_SUCCESS),
+			conversation_error: Cell::new(None),
+			session_open: false,
+			delay_sink: None,
+			delay_jitter: None,
+			populate_messages: true,
+			init_groups: true,
+			encoding_policy: EncodingPolicy::Lossy,
+			attempted_users: Vec::new(),
+			credentials_established: false,
+			confdir: None,
+			borrowed: true,
+			released: false,
+			detached_conversation: None,
+			_conversation: PhantomData,
+		})
+	}
+}
+
+impl Context<conv_mock::Conversation> {
+	/// Authenticates a user and records the attempt.
+	///
+	/// Works like [`authenticate_timed()`][`Self::authenticate_timed()`]
+	/// and collects the outcome, the duration and the messages logged by
+	/// the conversation handler during the call into an [`AuthAttempt`].
+	/// Earlier log entries of the handler are not included and the log is
+	/// left unchanged.
+	///
+	/// If a [log sink][`conv_mock::Conversation::set_log_sink()`] is
+	/// installed, the entries are passed to the sink instead and the `log`
+	/// of the attempt is empty.
+	///
+	/// ```rust
+	/// # use pam_client::{Context, Flag};
+	/// use pam_client::conv_mock::Conversation;
+	///
+	/// let mut context = Context::new(
+	///     "test",
+	///     Some("user"),
+	///     Conversation::with_credentials("user", "password"),
+	/// )?;
+	/// let attempt = context.authenticate_attempt(Flag::NONE);
+	/// assert_eq!(attempt.service, "test");
+	/// assert_eq!(attempt.flags, Flag::NONE);
+	/// # Ok::<(), pam_client::Error>(())
+	/// ```
+	pub fn authenticate_attempt(&mut self, flags: Flag) -> AuthAttempt {
+		let first_id = self.conversation().next_log_id();
+		let (result, duration) = self.authenticate_timed(flags);
+		AuthAttempt {
+			service: self.service().unwrap_or_default(),
+			user: self.user().ok(),
+			flags,
+			outcome: result.err().map(|e| e.code()),
+			duration,
+			log: self
+				.conversation()
+				.log_since(first_id)
+				.map(|(_, entry)| entry.clone())
+				.collect(),
+		}
+	}
+}
+
 impl<ConvT> Context<ConvT> {
 	/// Internal: Gets the PAM handle.
 	#[inline]
@@ -417,17 +1460,410 @@ impl<ConvT> Context<ConvT> {
 		self.handle
 	}
 
+	/// Internal: Fails if the original conversation handler couldn't be
+	/// reinstalled after temporarily replacing it, as the PAM handle then
+	/// references a handler of another type.
+	fn check_usable(&self) -> Result<()> {
+		if self.detached_conversation.is_some() {
+			return Err(Error::with_message(
+				ErrorCode::ABORT,
+				"the conversation handler of the context couldn't be reinstalled",
+			));
+		}
+		Ok(())
+	}
+
 	/// Internal: Wraps a `ErrorCode` into a `Result` and sets `last_status`.
 	#[inline]
 	pub(crate) fn wrap_pam_return(&self, status: c_int) -> Result<()> {
 		self.last_status.set(status);
 		match status {
 			PAM_SUCCESS => Ok(()),
-			code => Err(Error::new(
-				self.handle(),
-				ErrorCode::from_repr(code).unwrap_or(ErrorCode::ABORT),
-			)),
+			code => {
+				let code = ErrorCode::from_repr(code).unwrap_or(ErrorCode::ABORT);
+				if self.populate_messages {
+					Err(Error::new(self.handle(), code))
+				} else {
+					Err(Error::from(code))
+				}
+			}
+		}
+	}
+
+	/// Internal: Like [`wrap_pam_return()`][`Self::wrap_pam_return()`], but
+	/// treats `PAM_IGNORE` as success.
+	///
+	/// Only for operations without a security decision, where a stack
+	/// without opinion simply had nothing to do. See [`ErrorCode::IGNORE`].
+	pub(crate) fn wrap_pam_return_ignorable(&self, status: c_int) -> Result<()> {
+		match self.wrap_pam_return(status) {
+			Err(e) if e.is_ignored() => Ok(()),
+			result => result,
+		}
+	}
+
+	/// Internal: Runs a high-level PAM operation.
+	///
+	/// Records the error the conversation handler returned during the
+	/// operation (if any) for [`conversation_error()`][`Self::conversation_error()`],
+	/// marks errors after a [cancellation][`crate::user_cancelled()`]
+	/// and traces the operation if the `tracing` feature is enabled.
+	pub(crate) fn wrap_operation<R>(
+		&self,
+		operation: &'static str,
+		func: impl FnOnce() -> Result<R>,
+	) -> Result<R> {
+		self.check_usable()?;
+		let _ = take_handler_error();
+		let _ = take_fail_delay();
+		let _ = take_user_cancelled();
+		let mut result = {
+			let _policy = EncodingPolicyGuard::new(self.encoding_policy);
+			self.traced(operation, func)
+		};
+		self.conversation_error.set(take_handler_error());
+		if take_user_cancelled() {
+			result = result.map_err(Error::into_user_cancelled);
+		}
+		if let (Some(duration), Some(sink)) = (take_fail_delay(), &self.delay_sink) {
+			match &self.delay_jitter {
+				Some(jitter) => sink.fail_delay(jitter.jitter(duration)),
+				None => sink.fail_delay(duration),
+			}
+		}
+		result
+	}
+
+	/// Returns the error code the conversation handler returned during the
+	/// last operation (authentication, account validation, session opening,
+	/// etc.), if any.
+	///
+	/// PAM modules usually report conversation failures as a generic
+	/// `CONV_ERR`, `AUTH_ERR`, etc. and the code returned by the handler is
+	/// lost. This allows distinguishing failures caused by the conversation
+	/// handler from failures reported by the modules themselves.
+	///
+	/// Only the last error returned by the handler in an operation is
+	/// recorded. Successful operations may also have a handler error, if a
+	/// module tolerated the failure.
+	pub fn conversation_error(&self) -> Option<ErrorCode> {
+		self.conversation_error.get()
+	}
+
+	/// Installs a sink receiving fail delays (Linux specific)
+	///
+	/// Instead of sleeping after a failed operation, the PAM library passes
+	/// the requested delay to `sink` after the operation returned. Use
+	/// [`SleepingDelay`][`crate::SleepingDelay`] to keep the default
+	/// behaviour but e.g. log the delay, or
+	/// [`RecordingDelay`][`crate::RecordingDelay`] to test delay behaviour
+	/// without slowing down the tests.
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `BAD_ITEM` – Fail delays can't be intercepted on this platform
+	#[cfg(any(target_os = "linux", doc))]
+	pub fn set_delay_sink(&mut self, sink: impl DelaySink + 'static) -> Result<()> {
+		let callback: extern "C" fn(c_int, libc::c_uint, *mut c_void) =
+			crate::ffi::fail_delay_callback;
+		self.wrap_pam_return(unsafe {
+			pam_set_item(
+				self.handle().into(),
+				pam_sys::PAM_FAIL_DELAY as c_int,
+				callback as *const c_void,
+			)
+		})?;
+		self.delay_sink = Some(Box::new(sink));
+		Ok(())
+	}
+
+	/// Installs a sink receiving fail delays (Linux specific)
+	///
+	/// Always fails with `BAD_ITEM` on this platform.
+	#[cfg(not(any(target_os = "linux", doc)))]
+	pub fn set_delay_sink(&mut self, _sink: impl DelaySink + 'static) -> Result<()> {
+		Err(Error::with_message(
+			ErrorCode::BAD_ITEM,
+			"PAM_FAIL_DELAY is not supported on this platform",
+		))
+	}
+
+	/// Removes the delay sink, so the PAM library sleeps by itself again
+	///
+	/// # Errors
+	/// Fails if the PAM library rejects resetting `PAM_FAIL_DELAY`.
+	pub fn clear_delay_sink(&mut self) -> Result<()> {
+		if self.delay_sink.is_some() {
+			#[cfg(target_os = "linux")]
+			self.wrap_pam_return(unsafe {
+				pam_set_item(
+					self.handle().into(),
+					pam_sys::PAM_FAIL_DELAY as c_int,
+					ptr::null(),
+				)
+			})?;
+			self.delay_sink = None;
+		}
+		Ok(())
+	}
+
+	/// Installs a jitter source for the delays passed to the delay sink
+	///
+	/// The PAM library randomizes fail delays, so the durations passed to
+	/// the [delay sink][`Self::set_delay_sink()`] differ between runs.
+	/// `jitter` replaces them, e.g. with a fixed duration or a replayable
+	/// [`SeededJitter`][`crate::SeededJitter`] for deterministic tests.
+	/// Has no effect without a delay sink.
+	///
+	/// ```rust
+	/// # use pam_client::{Context, RecordingDelay};
+	/// # use pam_client::conv_null::Conversation;
+	/// use std::time::Duration;
+	///
+	/// # let mut context = Context::new("test", None, Conversation::new()).unwrap();
+	/// # #[cfg(target_os = "linux")]
+	/// context.set_delay_sink(RecordingDelay::new()).unwrap();
+	/// context.set_delay_jitter(|_| Duration::from_secs(2));
+	/// ```
+	pub fn set_delay_jitter(&mut self, jitter: impl DelayJitter + 'static) {
+		self.delay_jitter = Some(Box::new(jitter));
+	}
+
+	/// Removes the jitter source, so the delays of the PAM library are
+	/// passed to the delay sink unchanged again
+	pub fn clear_delay_jitter(&mut self) {
+		self.delay_jitter = None;
+	}
+
+	/// Returns whether errors of PAM calls get their messages populated.
+	///
+	/// See [`set_populate_messages()`][`Self::set_populate_messages()`].
+	#[must_use]
+	pub const fn populate_messages(&self) -> bool {
+		self.populate_messages
+	}
+
+	/// Sets whether errors of PAM calls get their messages populated.
+	///
+	/// By default the message of each error returned by a PAM call is looked
+	/// up with `pam_strerror()` and copied into the error, costing an
+	/// allocation per error. Servers rejecting large numbers of logins
+	/// without ever displaying the messages can disable this. The messages
+	/// of such errors can still be looked up on demand with
+	/// [`ErrorWith::message_with()`][`crate::ErrorWith::message_with()`].
+	pub fn set_populate_messages(&mut self, populate: bool) {
+		self.populate_messages = populate;
+	}
+
+	/// Returns whether the supplementary groups of the user are initialized
+	/// when switching to the user.
+	///
+	/// See [`set_init_groups()`][`Self::set_init_groups()`].
+	#[must_use]
+	pub const fn init_groups(&self) -> bool {
+		self.init_groups
+	}
+
+	/// Sets whether the supplementary groups of the user are initialized
+	/// when switching to the user.
+	///
+	/// Affects [`run_as_authenticated_user()`][`Self::run_as_authenticated_user()`].
+	/// By default `initgroups()` is called, so the user gets all groups it
+	/// is a member of according to the group database, like after a login.
+	///
+	/// When disabled, the supplementary groups are reduced to the primary
+	/// group of the user instead. This suits confined service accounts,
+	/// which shouldn't gain the permissions of additional groups, e.g.
+	/// when group memberships are managed for interactive logins only.
+	/// The supplementary groups of the calling process are never kept, as
+	/// they usually include privileged groups the user isn't a member of.
+	///
+	/// Please note that modules like `pam_group` grant groups when
+	/// credentials are established; these are applied independently of
+	/// this setting.
+	pub fn set_init_groups(&mut self, init_groups: bool) {
+		self.init_groups = init_groups;
+	}
+
+	/// Returns how texts that aren't valid UTF-8 are handled in
+	/// conversations.
+	///
+	/// See [`set_encoding_policy()`][`Self::set_encoding_policy()`].
+	#[must_use]
+	pub const fn encoding_policy(&self) -> EncodingPolicy {
+		self.encoding_policy
+	}
+
+	/// Sets how texts that aren't valid UTF-8 are handled in conversations.
+	///
+	/// The policy is applied to the messages of PAM modules before they are
+	/// passed to the conversation handler and to the responses of the
+	/// handler, during operations like [`authenticate()`][`Self::authenticate()`]
+	/// or [`open_session()`][`Self::open_session()`]. Closing a session
+	/// uses the default policy.
+	///
+	/// The default [`EncodingPolicy::Lossy`] passes texts on unchanged, so
+	/// handlers assuming UTF-8 work on legacy systems as far as possible
+	/// and raw handlers see the original bytes. Use
+	/// [`EncodingPolicy::Strict`] to reject texts in other encodings or
+	/// [`EncodingPolicy::Transcode`] on systems with a known legacy
+	/// encoding.
+	///
+	/// ```rust
+	/// use pam_client::{Context, Encoding, EncodingPolicy};
+	/// # use pam_client::conv_mock::Conversation;
+	///
+	/// let mut context = Context::new("my-service", None, Conversation::new())?;
+	/// context.set_encoding_policy(EncodingPolicy::Transcode(Encoding::Latin1));
+	/// # Ok::<(), pam_client::Error>(())
+	/// ```
+	pub fn set_encoding_policy(&mut self, policy: EncodingPolicy) {
+		self.encoding_policy = policy;
+	}
+
+	/// Creates an audit record for a PAM operation.
+	///
+	/// Collects the service, user, remote host and terminal name of this
+	/// context together with the error code of `outcome` and the current
+	/// time. Items that aren't set are recorded as `None`. No secrets are
+	/// included, so the record can safely be written to a log.
+	///
+	/// ```rust
+	/// # use pam_client::{Context, Flag};
+	/// # let mut context = Context::new("test", Some("user"), pam_client::conv_null::Conversation::new()).unwrap();
+	/// let result = context.authenticate(Flag::NONE);
+	/// let record = context.audit_record(&result);
+	/// assert_eq!(record.service, "test");
+	/// assert_eq!(record.is_success(), result.is_ok());
+	/// ```
+	#[must_use]
+	pub fn audit_record(&self, outcome: &Result<()>) -> AuditRecord {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_or(0, |d| d.as_secs());
+		AuditRecord {
+			service: self.service().unwrap_or_default(),
+			user: self.user().ok(),
+			rhost: self.rhost().ok(),
+			tty: self.tty().ok(),
+			outcome: outcome.as_ref().err().map(Error::code),
+			timestamp,
+		}
+	}
+
+	/// Reads all non-secret string items at once.
+	///
+	/// Useful to log the state of the context when troubleshooting. Items
+	/// that are not set are `None`.
+	///
+	/// ```rust
+	/// # use pam_client::Context;
+	/// # let context = Context::new("test", Some("user"), pam_client::conv_null::Conversation::new()).unwrap();
+	/// let snapshot = context.items_snapshot();
+	/// assert_eq!(snapshot.service.as_deref(), Some("test"));
+	/// println!("{:?}", snapshot);
+	/// ```
+	#[must_use]
+	pub fn items_snapshot(&self) -> ItemsSnapshot {
+		ItemsSnapshot {
+			service: self.service().ok(),
+			user: self.user().ok(),
+			user_prompt: self.user_prompt().ok(),
+			tty: self.tty().ok(),
+			ruser: self.ruser().ok(),
+			rhost: self.rhost().ok(),
+		}
+	}
+
+	/// Returns the configuration file PAM uses for the service.
+	///
+	/// This is a diagnostic helper based on the filesystem, not on a PAM
+	/// API call. It reports *confdir*`/`*service* for contexts created with
+	/// an alternative configuration directory, otherwise
+	/// `/etc/pam.d/`*service* (or `/usr/lib/pam.d/`*service*). If the
+	/// service has no policy, the `other` policy PAM falls back to is
+	/// reported. Without `/etc/pam.d`, `/etc/pam.conf` is reported if it
+	/// has entries for the service or `other`.
+	///
+	/// Returns `None` if no policy was found, in which case PAM denies all
+	/// operations.
+	#[must_use]
+	pub fn resolved_config_path(&self) -> Option<PathBuf> {
+		let service = self.service().ok()?;
+		crate::services::resolve_config(&service, self.confdir.as_deref())
+	}
+
+	/// Returns whether credentials were established with
+	/// [`establish_credentials_only()`][`Self::establish_credentials_only()`]
+	/// and not yet deleted.
+	#[must_use]
+	pub fn credentials_established(&self) -> bool {
+		self.credentials_established
+	}
+
+	/// Returns whether a PAM session is currently open on this context.
+	///
+	/// Set when a session is opened with
+	/// [`open_session()`][`Self::open_session()`] or resumed with
+	/// [`unleak_session()`][`Self::unleak_session()`] and cleared when it is
+	/// closed or dropped. A [leaked][`Session::leak()`] session stays open.
+	/// Pseudo sessions don't count as open sessions.
+	#[must_use]
+	pub fn session_open(&self) -> bool {
+		self.session_open
+	}
+
+	/// Internal: Updates the session state returned by
+	/// [`session_open()`][`Self::session_open()`].
+	#[inline]
+	pub(crate) fn set_session_open(&mut self, open: bool) {
+		self.session_open = open;
+	}
+
+	/// Internal: Runs a PAM operation in a `tracing` span.
+	///
+	/// The span records the service, the user and the resulting code.
+	#[cfg(feature = "tracing")]
+	pub(crate) fn traced<R>(
+		&self,
+		operation: &'static str,
+		func: impl FnOnce() -> Result<R>,
+	) -> Result<R> {
+		use tracing::field::{debug, Empty};
+
+		let span = tracing::debug_span!(
+			"pam",
+			operation,
+			service = Empty,
+			user = Empty,
+			code = Empty
+		);
+		if !span.is_disabled() {
+			if let Ok(service) = self.service() {
+				span.record("service", &service.as_str());
+			}
+			if let Ok(user) = self.user() {
+				span.record("user", &user.as_str());
+			}
 		}
+		let _entered = span.enter();
+		let result = func();
+		match &result {
+			Ok(_) => span.record("code", &"SUCCESS"),
+			Err(e) => span.record("code", &debug(e.code())),
+		};
+		result
+	}
+
+	/// Internal: Runs a PAM operation (no-op without the `tracing` feature).
+	#[cfg(not(feature = "tracing"))]
+	#[inline]
+	pub(crate) fn traced<R>(
+		&self,
+		_operation: &'static str,
+		func: impl FnOnce() -> Result<R>,
+	) -> Result<R> {
+		func()
 	}
 
 	/// Returns raw PAM information.
@@ -442,6 +1878,7 @@ impl<ConvT> Context<ConvT> {
 	/// - `PERM_DENIED` – The value was NULL/None
 	#[rustversion::attr(since(1.48), doc(alias = "pam_get_item"))]
 	pub fn get_item(&self, item_type: c_int) -> Result<*const c_void> {
+		self.check_usable()?;
 		let mut result: *const c_void = ptr::null();
 		self.wrap_pam_return(unsafe {
 			pam_get_item(self.handle().into(), item_type, &mut result)
@@ -467,6 +1904,7 @@ impl<ConvT> Context<ConvT> {
 	/// `PAM_XAUTHDATA`.
 	#[rustversion::attr(since(1.48), doc(alias = "pam_set_item"))]
 	pub unsafe fn set_item(&mut self, item_type: c_int, value: *const c_void) -> Result<()> {
+		self.check_usable()?;
 		self.wrap_pam_return(pam_set_item(self.handle().into(), item_type, &*value))
 	}
 
@@ -477,6 +1915,15 @@ impl<ConvT> Context<ConvT> {
 	/// extracting the handler from the PAM handle fails.
 	#[inline]
 	fn conversation_raw(&self) -> *mut ConvT {
+		if self.borrowed {
+			// Borrowed contexts only exist with the zero-sized
+			// `ForeignConversation`, for which any non-null, aligned
+			// pointer is valid.
+			return NonNull::dangling().as_ptr();
+		}
+		if let Some(handler) = self.detached_conversation {
+			return handler.as_ptr();
+		}
 		let ptr = self
 			.get_item(pam_sys::PAM_CONV as c_int)
 			.expect("Extracting the conversation handler should never fail")
@@ -512,7 +1959,8 @@ impl<ConvT> Context<ConvT> {
 		"the service name"
 	);
 	impl_pam_str_item!(user, set_user, pam_sys::PAM_USER, "the username of the entity under whose identity service will be given",
-		"This value can be mapped by any module in the PAM stack, so don't assume it stays unchanged after calling other methods on `Self`.");
+		"This value can be mapped by any module in the PAM stack, so don't assume it stays unchanged after calling other methods on `Self`.",
+		"Modules get a username set this way without calling the conversation handler, so this is the standard way to supply an already known username and skip the username prompt. Modules canonicalizing usernames (e.g. mapping case or aliases) may still replace it during an operation; read it back with [`user()`][`Self::user()`] afterwards to get the name the stack actually used.");
 	impl_pam_str_item!(
 		user_prompt,
 		set_user_prompt,
@@ -538,8 +1986,31 @@ impl<ConvT> Context<ConvT> {
 		set_authtok_type,
 		pam_sys::PAM_AUTHTOK_TYPE,
 		"the default password type in the prompt (Linux specific)",
-		"E.g. \"UNIX\" for \"Enter UNIX password:\""
+		"E.g. \"UNIX\" for \"Enter UNIX password:\". Password change UIs can use this to adapt their wording."
 	);
+
+	/// Returns the default password type in the prompt (Linux specific)
+	///
+	/// Always fails with `BAD_ITEM` on this platform.
+	#[cfg(not(any(target_os = "linux", doc)))]
+	pub fn authtok_type(&self) -> Result<String> {
+		Err(Error::with_message(
+			ErrorCode::BAD_ITEM,
+			"PAM_AUTHTOK_TYPE is not supported on this platform",
+		))
+	}
+
+	/// Sets the default password type in the prompt (Linux specific)
+	///
+	/// Always fails with `BAD_ITEM` on this platform.
+	#[cfg(not(any(target_os = "linux", doc)))]
+	pub fn set_authtok_type(&mut self, _value: Option<&str>) -> Result<()> {
+		Err(Error::with_message(
+			ErrorCode::BAD_ITEM,
+			"PAM_AUTHTOK_TYPE is not supported on this platform",
+		))
+	}
+
 	#[cfg(any(target_os = "linux", doc))]
 	impl_pam_str_item!(
 		xdisplay,
@@ -576,6 +2047,83 @@ impl<ConvT> Context<ConvT> {
 		))
 	}
 
+	/// Returns the account repository (Solaris specific).
+	///
+	/// The repository selects the backend (e.g. `"files"`, `"ldap"`) used by
+	/// modules supporting it. Returns the repository name and the optional
+	/// repository-specific scope data.
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `BAD_ITEM` – Unsupported item
+	/// - `PERM_DENIED` – The value is NULL/empty
+	/// - `BUF_ERR` – Invalid data in the item
+	#[cfg(any(target_os = "solaris", target_os = "illumos", doc))]
+	pub fn repository(&self) -> Result<(&CStr, &[u8])> {
+		let handle = self.handle();
+		let ptr = self.get_item(PAM_REPOSITORY)?.cast::<RepositoryData>();
+		if ptr.is_null() {
+			return Err(Error::new(handle, ErrorCode::PERM_DENIED));
+		}
+		let data = unsafe { &*ptr };
+		if data.type_.is_null() {
+			return Err(Error::new(handle, ErrorCode::BUF_ERR));
+		}
+		let scope = if data.scope.is_null() {
+			&[][..]
+		} else {
+			unsafe { slice::from_raw_parts(data.scope.cast(), data.scope_len) }
+		};
+		Ok((unsafe { CStr::from_ptr(data.type_) }, scope))
+	}
+
+	/// Returns the account repository (Solaris specific).
+	///
+	/// Always fails with `BAD_ITEM` on this platform.
+	#[cfg(not(any(target_os = "solaris", target_os = "illumos", doc)))]
+	pub fn repository(&self) -> Result<(&CStr, &[u8])> {
+		Err(Error::with_message(
+			ErrorCode::BAD_ITEM,
+			"PAM_REPOSITORY is not supported on this platform",
+		))
+	}
+
+	/// Sets the account repository (Solaris specific).
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `BAD_ITEM` – Unsupported item
+	/// - `BUF_ERR` – Memory buffer error
+	#[cfg(any(target_os = "solaris", target_os = "illumos", doc))]
+	pub fn set_repository(&mut self, value: Option<(&CStr, &[u8])>) -> Result<()> {
+		match value {
+			None => unsafe { self.set_item(PAM_REPOSITORY, ptr::null()) },
+			Some((type_, scope)) => {
+				let repository = RepositoryData {
+					type_: type_.as_ptr(),
+					scope: if scope.is_empty() {
+						ptr::null()
+					} else {
+						scope.as_ptr().cast()
+					},
+					scope_len: scope.len(),
+				};
+				unsafe { self.set_item(PAM_REPOSITORY, &repository as *const _ as *const c_void) }
+			}
+		}
+	}
+
+	/// Sets the account repository (Solaris specific).
+	///
+	/// Always fails with `BAD_ITEM` on this platform.
+	#[cfg(not(any(target_os = "solaris", target_os = "illumos", doc)))]
+	pub fn set_repository(&mut self, _value: Option<(&CStr, &[u8])>) -> Result<()> {
+		Err(Error::with_message(
+			ErrorCode::BAD_ITEM,
+			"PAM_REPOSITORY is not supported on this platform",
+		))
+	}
+
 	/// Sets X authentication data (Linux specific).
 	///
 	/// # Errors
@@ -610,6 +2158,180 @@ impl<ConvT> Context<ConvT> {
 		}
 	}
 
+	/// Sets the requesting hostname from the address of a network peer.
+	///
+	/// Network services can pass the peer address of the client connection,
+	/// e.g. from [`TcpStream::peer_addr()`][`std::net::TcpStream::peer_addr()`].
+	/// With `resolve_dns` the hostname is looked up by reverse DNS;
+	/// otherwise, or if the lookup fails, the IP address is used in its
+	/// textual form. IPv4-mapped IPv6 addresses are set as plain IPv4
+	/// addresses. The port is ignored.
+	///
+	/// Reverse DNS entries are controlled by the owner of the address, so
+	/// a resolved name is no proof of the client's identity. Modules
+	/// granting access based on host names, like `pam_access`, should
+	/// rather be configured with addresses in that case. The lookup also
+	/// blocks until the DNS server answered or timed out.
+	///
+	/// ```rust
+	/// # use pam_client::Context;
+	/// # let mut context = Context::new("test", None, pam_client::conv_null::Conversation::new()).unwrap();
+	/// let peer = "192.0.2.1:22".parse().unwrap();
+	/// context.set_rhost_from_addr(peer, false)?;
+	/// assert_eq!(context.rhost()?, "192.0.2.1");
+	/// # Ok::<(), pam_client::Error>(())
+	/// ```
+	///
+	/// # Errors
+	/// Same as [`set_rhost()`][`Self::set_rhost()`].
+	pub fn set_rhost_from_addr(&mut self, addr: SocketAddr, resolve_dns: bool) -> Result<()> {
+		let host = crate::rhost::host_from_addr(&addr, resolve_dns);
+		self.set_rhost(Some(&host))
+	}
+
+	/// Sets the terminal name after validating and normalizing it.
+	///
+	/// Unlike [`set_tty()`][`Self::set_tty()`], which passes the value on
+	/// unchanged, this brings it into the form `pam_securetty` and similar
+	/// modules compare against (e.g. the entries of `/etc/securetty`), so
+	/// a misspelled value can't silently disable a root login policy:
+	/// - A `/dev/` prefix is removed: `/dev/tty1` becomes `tty1` and
+	///   `/dev/pts/0` becomes `pts/0`.
+	/// - Bare names like `tty1` or `pts/0` are used unchanged.
+	/// - X displays like `:0` or `host:0.0` are used unchanged.
+	///
+	/// # Errors
+	/// - `BAD_ITEM` – The value is clearly invalid: empty, an absolute path
+	///   outside of `/dev`, containing whitespace or control characters or
+	///   having empty, `.` or `..` path components.
+	///
+	/// Additionally all errors of [`set_tty()`][`Self::set_tty()`].
+	pub fn set_tty_device(&mut self, tty: &str) -> Result<()> {
+		let name = crate::tty::normalize_tty(tty)?;
+		self.set_tty(Some(name))
+	}
+
+	/// Clears the cached authentication token (unsupported by Linux-PAM).
+	///
+	/// Overwrites the current value of `PAM_AUTHTOK` with zeros and resets
+	/// the item, so the token doesn't linger in the PAM handle after use.
+	///
+	/// Linux-PAM only allows modules to access `PAM_AUTHTOK`, so this
+	/// always fails with `BAD_ITEM` there. It does however overwrite the
+	/// token itself when it is replaced and when the transaction ends.
+	///
+	/// This is a defense-in-depth measure for other PAM implementations.
+	/// Copies made by modules or by the conversation handler are not
+	/// affected.
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `BAD_ITEM` – The item is not accessible to applications, always
+	///   with Linux-PAM
+	/// - `BUF_ERR` – Memory buffer error
+	pub fn clear_authtok(&mut self) -> Result<()> {
+		let ptr = self.get_item(pam_sys::PAM_AUTHTOK as c_int)?;
+		if !ptr.is_null() {
+			// Safety: PAM stores string items as null terminated copies
+			// it owns until the item is replaced.
+			unsafe {
+				let len = libc::strlen(ptr.cast());
+				ptr::write_bytes(ptr as *mut u8, 0, len);
+			}
+		}
+		unsafe { self.set_item(pam_sys::PAM_AUTHTOK as c_int, ptr::null()) }
+	}
+
+	/// Sets a string-typed PAM item.
+	///
+	/// # Errors
+	/// Same as the corresponding setter, e.g.
+	/// [`set_user()`][`Self::set_user()`]. `Item::XDisplay` fails with
+	/// `BAD_ITEM` on platforms other than Linux and the binary
+	/// `Item::XAuthData` always fails with `BAD_ITEM`.
+	pub fn set_str_item(&mut self, item: Item, value: Option<&str>) -> Result<()> {
+		match item {
+			Item::Service => self.set_service(value),
+			Item::User => self.set_user(value),
+			Item::UserPrompt => self.set_user_prompt(value),
+			Item::Tty => self.set_tty(value),
+			Item::Ruser => self.set_ruser(value),
+			Item::Rhost => self.set_rhost(value),
+			Item::AuthtokType => self.set_authtok_type(value),
+			#[cfg(any(target_os = "linux", doc))]
+			Item::XDisplay => self.set_xdisplay(value),
+			#[cfg(not(any(target_os = "linux", doc)))]
+			Item::XDisplay => Err(Error::with_message(
+				ErrorCode::BAD_ITEM,
+				"PAM_XDISPLAY is not supported on this platform",
+			)),
+			Item::XAuthData => Err(Error::with_message(
+				ErrorCode::BAD_ITEM,
+				"PAM_XAUTHDATA is not a string item",
+			)),
+		}
+	}
+
+	/// Returns the raw bytes of a PAM item.
+	///
+	/// Unlike the string getters like [`user()`][`Self::user()`], no UTF-8
+	/// conversion is done, so values in legacy encodings are returned
+	/// unchanged. For string items the bytes exclude the terminating null
+	/// byte. For the binary `Item::XAuthData` the data part is returned,
+	/// which may contain null bytes; use [`xauthdata()`][`Self::xauthdata()`]
+	/// to also get the name.
+	///
+	/// Returns `None` if the item isn't set or not supported on this
+	/// platform.
+	#[must_use]
+	pub fn item_bytes(&self, item: Item) -> Option<Vec<u8>> {
+		let item_type = match item {
+			Item::Service => pam_sys::PAM_SERVICE as c_int,
+			Item::User => pam_sys::PAM_USER as c_int,
+			Item::UserPrompt => pam_sys::PAM_USER_PROMPT as c_int,
+			Item::Tty => pam_sys::PAM_TTY as c_int,
+			Item::Ruser => pam_sys::PAM_RUSER as c_int,
+			Item::Rhost => pam_sys::PAM_RHOST as c_int,
+			#[cfg(target_os = "linux")]
+			Item::AuthtokType => pam_sys::PAM_AUTHTOK_TYPE as c_int,
+			#[cfg(target_os = "linux")]
+			Item::XDisplay => pam_sys::PAM_XDISPLAY as c_int,
+			#[cfg(target_os = "linux")]
+			Item::XAuthData => return self.xauthdata().ok().map(|(_, data)| data.to_vec()),
+			#[cfg(not(target_os = "linux"))]
+			Item::AuthtokType | Item::XDisplay | Item::XAuthData => return None,
+		};
+		let ptr = self.get_item(item_type).ok()?;
+		if ptr.is_null() {
+			return None;
+		}
+		Some(unsafe { CStr::from_ptr(ptr.cast()) }.to_bytes().to_vec())
+	}
+
+	/// Sets multiple string-typed PAM items.
+	///
+	/// Applies the item/value pairs in order, e.g. from a configuration
+	/// file. Stops at the first failure; items before it stay set.
+	///
+	/// ```rust
+	/// # use pam_client::{Context, Item};
+	/// # let mut context = Context::new("test", None, pam_client::conv_null::Conversation::new()).unwrap();
+	/// context.set_items(&[(Item::Tty, "/dev/tty1"), (Item::Rhost, "localhost")])?;
+	/// # Ok::<(), pam_client::Error>(())
+	/// ```
+	///
+	/// # Errors
+	/// Same as [`set_str_item()`][`Self::set_str_item()`]. The message
+	/// names the item that failed.
+	pub fn set_items(&mut self, items: &[(Item, &str)]) -> Result<()> {
+		for (item, value) in items {
+			self.set_str_item(*item, Some(value)).map_err(|e| {
+				Error::with_message(e.code(), format!("setting {:?} failed: {}", item, e))
+			})?;
+		}
+		Ok(())
+	}
+
 	/// Returns the value of a PAM environment variable.
 	///
 	/// Searches the environment list in this PAM context for an
@@ -637,9 +2359,33 @@ impl<ConvT> Context<ConvT> {
 	pub fn putenv(&mut self, name_value: impl AsRef<OsStr>) -> Result<()> {
 		let c_name_value = CString::new(name_value.as_ref().as_bytes())
 			.map_err(|_| Error::from(ErrorCode::BUF_ERR))?;
+		self.check_usable()?;
 		self.wrap_pam_return(unsafe { pam_putenv(self.handle().into(), c_name_value.as_ptr()) })
 	}
 
+	/// Copies variables from the process environment into the PAM environment.
+	///
+	/// Each variable in `keys` that is set in the environment of the current
+	/// process is copied with [`putenv()`][`Self::putenv()`]. Unset variables
+	/// are silently skipped. This is useful before opening a session to pass
+	/// e.g. `TERM` or `LANG` to PAM modules and to child processes started
+	/// with the PAM environment.
+	///
+	/// # Errors
+	/// Fails on the first variable that [`putenv()`][`Self::putenv()`]
+	/// rejects. Variables before it are already copied.
+	pub fn import_env(&mut self, keys: &[&str]) -> Result<()> {
+		for key in keys {
+			if let Some(value) = std::env::var_os(key) {
+				let mut name_value = OsString::from(key);
+				name_value.push("=");
+				name_value.push(value);
+				self.putenv(name_value)?;
+			}
+		}
+		Ok(())
+	}
+
 	/// Returns a copy of the PAM environment in this context.
 	///
 	/// The contained variables represent the contents of the regular
@@ -649,20 +2395,108 @@ impl<ConvT> Context<ConvT> {
 	/// The returned [`EnvList`] type is designed to ease handing the
 	/// environment to [`std::process::Command::envs()`] and
 	/// `nix::unistd::execve()`.
+	///
+	/// If the PAM library fails to copy the environment, an empty list is
+	/// returned.
 	#[must_use]
 	#[rustversion::attr(since(1.48), doc(alias = "pam_getenvlist"))]
 	pub fn envlist(&self) -> EnvList {
 		unsafe { EnvList::new(pam_getenvlist(self.handle().into()).cast()) }
 	}
 
-	/// Swap the conversation handler.
+	/// Ends the PAM transaction with an explicit final status.
 	///
-	/// Consumes the context, returns the new context and the old conversation
-	/// handler.
+	/// The status is passed to `pam_end` and from there to the cleanup
+	/// functions of the PAM modules, some of which behave differently on
+	/// success and on failure. `None` signals success, `Some(code)` a failure
+	/// with the given error code.
 	///
-	/// # Errors
-	/// Expected error codes include:
-	/// - `BAD_ITEM` – Swapping the conversation handler is unsupported
+	/// When the context is simply dropped, the status of the last PAM call
+	/// on this context is passed instead.
+	#[rustversion::attr(since(1.48), doc(alias = "pam_end"))]
+	pub fn end_with_status(mut self, status: Option<ErrorCode>) {
+		self.release_with_status(status.map_or(PAM_SUCCESS, |code| code.repr()));
+	}
+
+	/// Ends the PAM transaction and reports the result of `pam_end`.
+	///
+	/// Like dropping the context, this deletes credentials established with
+	/// [`establish_credentials_only()`][`Self::establish_credentials_only()`]
+	/// and passes the status of the last PAM call to `pam_end`. Dropping
+	/// ignores errors, while this method returns them, so careful callers
+	/// can detect problems during cleanup.
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `SYSTEM_ERR` – The PAM library failed to release the transaction
+	/// - `ABORT` – The original conversation handler couldn't be reinstalled
+	///   by a previous operation, so the transaction was abandoned without
+	///   calling the PAM library
+	///
+	/// The context is released in any case. As the PAM handle is gone,
+	/// the error carries no message from the PAM library.
+	#[rustversion::attr(since(1.48), doc(alias = "pam_end"))]
+	pub fn end(mut self) -> Result<()> {
+		match self.release().result {
+			PAM_SUCCESS => Ok(()),
+			code => Err(Error::from(
+				ErrorCode::from_repr(code).unwrap_or(ErrorCode::SYSTEM_ERR),
+			)),
+		}
+	}
+
+	/// Internal: Deletes established credentials, ends the PAM transaction
+	/// and frees the conversation handler.
+	///
+	/// Passes the status of the last PAM call to `pam_end`. Later calls do
+	/// nothing and report success.
+	fn release(&mut self) -> Released {
+		self.release_with_status(self.last_status.get())
+	}
+
+	/// Internal: Like [`release()`][`Self::release()`], but passes
+	/// `end_status` to `pam_end`.
+	fn release_with_status(&mut self, end_status: c_int) -> Released {
+		let mut released = Released {
+			deleted_credentials: None,
+			end_status: None,
+			result: PAM_SUCCESS,
+		};
+		if self.borrowed || self.released {
+			return released;
+		}
+		self.released = true;
+		if let Some(handler) = self.detached_conversation {
+			// The handle still references the leaked temporary handler, so
+			// the modules must not be called anymore. Only the original
+			// handler, which PAM doesn't know anymore, can be freed.
+			drop(unsafe { Box::from_raw(handler.as_ptr()) });
+			released.result = ErrorCode::ABORT.repr();
+			return released;
+		}
+		let conv = self.conversation_raw();
+		if self.credentials_established {
+			released.deleted_credentials = Some(unsafe {
+				pam_setcred(
+					self.handle.into(),
+					(Flag::DELETE_CRED | Flag::SILENT).bits(),
+				)
+			});
+		}
+		released.end_status = Some(end_status);
+		released.result = unsafe { pam_end(self.handle.into(), end_status) };
+		drop(unsafe { Box::from_raw(conv) });
+		released
+	}
+
+	/// Swap the conversation handler.
+	///
+	/// Consumes the context, returns the new context and the old conversation
+	/// handler.
+	///
+	/// # Errors
+	/// Expected error codes include:
+	/// - `BAD_ITEM` – Swapping the conversation handler is unsupported
 	/// - `BUF_ERR` – Memory buffer error
 	///
 	/// The error payload contains the old context and the new handler.
@@ -682,6 +2516,9 @@ impl<ConvT> Context<ConvT> {
 		mut self,
 		new_handler: Box<T>,
 	) -> ExtResult<(Context<T>, Box<ConvT>), (Self, Box<T>)> {
+		if self.borrowed {
+			return Err(Error::from(ErrorCode::BAD_ITEM).into_with_payload((self, new_handler)));
+		}
 		// Get current username for handler initialization
 		let username = match self.user() {
 			Ok(u) => Some(u),
@@ -707,7 +2544,7 @@ impl<ConvT> Context<ConvT> {
 			Err(e.into_with_payload((self, new_handler)))
 		} else {
 			// Prevent dropping of the old context
-			let old = ManuallyDrop::new(self);
+			let mut old = ManuallyDrop::new(self);
 
 			// Reconstruct old handler from saved pointer
 			// Safety: The handler was replaced in the context, so there
@@ -719,6 +2556,19 @@ impl<ConvT> Context<ConvT> {
 			let mut context = Context::<T> {
 				handle: old.handle,
 				last_status: Cell::new(old.last_status.replace(PAM_SUCCESS)),
+				conversation_error: Cell::new(old.conversation_error.get()),
+				session_open: old.session_open,
+				delay_sink: old.delay_sink.take(),
+				delay_jitter: old.delay_jitter.take(),
+				populate_messages: old.populate_messages,
+				init_groups: old.init_groups,
+				encoding_policy: old.encoding_policy,
+				attempted_users: mem::take(&mut old.attempted_users),
+				credentials_established: old.credentials_established,
+				confdir: old.confdir.take(),
+				borrowed: old.borrowed,
+				released: old.released,
+				detached_conversation: None,
 				_conversation: PhantomData,
 			};
 
@@ -731,25 +2581,61 @@ impl<ConvT> Context<ConvT> {
 	}
 }
 
+/// Internal: Outcome of releasing a context
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Released {
+	/// Result of deleting established credentials, if there were any
+	deleted_credentials: Option<c_int>,
+	/// Status passed to `pam_end`, if it was called
+	end_status: Option<c_int>,
+	/// Result of ending the PAM transaction
+	result: c_int,
+}
+
 /// Destructor ending the PAM transaction and releasing the PAM context
+///
+/// Credentials established with [`Context::establish_credentials_only()`]
+/// and not yet deleted are deleted first (with [`Flag::SILENT`]), so they
+/// don't leak when the context goes out of scope on an early return or
+/// while unwinding from a panic. Credentials of a [`Session`] are deleted
+/// by the session itself, unless it was [leaked][`Session::leak()`].
+///
+/// The status of the last PAM call on this context is passed to `pam_end`.
+/// Use [`Context::end_with_status()`] to pass a different status.
 impl<ConvT> Drop for Context<ConvT> {
 	#[rustversion::attr(since(1.48), doc(alias = "pam_end"))]
 	fn drop(&mut self) {
-		let conv = self.conversation_raw();
-		unsafe { pam_end(self.handle.into(), self.last_status.get()) };
-		drop(unsafe { Box::from_raw(conv) });
+		self.release();
 	}
 }
 
 // `Send` should be possible, as long as `ConvT` is `Send` too, as all memory
 // access is bound to an unique instance of `Context` (no copy/clone) and we
 // keep interior mutability bound to having a reference to the instance.
+// The delay sink is `Send` by its trait bound. `Sync` is deliberately not
+// implemented (and prevented by the `Cell` fields), as PAM calls taking
+// `&self` still modify the handle.
 unsafe impl<ConvT> Send for Context<ConvT> where ConvT: Send {}
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::conv_null;
+	use crate::fixtures::ServiceFixture;
 	use std::ffi::{OsStr, OsString};
+	use std::rc::Rc;
+
+	#[test]
+	fn test_send() {
+		fn assert_send<T: Send>() {}
+		assert_send::<Context<crate::conv_null::Conversation>>();
+		assert_send::<Context<crate::conv_mock::Conversation>>();
+		assert_send::<Context<crate::conv_map::Conversation>>();
+		assert_send::<Context<crate::conv_channel::Conversation>>();
+		#[cfg(feature = "cli")]
+		assert_send::<Context<crate::conv_cli::Conversation>>();
+		assert_send::<Session<'static, crate::conv_null::Conversation>>();
+	}
 
 	#[test]
 	fn test_basic() {
@@ -771,11 +2657,47 @@ mod tests {
 		assert_eq!(context.ruser().unwrap(), "nobody");
 		context.set_rhost(Some("nowhere")).unwrap();
 		assert_eq!(context.rhost().unwrap(), "nowhere");
-		// Check linux specific items
+		// Check items with fallbacks on other platforms
 		#[cfg(target_os = "linux")]
 		{
 			context.set_authtok_type(Some("TEST")).unwrap();
 			assert_eq!(context.authtok_type().unwrap(), "TEST");
+		}
+		#[cfg(not(target_os = "linux"))]
+		{
+			assert_eq!(
+				context.set_authtok_type(Some("TEST")).unwrap_err().code(),
+				ErrorCode::BAD_ITEM
+			);
+			assert_eq!(
+				context.authtok_type().unwrap_err().code(),
+				ErrorCode::BAD_ITEM
+			);
+		}
+		#[cfg(any(target_os = "solaris", target_os = "illumos"))]
+		{
+			let repository = CString::new("files").unwrap();
+			context
+				.set_repository(Some((&repository, b"scope")))
+				.unwrap();
+			let (name, scope) = context.repository().unwrap();
+			assert_eq!(name, repository.as_c_str());
+			assert_eq!(scope, b"scope");
+		}
+		#[cfg(not(any(target_os = "solaris", target_os = "illumos")))]
+		{
+			assert_eq!(
+				context.set_repository(None).unwrap_err().code(),
+				ErrorCode::BAD_ITEM
+			);
+			assert_eq!(
+				context.repository().unwrap_err().code(),
+				ErrorCode::BAD_ITEM
+			);
+		}
+		// Check linux specific items
+		#[cfg(target_os = "linux")]
+		{
 			context.set_xdisplay(Some(":0")).unwrap();
 			assert_eq!(context.xdisplay().unwrap(), ":0");
 			let xauthname = CString::new("TEST_DATA").unwrap();
@@ -860,6 +2782,11 @@ mod tests {
 			map.get(&OsString::from("TEST".to_string())),
 			Some(&OsString::from("1".to_string()))
 		);
+		context.putenv("TEST3=").unwrap();
+		let execve_env = context.envlist().as_execve_env();
+		assert_eq!(execve_env.len(), env.len() + 1);
+		assert!(execve_env.contains(&CString::new("TEST=1").unwrap()));
+		assert!(execve_env.contains(&CString::new("TEST3=").unwrap()));
 		drop(context)
 	}
 
@@ -935,6 +2862,895 @@ mod tests {
 		assert_eq!(mock_handler.username, "anybody");
 	}
 
+	/// Conversation handler cancelling all prompts
+	struct CancellingConversation;
+
+	impl ConversationHandler for CancellingConversation {
+		fn prompt_echo_on(&mut self, _msg: &CStr) -> std::result::Result<CString, ErrorCode> {
+			Err(crate::user_cancelled())
+		}
+		fn prompt_echo_off(&mut self, _msg: &CStr) -> std::result::Result<CString, ErrorCode> {
+			Err(crate::user_cancelled())
+		}
+		fn text_info(&mut self, _msg: &CStr) {}
+		fn error_msg(&mut self, _msg: &CStr) {}
+	}
+
+	#[test]
+	fn test_user_cancelled() {
+		let mut context = Context::new("test", None, CancellingConversation).unwrap();
+		// Without a preset user name a module will probably ask for it
+		let error = context.authenticate(Flag::NONE).unwrap_err();
+		assert_eq!(context.conversation_error(), Some(ErrorCode::CONV_ERR));
+		assert!(error.is_user_cancelled());
+		context.set_user(Some("user")).unwrap();
+		if let Err(e) = context.acct_mgmt(Flag::SILENT) {
+			assert!(!e.is_user_cancelled());
+		}
+	}
+
+	#[test]
+	fn test_import_env() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		std::env::set_var("PAM_CLIENT_TEST_IMPORT", "imported");
+		std::env::remove_var("PAM_CLIENT_TEST_UNSET");
+		context
+			.import_env(&["PAM_CLIENT_TEST_IMPORT", "PAM_CLIENT_TEST_UNSET"])
+			.unwrap();
+		assert_eq!(context.getenv("PAM_CLIENT_TEST_IMPORT"), Some("imported"));
+		assert!(context.getenv("PAM_CLIENT_TEST_UNSET").is_none());
+	}
+
+	/// A context whose handler couldn't be reinstalled fails all operations
+	#[test]
+	fn test_detached_conversation() {
+		let handler = crate::conv_mock::Conversation::with_credentials("user", "secret");
+		let mut context = Context::new("test", Some("user"), handler).unwrap();
+		// Leave the handle referencing another handler, like a failed restore
+		let original = context.conversation_raw();
+		let temporary = into_pam_conv(Box::new(crate::conv_null::Conversation::new()));
+		unsafe {
+			context
+				.set_item(
+					pam_sys::PAM_CONV as c_int,
+					&temporary as *const _ as *const _,
+				)
+				.unwrap();
+		}
+		context.detached_conversation = NonNull::new(original);
+
+		assert_eq!(context.conversation().password, "secret");
+		let error = context.authenticate(Flag::SILENT).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::ABORT);
+		assert_eq!(context.user().unwrap_err().code(), ErrorCode::ABORT);
+		assert_eq!(context.putenv("A=b").unwrap_err().code(), ErrorCode::ABORT);
+		assert_eq!(context.end().unwrap_err().code(), ErrorCode::ABORT);
+		// The abandoned handle doesn't use the temporary handler anymore
+		drop(unsafe { Box::from_raw(from_pam_conv::<crate::conv_null::Conversation>(&temporary)) });
+	}
+
+	#[test]
+	fn test_end_with_status() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let released = context.release_with_status(ErrorCode::ABORT.repr());
+		assert_eq!(released.end_status, Some(ErrorCode::ABORT.repr()));
+		assert_eq!(released.result, PAM_SUCCESS);
+		let context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		context.end_with_status(None);
+		let context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert!(context.end().is_ok());
+	}
+
+	#[test]
+	fn test_from_raw_handle() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let mut borrowed = unsafe { Context::from_raw_handle(context.handle().as_ptr()) }.unwrap();
+		assert_eq!(borrowed.service().unwrap(), "test");
+		borrowed.set_ruser(Some("other")).unwrap();
+		assert_eq!(*borrowed.conversation(), ForeignConversation);
+		assert_eq!(
+			borrowed.set_conversation_on_handle().unwrap_err().code(),
+			ErrorCode::BAD_ITEM
+		);
+		let mut error = match borrowed.replace_conversation(crate::conv_null::Conversation::new()) {
+			Ok(_) => panic!("replaced the conversation of a borrowed handle"),
+			Err(e) => e,
+		};
+		assert_eq!(error.code(), ErrorCode::BAD_ITEM);
+		let (borrowed, _) = error.take_payload().unwrap();
+		borrowed.end().unwrap();
+
+		// The owner still has a working handle and conversation
+		assert_eq!(context.ruser().unwrap(), "other");
+		context.set_conversation_on_handle().unwrap();
+		let _ = context.conversation_mut();
+
+		assert!(unsafe { Context::from_raw_handle(ptr::null_mut()) }.is_err());
+	}
+
+	#[test]
+	fn test_ignorable() {
+		let context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let ignore = pam_sys::PAM_IGNORE as c_int;
+		assert!(context.wrap_pam_return(ignore).unwrap_err().is_ignored());
+		assert!(context.wrap_pam_return_ignorable(ignore).is_ok());
+		assert_eq!(context.last_status.get(), ignore);
+		assert_eq!(
+			context
+				.wrap_pam_return_ignorable(pam_sys::PAM_PERM_DENIED as c_int)
+				.unwrap_err()
+				.code(),
+			ErrorCode::PERM_DENIED
+		);
+	}
+
+	#[test]
+	fn test_run_as_authenticated_user() {
+		let mut context =
+			Context::new("test", Some("root"), crate::conv_null::Conversation::new()).unwrap();
+		let euid = unsafe { libc::geteuid() };
+		if let Ok(uid) = context.run_as_authenticated_user(Flag::SILENT, |uid, _, _| uid) {
+			assert_eq!(uid, 0);
+		}
+		assert_eq!(unsafe { libc::geteuid() }, euid);
+	}
+
+	#[test]
+	fn test_set_conversation_on_handle() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_mock::Conversation::new()).unwrap();
+		let before = context.conversation_raw();
+		context.set_conversation_on_handle().unwrap();
+		assert_eq!(context.conversation_raw(), before);
+		assert_eq!(context.conversation().username, "user");
+	}
+
+	#[test]
+	fn test_encoding_policy() {
+		use crate::ffi::set_encoding_policy;
+
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert_eq!(context.encoding_policy(), EncodingPolicy::Lossy);
+		context.set_encoding_policy(EncodingPolicy::Strict);
+		let _ = context.wrap_operation("test", || {
+			assert_eq!(
+				set_encoding_policy(EncodingPolicy::Strict),
+				EncodingPolicy::Strict
+			);
+			Ok(())
+		});
+		assert_eq!(
+			set_encoding_policy(EncodingPolicy::Lossy),
+			EncodingPolicy::Lossy
+		);
+		// A panicking operation doesn't leak its policy
+		let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+			context.wrap_operation("test", || -> Result<()> { panic!("operation failed") })
+		}));
+		assert!(result.is_err());
+		assert_eq!(
+			set_encoding_policy(EncodingPolicy::Lossy),
+			EncodingPolicy::Lossy
+		);
+		let (context, _) = context
+			.replace_conversation(crate::conv_null::Conversation::new())
+			.unwrap();
+		assert_eq!(context.encoding_policy(), EncodingPolicy::Strict);
+	}
+
+	#[test]
+	fn test_init_groups() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert!(context.init_groups());
+		context.set_init_groups(false);
+		assert!(!context.init_groups());
+		let (context, _) = context
+			.replace_conversation(crate::conv_null::Conversation::new())
+			.unwrap();
+		assert!(!context.init_groups());
+	}
+
+	#[test]
+	fn test_populate_messages() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert!(context.populate_messages());
+		let error = context
+			.wrap_pam_return(ErrorCode::AUTH_ERR as c_int)
+			.unwrap_err();
+		assert!(error.message().is_some());
+
+		context.set_populate_messages(false);
+		assert!(!context.populate_messages());
+		let lazy = context
+			.wrap_pam_return(ErrorCode::AUTH_ERR as c_int)
+			.unwrap_err();
+		assert_eq!(lazy.code(), ErrorCode::AUTH_ERR);
+		assert!(lazy.message().is_none());
+		assert_eq!(lazy.message_with(&context), error.message().unwrap());
+		assert_eq!(error.message_with(&context), error.message().unwrap());
+	}
+
+	#[test]
+	fn test_authorize_only() {
+		let mut context =
+			Context::new("test", None, crate::conv_null::Conversation::new()).unwrap();
+		let _ = context.authorize_only("nobody", Flag::SILENT);
+		assert_eq!(context.user().unwrap(), "nobody");
+		assert!(context.authorize_only("no\0body", Flag::NONE).is_err());
+	}
+
+	#[test]
+	fn test_delay_sink() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let recorder = crate::RecordingDelay::new();
+		#[cfg(target_os = "linux")]
+		{
+			context.set_delay_sink(recorder.clone()).unwrap();
+			let _ = context.wrap_operation("test", || {
+				crate::ffi::fail_delay_callback(
+					ErrorCode::AUTH_ERR as c_int,
+					2000,
+					ptr::null_mut(),
+				);
+				Ok(())
+			});
+			assert_eq!(recorder.delays(), vec![Duration::from_millis(2)]);
+			context.set_delay_jitter(crate::SeededJitter::new(7));
+			let replay = crate::SeededJitter::new(7);
+			let _ = context.wrap_operation("test", || {
+				crate::ffi::fail_delay_callback(
+					ErrorCode::AUTH_ERR as c_int,
+					2000,
+					ptr::null_mut(),
+				);
+				Ok(())
+			});
+			assert_eq!(
+				recorder.delays()[1],
+				replay.jitter(Duration::from_millis(2))
+			);
+			context.clear_delay_jitter();
+			let _ = context.authenticate(Flag::SILENT);
+		}
+		#[cfg(not(target_os = "linux"))]
+		assert!(context.set_delay_sink(recorder).is_err());
+		context.clear_delay_sink().unwrap();
+		let _ = context.wrap_operation("test", || Ok(()));
+	}
+
+	#[test]
+	fn test_session_open() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert!(!context.session_open());
+		let session = context.unleak_session(SessionToken::PseudoSession);
+		assert!(!session.context().session_open());
+		let _ = session.leak();
+		assert!(!context.session_open());
+		let session = context.unleak_session(SessionToken::FullSession);
+		assert!(session.context().session_open());
+		let token = session.leak();
+		assert!(context.session_open());
+		drop(context.unleak_session(token));
+		assert!(!context.session_open());
+	}
+
+	#[test]
+	fn test_authenticate_timeout() {
+		let context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let context = match context.authenticate_timeout(Flag::SILENT, Duration::from_secs(60)) {
+			Ok(context) => context,
+			Err(mut e) => e.take_payload().unwrap(),
+		};
+		assert_eq!(context.user().unwrap(), "user");
+	}
+
+	#[test]
+	fn test_authenticate_timed() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let before = Instant::now();
+		let (result, elapsed) = context.authenticate_timed(Flag::SILENT);
+		assert!(elapsed <= before.elapsed());
+		// The test service may not exist, but the plain result must match
+		assert_eq!(
+			result.map_err(|e| e.code()),
+			context.authenticate(Flag::SILENT).map_err(|e| e.code())
+		);
+	}
+
+	#[test]
+	fn test_authenticate_attempt() {
+		let mut conv = crate::conv_mock::Conversation::with_credentials("user", "password");
+		conv.text_info(&CString::new("earlier").unwrap());
+		let mut context = Context::new("test", Some("user"), conv).unwrap();
+		let attempt = context.authenticate_attempt(Flag::SILENT);
+		assert_eq!(attempt.service, "test");
+		assert_eq!(attempt.user.as_deref(), Some("user"));
+		assert_eq!(attempt.flags, Flag::SILENT);
+		let expected = context.authenticate(Flag::SILENT).err().map(|e| e.code());
+		assert_eq!(attempt.outcome, expected);
+		assert_eq!(attempt.is_success(), expected.is_none());
+		assert!(!attempt.log.contains(&crate::conv_mock::LogEntry::Info(
+			CString::new("earlier").unwrap()
+		)));
+		assert_eq!(context.conversation().log.len(), 2 * attempt.log.len() + 1);
+
+		context.conversation_mut().set_log_sink(|_| {});
+		assert!(context.authenticate_attempt(Flag::SILENT).log.is_empty());
+	}
+
+	#[test]
+	fn test_set_items() {
+		let mut context =
+			Context::new("test", None, crate::conv_null::Conversation::new()).unwrap();
+		context
+			.set_items(&[
+				(Item::User, "user"),
+				(Item::Tty, "/dev/tty1"),
+				(Item::Rhost, "localhost"),
+			])
+			.unwrap();
+		assert_eq!(context.user().unwrap(), "user");
+		assert_eq!(context.tty().unwrap(), "/dev/tty1");
+		assert_eq!(context.rhost().unwrap(), "localhost");
+
+		let error = context
+			.set_items(&[(Item::Ruser, "nobody"), (Item::UserPrompt, "bad\0prompt")])
+			.unwrap_err();
+		assert_eq!(error.code(), ErrorCode::BUF_ERR);
+		assert!(error.to_string().contains("UserPrompt"));
+		assert_eq!(context.ruser().unwrap(), "nobody");
+	}
+
+	#[test]
+	fn test_set_rhost_from_addr() {
+		let mut context =
+			Context::new("test", None, crate::conv_null::Conversation::new()).unwrap();
+		let addr = "[::ffff:192.0.2.7]:4711".parse().unwrap();
+		context.set_rhost_from_addr(addr, false).unwrap();
+		assert_eq!(context.rhost().unwrap(), "192.0.2.7");
+	}
+
+	#[test]
+	fn test_item_bytes() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert_eq!(context.item_bytes(Item::Service).unwrap(), b"test");
+		assert_eq!(context.item_bytes(Item::User).unwrap(), b"user");
+		assert_eq!(context.item_bytes(Item::Rhost), None);
+		assert_eq!(context.item_bytes(Item::XAuthData), None);
+		assert_eq!(
+			context
+				.set_str_item(Item::XAuthData, Some("data"))
+				.unwrap_err()
+				.code(),
+			ErrorCode::BAD_ITEM
+		);
+		#[cfg(target_os = "linux")]
+		{
+			let name = CString::new("MIT-MAGIC-COOKIE-1").unwrap();
+			context.set_xauthdata(Some((&name, &[1, 0, 255]))).unwrap();
+			assert_eq!(context.item_bytes(Item::XAuthData).unwrap(), [1, 0, 255]);
+		}
+	}
+
+	#[test]
+	fn test_establish_credentials_only() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert!(!context.credentials_established());
+		context.delete_credentials(Flag::NONE).unwrap();
+		if context.establish_credentials_only(Flag::SILENT).is_ok() {
+			assert!(context.credentials_established());
+			let _ = context.delete_credentials(Flag::SILENT);
+		}
+		assert!(!context.credentials_established());
+		// Dropping with established credentials must clean up
+		let _ = context.establish_credentials_only(Flag::SILENT);
+		drop(context);
+	}
+
+	#[test]
+	#[cfg(target_os = "linux")]
+	fn test_clear_authtok() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		// Linux-PAM doesn't give applications access to the token
+		let error = context.clear_authtok().unwrap_err();
+		assert_eq!(error.code(), ErrorCode::BAD_ITEM);
+	}
+
+	#[test]
+	fn test_audit_record() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let record = context.audit_record(&Ok(()));
+		assert_eq!(record.service, "test");
+		assert_eq!(record.user.as_deref(), Some("user"));
+		assert_eq!(record.rhost, None);
+		assert_eq!(record.tty, None);
+		assert!(record.is_success());
+		assert!(record.timestamp > 0);
+
+		context.set_rhost(Some("remote")).unwrap();
+		context.set_tty(Some("/dev/tty")).unwrap();
+		let record = context.audit_record(&Err(ErrorCode::AUTH_ERR.into()));
+		assert_eq!(record.rhost.as_deref(), Some("remote"));
+		assert_eq!(record.tty.as_deref(), Some("/dev/tty"));
+		assert_eq!(record.outcome, Some(ErrorCode::AUTH_ERR));
+		assert!(!record.is_success());
+	}
+
+	#[test]
+	fn test_set_tty_device() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		context.set_tty_device("/dev/pts/1").unwrap();
+		assert_eq!(context.tty().unwrap(), "pts/1");
+		let error = context.set_tty_device("/tmp/pts/1").unwrap_err();
+		assert_eq!(error.code(), ErrorCode::BAD_ITEM);
+		assert_eq!(context.tty().unwrap(), "pts/1");
+	}
+
+	#[test]
+	fn test_items_snapshot() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		context.set_ruser(Some("admin")).unwrap();
+		context.set_rhost(Some("remote")).unwrap();
+		let snapshot = context.items_snapshot();
+		assert_eq!(
+			snapshot,
+			ItemsSnapshot {
+				service: Some("test".to_string()),
+				user: Some("user".to_string()),
+				ruser: Some("admin".to_string()),
+				rhost: Some("remote".to_string()),
+				..ItemsSnapshot::default()
+			}
+		);
+	}
+
+	#[test]
+	fn test_authenticate_retry() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		assert!(context.attempted_users().is_empty());
+		let _ = context.authenticate_retry(Flag::SILENT, 3);
+		assert!(!context.attempted_users().is_empty());
+		assert!(context.attempted_users().len() <= 3);
+		assert!(context
+			.attempted_users()
+			.iter()
+			.all(|u| u.as_bytes() == b"user"));
+		assert_eq!(context.user().unwrap(), "user");
+
+		assert!(context.authenticate_retry(Flag::SILENT, 0).is_err());
+		assert!(context.attempted_users().is_empty());
+	}
+
+	#[test]
+	fn test_continue_authentication() {
+		let mut context =
+			Context::new("test", Some("user"), crate::conv_null::Conversation::new()).unwrap();
+		let error = context.continue_authentication(Flag::SILENT).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::ABORT);
+		assert_eq!(context.user().unwrap(), "user");
+	}
+
+	#[test]
+	fn test_authenticate_with() {
+		let mut context =
+			Context::new("test", None, crate::conv_mock::Conversation::new()).unwrap();
+		let _ = context.authenticate_with("nobody", "password", Flag::SILENT);
+		assert_eq!(context.user().unwrap(), "nobody");
+		// The original handler must be back in place and untouched
+		assert_eq!(context.conversation().password, "");
+		assert!(context
+			.authenticate_with("no\0body", "password", Flag::NONE)
+			.is_err());
+	}
+
+	#[test]
+	fn test_shared() {
+		use std::sync::{Arc, Mutex};
+
+		let handler = Arc::new(Mutex::new(crate::conv_mock::Conversation::new()));
+		let mut context_a = Context::new("test", Some("user"), handler.clone()).unwrap();
+		let context_b = Context::new("test", Some("other"), handler.clone()).unwrap();
+		// Only the first context initialized the username
+		assert_eq!(handler.lock().unwrap().username, "user");
+		context_a
+			.conversation_mut()
+			.text_info(&CString::new("info").unwrap());
+		drop(context_a);
+		drop(context_b);
+		assert_eq!(handler.lock().unwrap().log.len(), 1);
+	}
+
+	/// Creates a context for `fixture`.
+	///
+	/// Panics if the PAM library doesn't support alternative configuration
+	/// directories. Tests using it are therefore ignored unless the
+	/// `test-fixtures` feature is enabled.
+	fn start_fixture<ConvT: ConversationHandler>(
+		fixture: &ServiceFixture,
+		username: Option<&str>,
+		conversation: ConvT,
+	) -> Context<ConvT> {
+		match fixture.context(username, conversation) {
+			Ok(context) => context,
+			Err(e) if e.code() == ErrorCode::SYMBOL_ERR => {
+				panic!("the PAM library doesn't support pam_start_confdir")
+			}
+			Err(e) => panic!("creating the fixture context failed: {}", e),
+		}
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_new_authtok_reqd() {
+		let fixture =
+			ServiceFixture::with_policy("auth\trequired\tpam_debug.so auth=new_authtok_reqd\n")
+				.unwrap();
+		let mut context = start_fixture(&fixture, Some("user"), conv_null::Conversation::new());
+		assert_eq!(
+			context.authenticate_outcome(Flag::NONE).unwrap(),
+			AuthOutcome::PasswordChangeRequired
+		);
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		assert_eq!(
+			context.authenticate_outcome(Flag::NONE).unwrap(),
+			AuthOutcome::Authenticated
+		);
+		// Other failures are still errors
+		let deny = ServiceFixture::deny().unwrap();
+		let mut context = start_fixture(&deny, Some("user"), conv_null::Conversation::new());
+		let error = context.authenticate_outcome(Flag::NONE).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::AUTH_ERR);
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_authenticate_strict() {
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		context.authenticate_strict(Flag::SILENT).unwrap();
+		assert_eq!(context.user().unwrap(), "user");
+		let deny = ServiceFixture::deny().unwrap();
+		let mut context = start_fixture(&deny, Some("user"), conv_null::Conversation::new());
+		let error = context.authenticate_strict(Flag::SILENT).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::AUTH_ERR);
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_change_expired_authtok() {
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		context.change_expired_authtok(Flag::SILENT).unwrap();
+		let fixture =
+			ServiceFixture::with_policy("password\trequired\tpam_debug.so chauthtok=authtok_err\n")
+				.unwrap();
+		let mut context = start_fixture(&fixture, Some("user"), conv_null::Conversation::new());
+		let error = context.change_expired_authtok(Flag::SILENT).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::AUTHTOK_ERR);
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_continue_authentication_incomplete() {
+		let fixture =
+			ServiceFixture::with_policy("auth\trequired\tpam_debug.so auth=incomplete\n").unwrap();
+		let mut context = start_fixture(&fixture, Some("user"), conv_null::Conversation::new());
+		let error = context.authenticate(Flag::NONE).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::INCOMPLETE);
+		let error = context.continue_authentication(Flag::NONE).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::INCOMPLETE);
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		context.authenticate(Flag::NONE).unwrap();
+		let error = context.continue_authentication(Flag::NONE).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::ABORT);
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_authenticate_with_provider() {
+		// pam_exec asks for the password and fails if it doesn't match
+		let fixture = ServiceFixture::with_policy(
+			"auth\trequired\tpam_exec.so expose_authtok /usr/bin/grep -qF secret\n",
+		)
+		.unwrap();
+		let conv = conv_mock::Conversation::with_credentials("user", "other");
+		let mut context = start_fixture(&fixture, Some("user"), conv);
+		for &(password, success) in &[("secret", true), ("wrong", false)] {
+			let mut prompts = Vec::new();
+			let result = context.authenticate_with_provider(
+				|prompt: &CStr| {
+					prompts.push(prompt.to_owned());
+					Ok(CString::new(password).unwrap())
+				},
+				Flag::NONE,
+			);
+			assert_eq!(result.is_ok(), success);
+			assert_eq!(prompts.len(), 1);
+		}
+		// The original handler is back in place and was never asked
+		assert_eq!(context.conversation().username, "user");
+		assert!(context.conversation().responses().is_empty());
+	}
+
+	/// Conversation handler failing with an unusual code on all prompts
+	struct FailingConversation;
+
+	impl ConversationHandler for FailingConversation {
+		fn prompt_echo_on(&mut self, _msg: &CStr) -> std::result::Result<CString, ErrorCode> {
+			Err(ErrorCode::BUF_ERR)
+		}
+		fn prompt_echo_off(&mut self, _msg: &CStr) -> std::result::Result<CString, ErrorCode> {
+			Err(ErrorCode::BUF_ERR)
+		}
+		fn text_info(&mut self, _msg: &CStr) {}
+		fn error_msg(&mut self, _msg: &CStr) {}
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_conversation_error() {
+		// pam_exec asks for the password
+		let fixture = ServiceFixture::with_policy(
+			"auth\trequired\tpam_exec.so expose_authtok /bin/true\n\
+			 account\trequired\tpam_permit.so\n",
+		)
+		.unwrap();
+		let mut context = start_fixture(&fixture, Some("user"), FailingConversation);
+		assert_eq!(context.conversation_error(), None);
+		assert!(context.authenticate(Flag::NONE).is_err());
+		assert_eq!(context.conversation_error(), Some(ErrorCode::BUF_ERR));
+		context.acct_mgmt(Flag::SILENT).unwrap();
+		assert_eq!(context.conversation_error(), None);
+	}
+
+	/// Conversation handler recording when it is dropped
+	struct DropFlag(Rc<Cell<bool>>);
+
+	impl ConversationHandler for DropFlag {
+		fn prompt_echo_on(&mut self, _msg: &CStr) -> std::result::Result<CString, ErrorCode> {
+			Err(ErrorCode::CONV_ERR)
+		}
+		fn prompt_echo_off(&mut self, _msg: &CStr) -> std::result::Result<CString, ErrorCode> {
+			Err(ErrorCode::CONV_ERR)
+		}
+		fn text_info(&mut self, _msg: &CStr) {}
+		fn error_msg(&mut self, _msg: &CStr) {}
+	}
+
+	impl Drop for DropFlag {
+		fn drop(&mut self) {
+			self.0.set(true);
+		}
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_release_deletes_credentials() {
+		let deny = ServiceFixture::deny().unwrap();
+		let mut context = start_fixture(&deny, Some("user"), conv_null::Conversation::new());
+		assert!(context.establish_credentials_only(Flag::SILENT).is_err());
+		assert!(!context.credentials_established());
+		// Nothing to delete
+		assert_eq!(context.release().deleted_credentials, None);
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		context.establish_credentials_only(Flag::SILENT).unwrap();
+		let released = context.release();
+		assert_eq!(released.deleted_credentials, Some(PAM_SUCCESS));
+		assert_eq!(released.result, PAM_SUCCESS);
+		// Releasing again does nothing
+		assert_eq!(context.release().deleted_credentials, None);
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_end_status() {
+		let deny = ServiceFixture::deny().unwrap();
+		let mut context = start_fixture(&deny, Some("user"), conv_null::Conversation::new());
+		assert!(context.authenticate(Flag::SILENT).is_err());
+		// Reading the conversation handler on release must not reset it
+		let released = context.release();
+		assert_eq!(released.end_status, Some(ErrorCode::AUTH_ERR.repr()));
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		context.authenticate(Flag::SILENT).unwrap();
+		let released = context.release_with_status(ErrorCode::ABORT.repr());
+		assert_eq!(released.end_status, Some(ErrorCode::ABORT.repr()));
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_credentials_deleted_on_unwind() {
+		let permit = ServiceFixture::permit().unwrap();
+		let dropped = Rc::new(Cell::new(false));
+		let mut context = start_fixture(&permit, Some("user"), DropFlag(dropped.clone()));
+		context.establish_credentials_only(Flag::SILENT).unwrap();
+		assert!(context.credentials_established());
+		// The context is released while unwinding, which deletes the
+		// credentials before freeing the handler
+		let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+			let _context = context;
+			panic!("early exit");
+		}));
+		assert!(result.is_err());
+		assert!(dropped.get());
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_authenticate_and_check() {
+		use crate::AuthStep;
+
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		context.authenticate_and_check(Flag::NONE).unwrap();
+		let deny = ServiceFixture::deny().unwrap();
+		let mut context = start_fixture(&deny, Some("user"), conv_null::Conversation::new());
+		let error = context.authenticate_and_check(Flag::NONE).unwrap_err();
+		assert_eq!(error.payload(), Some(&AuthStep::Authenticate));
+		let expired = ServiceFixture::with_policy(
+			"auth\trequired\tpam_permit.so\naccount\trequired\tpam_debug.so acct=acct_expired\n",
+		)
+		.unwrap();
+		let mut context = start_fixture(&expired, Some("user"), conv_null::Conversation::new());
+		let error = context.authenticate_and_check(Flag::NONE).unwrap_err();
+		assert_eq!(error.code(), ErrorCode::ACCT_EXPIRED);
+		assert_eq!(error.payload(), Some(&AuthStep::AcctMgmt));
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_set_user_skips_prompt() {
+		let fixture = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&fixture, None, conv_mock::Conversation::new());
+		context.set_user(Some("user")).unwrap();
+		context.authenticate(Flag::NONE).unwrap();
+		assert!(context.conversation().responses().is_empty());
+		assert_eq!(context.user().unwrap(), "user");
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_reauthenticate_sudo_style() {
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		context
+			.reauthenticate_sudo_style("[sudo] password for %u: ", Flag::NONE)
+			.unwrap();
+		assert_eq!(context.user_prompt().unwrap(), "[sudo] password for user: ");
+		let deny = ServiceFixture::deny().unwrap();
+		let mut context = start_fixture(&deny, Some("user"), conv_null::Conversation::new());
+		assert!(context
+			.reauthenticate_sudo_style("Password: ", Flag::NONE)
+			.is_err());
+		let mut context = start_fixture(&permit, None, conv_null::Conversation::new());
+		let error = context
+			.reauthenticate_sudo_style("Password: ", Flag::NONE)
+			.unwrap_err();
+		assert_eq!(error.code(), ErrorCode::USER_UNKNOWN);
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_open_session_presets() {
+		let fixture = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&fixture, Some("user"), conv_null::Conversation::new());
+		drop(context.open_session_silent().unwrap());
+		drop(context.open_session_verbose().unwrap());
+		assert!(!context.session_open());
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_authenticate_unless_cached() {
+		use crate::AuthCache;
+		use std::cell::RefCell;
+
+		#[derive(Default)]
+		struct Cache(RefCell<Vec<String>>);
+
+		impl AuthCache for Cache {
+			fn is_fresh(&self, key: &str) -> bool {
+				self.0.borrow().iter().any(|k| k == key)
+			}
+			fn record(&self, key: &str) {
+				self.0.borrow_mut().push(key.to_owned());
+			}
+		}
+
+		let cache = Cache::default();
+		let permit = ServiceFixture::permit().unwrap();
+		let mut context = start_fixture(&permit, Some("user"), conv_null::Conversation::new());
+		assert!(!context
+			.authenticate_unless_cached(&cache, "user", Flag::NONE)
+			.unwrap());
+		assert!(context
+			.authenticate_unless_cached(&cache, "user", Flag::NONE)
+			.unwrap());
+
+		// Account validation still runs for cached authentications
+		let deny = ServiceFixture::deny().unwrap();
+		let mut context = start_fixture(&deny, Some("user"), conv_null::Conversation::new());
+		cache.record("other");
+		assert!(context
+			.authenticate_unless_cached(&cache, "other", Flag::NONE)
+			.is_err());
+		assert!(context
+			.authenticate_unless_cached(&cache, "new", Flag::NONE)
+			.is_err());
+		assert!(!cache.is_fresh("new"));
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_transaction_rollback() {
+		let fixture = ServiceFixture::with_policy(
+			"auth\trequired\tpam_permit.so\n\
+			 account\trequired\tpam_permit.so\n\
+			 session\trequired\tpam_debug.so open_session=session_err\n",
+		)
+		.unwrap();
+		let mut context = start_fixture(&fixture, Some("user"), conv_null::Conversation::new());
+		// Account validation and setting credentials succeed
+		let error = context
+			.open_session_transaction(Flag::SILENT)
+			.err()
+			.unwrap();
+		assert_eq!(error.code(), ErrorCode::SESSION_ERR);
+		assert!(!context.credentials_established());
+		assert!(!context.session_open());
+		// The context is still usable
+		let error = context
+			.open_session_transaction(Flag::SILENT)
+			.err()
+			.unwrap();
+		assert_eq!(error.code(), ErrorCode::SESSION_ERR);
+		assert!(!context.session_open());
+		assert_eq!(context.user().unwrap(), "user");
+	}
+
+	#[test]
+	#[cfg_attr(not(feature = "test-fixtures"), ignore = "needs pam_start_confdir")]
+	fn test_session_messages() {
+		let fixture = ServiceFixture::with_policy(
+			"auth\trequired\tpam_permit.so\n\
+			 account\trequired\tpam_permit.so\n\
+			 session\toptional\tpam_echo.so Welcome %u\n\
+			 session\trequired\tpam_permit.so\n",
+		)
+		.unwrap();
+		let mut context = start_fixture(&fixture, Some("user"), conv_mock::Conversation::new());
+		drop(context.open_session_silent().unwrap());
+		assert_eq!(context.conversation().infos().count(), 0);
+		drop(context.open_session_verbose().unwrap());
+		assert_eq!(
+			context.conversation().infos_lossy().collect::<Vec<_>>(),
+			vec!["Welcome user"]
+		);
+	}
+
 	/// Shallowly tests a full authentication + password change + session cycle.
 	///
 	/// This will fail if the environment is not appropriately
@@ -0,0 +1,253 @@
+/*!
+ * Channel-based conversation handler
+ *
+ * Forwards prompts and messages over a [`std::sync::mpsc`] channel, so they
+ * can be answered from another thread, e.g. the event loop of a graphical
+ * or asynchronous user interface.
+ */
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use super::ConversationHandler;
+use crate::error::ErrorCode;
+use std::ffi::{CStr, CString};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Kind of a [`Prompt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PromptKind {
+	/// Prompt for visible input, e.g. the username
+	EchoOn,
+	/// Prompt for hidden input, e.g. a password
+	EchoOff,
+	/// Yes/no question (Linux specific), e.g. "Approve login?"
+	Radio,
+}
+
+/// Internal: Answer sent back to the conversation handler
+#[derive(Debug)]
+enum Answer {
+	Text(CString),
+	Bool(bool),
+}
+
+/// A prompt waiting for an answer
+///
+/// Answer text prompts with [`answer()`][`Self::answer()`] and radio
+/// prompts with [`confirm()`][`Self::confirm()`]. Dropping the prompt
+/// without answering cancels it: text prompts fail with a conversation
+/// error and radio prompts are answered with "no".
+#[derive(Debug)]
+pub struct Prompt {
+	kind: PromptKind,
+	text: CString,
+	reply: Sender<Answer>,
+}
+
+impl Prompt {
+	/// The kind of the prompt
+	#[inline]
+	#[must_use]
+	pub fn kind(&self) -> PromptKind {
+		self.kind
+	}
+
+	/// The prompt text sent by the PAM module
+	#[inline]
+	#[must_use]
+	pub fn text(&self) -> &CStr {
+		&self.text
+	}
+
+	/// Answers a text prompt.
+	///
+	/// Answering a radio prompt with text makes it fail.
+	pub fn answer(self, value: CString) {
+		let _ = self.reply.send(Answer::Text(value));
+	}
+
+	/// Answers a radio prompt with yes (`true`) or no (`false`).
+	///
+	/// Answering a text prompt this way makes it fail.
+	pub fn confirm(self, value: bool) {
+		let _ = self.reply.send(Answer::Bool(value));
+	}
+
+	/// Cancels the prompt. Same as dropping it.
+	pub fn cancel(self) {}
+}
+
+/// Event sent over the channel of a [`Conversation`]
+#[derive(Debug)]
+pub enum Event {
+	/// A prompt that must be answered
+	Prompt(Prompt),
+	/// Informational text
+	Info(CString),
+	/// An error message
+	Error(CString),
+}
+
+/// Channel-based implementation of `ConversationHandler`
+///
+/// Every prompt and message is sent as an [`Event`] over a channel. The
+/// handler blocks until prompts are answered through the [`Prompt`].
+///
+/// If the receiving end disconnects, all prompts fail with
+/// [`ErrorCode::CONV_ERR`] except radio prompts, which are treated as
+/// denied.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::conv_channel::{Conversation, Event, PromptKind};
+/// use std::ffi::CString;
+/// use std::thread;
+///
+/// let (handler, events) = Conversation::new();
+/// thread::spawn(move || {
+///     for event in events {
+///         if let Event::Prompt(prompt) = event {
+///             match prompt.kind() {
+///                 PromptKind::Radio => prompt.confirm(true),
+///                 _ => prompt.answer(CString::new("secret").unwrap()),
+///             }
+///         }
+///     }
+/// });
+/// # let context = pam_client::Context::new("test", Some("user"), handler).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Conversation {
+	sender: Sender<Event>,
+}
+
+impl Conversation {
+	/// Creates a new channel conversation handler and the receiving end
+	/// of its channel.
+	#[must_use]
+	pub fn new() -> (Self, Receiver<Event>) {
+		let (sender, receiver) = channel();
+		(Self { sender }, receiver)
+	}
+
+	/// Sends a prompt and waits for the answer.
+	///
+	/// Returns `None` if the prompt was cancelled or the channel is
+	/// disconnected.
+	fn ask(&self, kind: PromptKind, msg: &CStr) -> Option<Answer> {
+		let (reply, answer) = channel();
+		let prompt = Prompt {
+			kind,
+			text: msg.to_owned(),
+			reply,
+		};
+		self.sender.send(Event::Prompt(prompt)).ok()?;
+		answer.recv().ok()
+	}
+
+	/// Sends a text prompt and waits for the answer.
+	fn ask_text(&self, kind: PromptKind, msg: &CStr) -> Result<CString, ErrorCode> {
+		match self.ask(kind, msg) {
+			Some(Answer::Text(value)) => Ok(value),
+			_ => Err(ErrorCode::CONV_ERR),
+		}
+	}
+}
+
+impl ConversationHandler for Conversation {
+	fn prompt_echo_on(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+		self.ask_text(PromptKind::EchoOn, msg)
+	}
+
+	fn prompt_echo_off(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+		self.ask_text(PromptKind::EchoOff, msg)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		let _ = self.sender.send(Event::Info(msg.to_owned()));
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		let _ = self.sender.send(Event::Error(msg.to_owned()));
+	}
+
+	fn radio_prompt(&mut self, msg: &CStr) -> Result<bool, ErrorCode> {
+		match self.ask(PromptKind::Radio, msg) {
+			Some(Answer::Bool(value)) => Ok(value),
+			Some(Answer::Text(_)) => Err(ErrorCode::CONV_ERR),
+			// Disconnected or cancelled means deny
+			None => Ok(false),
+		}
+	}
+
+	/// Always succeeds, as the receiving end can't be checked without
+	/// sending it an event.
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::thread;
+
+	#[test]
+	fn test_prompts() {
+		let text = CString::new("test").unwrap();
+		let (mut c, events) = Conversation::new();
+		let ui = thread::spawn(move || {
+			let mut messages = Vec::new();
+			for event in events {
+				match event {
+					Event::Prompt(prompt) => match (prompt.kind(), prompt.text().to_bytes()) {
+						(PromptKind::EchoOn, _) => prompt.answer(CString::new("user").unwrap()),
+						(PromptKind::EchoOff, b"wrong") => prompt.confirm(true),
+						(PromptKind::EchoOff, _) => prompt.answer(CString::new("pw").unwrap()),
+						(PromptKind::Radio, b"cancel") => prompt.cancel(),
+						(PromptKind::Radio, b"wrong") => prompt.answer(CString::default()),
+						(PromptKind::Radio, _) => prompt.confirm(true),
+					},
+					Event::Info(msg) | Event::Error(msg) => messages.push(msg),
+				}
+			}
+			messages
+		});
+
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_bytes(), b"user");
+		assert_eq!(c.prompt_echo_off(&text).unwrap().to_bytes(), b"pw");
+		assert_eq!(c.radio_prompt(&text), Ok(true));
+		let cancel = CString::new("cancel").unwrap();
+		assert_eq!(c.radio_prompt(&cancel), Ok(false));
+		let wrong = CString::new("wrong").unwrap();
+		assert_eq!(c.radio_prompt(&wrong), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.prompt_echo_off(&wrong), Err(ErrorCode::CONV_ERR));
+		c.text_info(&text);
+		c.error_msg(&text);
+		assert_eq!(c.self_test(), Ok(()));
+		drop(c);
+		assert_eq!(ui.join().unwrap(), vec![text.clone(), text]);
+	}
+
+	#[test]
+	fn test_disconnected() {
+		let text = CString::new("test").unwrap();
+		let (mut c, events) = Conversation::new();
+		drop(events);
+		assert_eq!(c.prompt_echo_on(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.prompt_echo_off(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.radio_prompt(&text), Ok(false));
+		c.text_info(&text);
+		c.error_msg(&text);
+		assert!(format!("{:?}", c.clone()).contains("Conversation"));
+	}
+}
@@ -14,10 +14,17 @@
 
 #![forbid(unsafe_code)]
 
-use super::ConversationHandler;
+use super::{user_cancelled, ConversationHandler};
 use crate::error::ErrorCode;
+use std::borrow::Cow;
 use std::ffi::{CStr, CString};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::fs::OpenOptions;
 use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+/// Type of the prompt and message translation function
+type Translator = dyn for<'a> Fn(&'a str) -> Cow<'a, str> + Send + Sync;
 
 /// Newline trimming helper function
 fn trim_newline(s: &mut String) {
@@ -41,12 +48,31 @@ fn trim_newline(s: &mut String) {
 ///
 /// Please note that UTF-8 encoding is assumed for terminal I/O, so this
 /// handler may fail to authenticate on legacy non-UTF-8 systems when the user
-/// input contains non-ASCII characters.
-#[derive(Debug, Clone)]
+/// input contains non-ASCII characters, unless an
+/// [`EncodingPolicy`][`crate::EncodingPolicy`] on the context converts it.
+///
+/// # Localization
+///
+/// PAM modules usually send untranslated prompts. A [translator][`Self::set_translator()`]
+/// can be set to map known prompts and messages to localized strings:
+///
+/// ```rust
+/// use std::borrow::Cow;
+/// use pam_client::conv_cli::Conversation;
+///
+/// let mut handler = Conversation::new();
+/// handler.set_translator(|text| match text {
+///     "Password: " => Cow::Borrowed("Passwort: "),
+///     _ => Cow::Borrowed(text),
+/// });
+/// ```
+#[derive(Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Conversation {
 	info_prefix: String,
 	error_prefix: String,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	translator: Option<Arc<Translator>>,
 }
 
 impl Conversation {
@@ -56,6 +82,7 @@ impl Conversation {
 		Self {
 			info_prefix: "[PAM INFO] ".to_string(),
 			error_prefix: "[PAM ERROR] ".to_string(),
+			translator: None,
 		}
 	}
 
@@ -82,6 +109,45 @@ impl Conversation {
 	pub fn set_error_prefix(&mut self, prefix: impl Into<String>) {
 		self.error_prefix = prefix.into();
 	}
+
+	/// Sets a function translating prompts, info text and error messages
+	///
+	/// The function is called with the text sent by the PAM module (after
+	/// lossy UTF-8 conversion) and should return unknown texts unchanged.
+	/// The prefixes are not passed through the translator.
+	pub fn set_translator<F>(&mut self, translator: F)
+	where
+		F: for<'a> Fn(&'a str) -> Cow<'a, str> + Send + Sync + 'static,
+	{
+		self.translator = Some(Arc::new(translator));
+	}
+
+	/// Removes the translator, so texts are output unchanged again
+	pub fn clear_translator(&mut self) {
+		self.translator = None;
+	}
+
+	/// Applies the translator (if any) to a text sent by a PAM module
+	fn translate<'a>(&self, msg: &'a CStr) -> Cow<'a, str> {
+		let text = msg.to_string_lossy();
+		match &self.translator {
+			None => text,
+			Some(translator) => Cow::Owned(translator(&text).into_owned()),
+		}
+	}
+}
+
+impl Debug for Conversation {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("Conversation")
+			.field("info_prefix", &self.info_prefix)
+			.field("error_prefix", &self.error_prefix)
+			.field(
+				"translator",
+				&self.translator.as_ref().map(|_| "<function>"),
+			)
+			.finish()
+	}
 }
 
 impl Default for Conversation {
@@ -93,33 +159,52 @@ impl Default for Conversation {
 impl ConversationHandler for Conversation {
 	fn prompt_echo_on(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
 		let mut line = String::new();
-		if io::stderr().lock().write_all(msg.to_bytes()).is_err() {
+		let written = match self.translator {
+			None => io::stderr().lock().write_all(msg.to_bytes()),
+			Some(_) => io::stderr()
+				.lock()
+				.write_all(self.translate(msg).as_bytes()),
+		};
+		if written.is_err() {
 			return Err(ErrorCode::CONV_ERR);
 		}
 		let result = io::stdin().lock().read_line(&mut line);
 		match result {
-			Err(_) | Ok(0) => Err(ErrorCode::CONV_ERR),
+			Err(_) => Err(ErrorCode::CONV_ERR),
+			// End of input, e.g. Ctrl-D
+			Ok(0) => Err(user_cancelled()),
 			Ok(_) => {
 				trim_newline(&mut line);
-				CString::new(line).map_err(|_| ErrorCode::CONV_ERR)
+				Ok(CString::new(line)?)
 			}
 		}
 	}
 
 	fn prompt_echo_off(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
-		let prompt = msg.to_string_lossy();
+		let prompt = self.translate(msg);
 		match rpassword::prompt_password(prompt) {
 			Err(_) => Err(ErrorCode::CONV_ERR),
-			Ok(password) => CString::new(password).map_err(|_| ErrorCode::CONV_ERR),
+			Ok(password) => Ok(CString::new(password)?),
 		}
 	}
 
 	fn text_info(&mut self, msg: &CStr) {
-		eprintln!("{}{}", &self.info_prefix, msg.to_string_lossy());
+		eprintln!("{}{}", &self.info_prefix, self.translate(msg));
 	}
 
 	fn error_msg(&mut self, msg: &CStr) {
-		eprintln!("{}{}", &self.error_prefix, msg.to_string_lossy());
+		eprintln!("{}{}", &self.error_prefix, self.translate(msg));
+	}
+
+	/// Checks that the controlling terminal needed for password prompts
+	/// can be opened, without prompting.
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		OpenOptions::new()
+			.read(true)
+			.write(true)
+			.open("/dev/tty")
+			.map(drop)
+			.map_err(|_| ErrorCode::CONV_ERR)
 	}
 }
 
@@ -151,4 +236,32 @@ mod tests {
 
 		assert!(format!("{:?}", &c).contains("ERROR: "));
 	}
+
+	#[test]
+	fn test_self_test() {
+		let tty = OpenOptions::new().read(true).write(true).open("/dev/tty");
+		let result = Conversation::new().self_test();
+		assert_eq!(result.is_ok(), tty.is_ok());
+	}
+
+	#[test]
+	fn test_translator() {
+		let password = CString::new("Password: ").unwrap();
+		let other = CString::new("Token: ").unwrap();
+		let mut c = Conversation::new();
+		assert_eq!(c.translate(&password), "Password: ");
+
+		c.set_translator(|text| match text {
+			"Password: " => Cow::Borrowed("Passwort: "),
+			_ => Cow::Borrowed(text),
+		});
+		assert_eq!(c.translate(&password), "Passwort: ");
+		assert_eq!(c.translate(&other), "Token: ");
+		assert_eq!(c.clone().translate(&password), "Passwort: ");
+		assert!(format!("{:?}", &c).contains("<function>"));
+		c.text_info(&password);
+
+		c.clear_translator();
+		assert_eq!(c.translate(&password), "Password: ");
+	}
 }
@@ -0,0 +1,365 @@
+/*!
+ * Conversation handler forwarding messages over a byte stream
+ *
+ * Serializes every message into a frame written to a stream and reads the
+ * answers from another one. This enables privilege separated designs like
+ * the monitor/agent split of OpenSSH, where a privileged process runs PAM
+ * and an unprivileged helper talks to the user over a pipe or socket.
+ *
+ * # Wire format
+ *
+ * All integers are big-endian.
+ *
+ * Each message is sent as a request frame:
+ *
+ * | Bytes | Content                                                      |
+ * |-------|--------------------------------------------------------------|
+ * | 1     | Message style: `1` echo off, `2` echo on, `3` error, `4` info, `5` radio, `7` binary |
+ * | 4     | Length *n* of the message                                    |
+ * | *n*   | Message text without null terminator. For binary messages the type byte followed by the payload. |
+ *
+ * Error and info messages are not answered. All other messages are
+ * answered with a response frame:
+ *
+ * | Bytes | Content                                                      |
+ * |-------|--------------------------------------------------------------|
+ * | 1     | Status: `0` answered, any other value cancels the prompt     |
+ * | 4     | Length *n* of the answer (`0` if cancelled)                  |
+ * | *n*   | Answer text without null terminator. For radio prompts one byte, `1` for yes and `0` for no. For binary prompts the type byte followed by the payload. |
+ *
+ * Cancelled prompts, short reads, end of file and other I/O errors make
+ * the prompt fail with [`ErrorCode::CONV_ERR`]. Answers longer than
+ * [`MAX_FRAME_LEN`] are rejected the same way.
+ *
+ * The helper side can use [`read_request()`] and [`write_response()`] to
+ * implement the protocol.
+ */
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use super::{BinaryType, ConversationHandler, MsgStyle};
+use crate::error::ErrorCode;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::io::{self, Read, Write};
+
+/// Maximum length of frames in bytes
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+/// Status byte of answered prompts
+const STATUS_ANSWERED: u8 = 0;
+
+/// Status byte of cancelled prompts
+const STATUS_CANCELLED: u8 = 1;
+
+/// Internal: Wire representation of a message style
+const fn style_code(style: MsgStyle) -> u8 {
+	match style {
+		MsgStyle::PromptEchoOff => 1,
+		MsgStyle::PromptEchoOn => 2,
+		MsgStyle::ErrorMsg => 3,
+		MsgStyle::TextInfo => 4,
+		MsgStyle::Radio => 5,
+		MsgStyle::BinaryPrompt => 7,
+	}
+}
+
+/// Internal: Message style of a wire representation
+fn code_style(code: u8) -> Option<MsgStyle> {
+	match code {
+		1 => Some(MsgStyle::PromptEchoOff),
+		2 => Some(MsgStyle::PromptEchoOn),
+		3 => Some(MsgStyle::ErrorMsg),
+		4 => Some(MsgStyle::TextInfo),
+		5 => Some(MsgStyle::Radio),
+		7 => Some(MsgStyle::BinaryPrompt),
+		_ => None,
+	}
+}
+
+/// Internal: Writes a frame of a header byte and `data`.
+fn write_frame<W: Write>(writer: &mut W, header: u8, data: &[u8]) -> io::Result<()> {
+	let len = u32::try_from(data.len())
+		.ok()
+		.filter(|len| *len as usize <= MAX_FRAME_LEN)
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame too long"))?;
+	let mut frame = Vec::with_capacity(5 + data.len());
+	frame.push(header);
+	frame.extend_from_slice(&len.to_be_bytes());
+	frame.extend_from_slice(data);
+	writer.write_all(&frame)?;
+	writer.flush()
+}
+
+/// Internal: Reads a frame, returning the header byte and the data.
+fn read_frame<R: Read>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+	let mut header = [0; 5];
+	reader.read_exact(&mut header)?;
+	let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+	if len > MAX_FRAME_LEN {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+	}
+	let mut data = vec![0; len];
+	reader.read_exact(&mut data)?;
+	Ok((header[0], data))
+}
+
+/// Reads a request frame sent by a [`Conversation`].
+///
+/// For use on the helper side of the protocol.
+///
+/// # Errors
+/// Returns the underlying I/O error or `InvalidData` if the frame is
+/// malformed.
+pub fn read_request<R: Read>(reader: &mut R) -> io::Result<(MsgStyle, Vec<u8>)> {
+	let (code, data) = read_frame(reader)?;
+	match code_style(code) {
+		Some(style) => Ok((style, data)),
+		None => Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"unknown message style",
+		)),
+	}
+}
+
+/// Writes a response frame answering a prompt of a [`Conversation`].
+///
+/// `None` cancels the prompt.
+///
+/// # Errors
+/// Returns the underlying I/O error or `InvalidInput` if `answer` is
+/// longer than [`MAX_FRAME_LEN`].
+pub fn write_response<W: Write>(writer: &mut W, answer: Option<&[u8]>) -> io::Result<()> {
+	match answer {
+		Some(data) => write_frame(writer, STATUS_ANSWERED, data),
+		None => write_frame(writer, STATUS_CANCELLED, &[]),
+	}
+}
+
+/// Stream-based implementation of `ConversationHandler`
+///
+/// Writes each message as a frame to `writer` and reads the answers from
+/// `reader`, see the [module documentation][`self`] for the wire format.
+/// Any [`Read`] and [`Write`] implementations can be used, e.g. the two
+/// ends of a [`UnixStream`][`std::os::unix::net::UnixStream`] or
+/// [`File`][`std::fs::File`]s created from pipe file descriptors.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::conv_fd::{self, Conversation};
+/// use pam_client::MsgStyle;
+/// use std::os::unix::net::UnixStream;
+/// use std::thread;
+///
+/// let (monitor, mut agent) = UnixStream::pair().unwrap();
+/// let handler = Conversation::new(monitor.try_clone().unwrap(), monitor);
+/// thread::spawn(move || {
+///     while let Ok((style, _message)) = conv_fd::read_request(&mut agent) {
+///         let answer: Option<&[u8]> = match style {
+///             MsgStyle::PromptEchoOn => Some(b"user"),
+///             MsgStyle::PromptEchoOff => Some(b"secret"),
+///             MsgStyle::Radio => Some(&[1]),
+///             MsgStyle::BinaryPrompt => None,
+///             MsgStyle::ErrorMsg | MsgStyle::TextInfo => continue,
+///         };
+///         if conv_fd::write_response(&mut agent, answer).is_err() {
+///             break;
+///         }
+///     }
+/// });
+/// # let context = pam_client::Context::new("test", None, handler).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Conversation<R, W> {
+	reader: R,
+	writer: W,
+}
+
+impl<R, W> Conversation<R, W>
+where
+	R: Read,
+	W: Write,
+{
+	/// Creates a new stream conversation handler reading answers from
+	/// `reader` and writing messages to `writer`.
+	pub const fn new(reader: R, writer: W) -> Self {
+		Self { reader, writer }
+	}
+
+	/// Unwraps the reader and the writer.
+	pub fn into_inner(self) -> (R, W) {
+		(self.reader, self.writer)
+	}
+
+	/// Sends a message and waits for the answer.
+	fn ask(&mut self, style: MsgStyle, msg: &[u8]) -> Result<Vec<u8>, ErrorCode> {
+		write_frame(&mut self.writer, style_code(style), msg).map_err(|_| ErrorCode::CONV_ERR)?;
+		match read_frame(&mut self.reader) {
+			Ok((STATUS_ANSWERED, answer)) => Ok(answer),
+			_ => Err(ErrorCode::CONV_ERR),
+		}
+	}
+
+	/// Sends a text prompt and waits for the answer.
+	fn ask_text(&mut self, style: MsgStyle, msg: &CStr) -> Result<CString, ErrorCode> {
+		let answer = self.ask(style, msg.to_bytes())?;
+		Ok(CString::new(answer)?)
+	}
+}
+
+impl<R, W> ConversationHandler for Conversation<R, W>
+where
+	R: Read,
+	W: Write,
+{
+	fn prompt_echo_on(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+		self.ask_text(MsgStyle::PromptEchoOn, msg)
+	}
+
+	fn prompt_echo_off(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+		self.ask_text(MsgStyle::PromptEchoOff, msg)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		let _ = write_frame(
+			&mut self.writer,
+			style_code(MsgStyle::TextInfo),
+			msg.to_bytes(),
+		);
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		let _ = write_frame(
+			&mut self.writer,
+			style_code(MsgStyle::ErrorMsg),
+			msg.to_bytes(),
+		);
+	}
+
+	fn radio_prompt(&mut self, msg: &CStr) -> Result<bool, ErrorCode> {
+		match self.ask(MsgStyle::Radio, msg.to_bytes())?.as_slice() {
+			[0] => Ok(false),
+			[1] => Ok(true),
+			_ => Err(ErrorCode::CONV_ERR),
+		}
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		let request = [&[u8::from(type_)], data].concat();
+		let mut answer = self.ask(MsgStyle::BinaryPrompt, &request)?;
+		if answer.is_empty() {
+			return Err(ErrorCode::CONV_ERR);
+		}
+		let type_ = answer.remove(0);
+		Ok((type_.into(), answer))
+	}
+
+	fn max_binary_len(&self) -> usize {
+		// Leave room for the type byte
+		MAX_FRAME_LEN - 1
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::Cursor;
+
+	fn responses(answers: &[Option<&[u8]>]) -> Cursor<Vec<u8>> {
+		let mut buffer = Vec::new();
+		for answer in answers {
+			write_response(&mut buffer, *answer).unwrap();
+		}
+		Cursor::new(buffer)
+	}
+
+	#[test]
+	fn test_prompts() {
+		let text = CString::new("test").unwrap();
+		let reader = responses(&[
+			Some(b"user"),
+			Some(b"pw"),
+			Some(&[1]),
+			Some(&[0]),
+			Some(&[42, 1, 2]),
+			None,
+			Some(b"nu\0l"),
+			Some(&[2]),
+		]);
+		let mut c = Conversation::new(reader, Vec::new());
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_bytes(), b"user");
+		assert_eq!(c.prompt_echo_off(&text).unwrap().to_bytes(), b"pw");
+		assert_eq!(c.radio_prompt(&text), Ok(true));
+		assert_eq!(c.radio_prompt(&text), Ok(false));
+		assert_eq!(
+			c.binary_prompt(BinaryType::Ok, b"data"),
+			Ok((BinaryType::Other(42), vec![1, 2]))
+		);
+		c.text_info(&text);
+		c.error_msg(&text);
+		assert_eq!(c.prompt_echo_on(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.prompt_echo_off(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.radio_prompt(&text), Err(ErrorCode::CONV_ERR));
+		// End of file
+		assert_eq!(c.prompt_echo_on(&text), Err(ErrorCode::CONV_ERR));
+		assert!(format!("{:?}", c).contains("Conversation"));
+
+		let (_, written) = c.into_inner();
+		let mut requests = Cursor::new(written);
+		let mut styles = Vec::new();
+		while let Ok((style, data)) = read_request(&mut requests) {
+			if style == MsgStyle::BinaryPrompt {
+				assert_eq!(data, b"\x01data");
+			} else {
+				assert_eq!(data, b"test");
+			}
+			styles.push(style);
+		}
+		assert_eq!(
+			styles,
+			vec![
+				MsgStyle::PromptEchoOn,
+				MsgStyle::PromptEchoOff,
+				MsgStyle::Radio,
+				MsgStyle::Radio,
+				MsgStyle::BinaryPrompt,
+				MsgStyle::TextInfo,
+				MsgStyle::ErrorMsg,
+				MsgStyle::PromptEchoOn,
+				MsgStyle::PromptEchoOff,
+				MsgStyle::Radio,
+				MsgStyle::PromptEchoOn,
+			]
+		);
+	}
+
+	#[test]
+	fn test_malformed() {
+		let text = CString::new("test").unwrap();
+		// Short read in the answer
+		let mut c = Conversation::new(Cursor::new(vec![0, 0, 0, 0, 5, b'a']), Vec::new());
+		assert_eq!(c.prompt_echo_on(&text), Err(ErrorCode::CONV_ERR));
+		// Too long answer
+		let mut c = Conversation::new(Cursor::new(vec![0, 0xFF, 0, 0, 0]), Vec::new());
+		assert_eq!(c.prompt_echo_on(&text), Err(ErrorCode::CONV_ERR));
+		// Unknown style
+		let mut request = Cursor::new(vec![6, 0, 0, 0, 0]);
+		assert_eq!(
+			read_request(&mut request).unwrap_err().kind(),
+			io::ErrorKind::InvalidData
+		);
+		assert!(write_response(&mut Vec::new(), Some(&vec![0; MAX_FRAME_LEN + 1])).is_err());
+	}
+}
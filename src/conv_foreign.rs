@@ -0,0 +1,43 @@
+//! Placeholder conversation handler for borrowed PAM handles
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use super::ConversationHandler;
+use crate::error::ErrorCode;
+use std::ffi::{CStr, CString};
+
+/// Conversation handler type of contexts wrapping a foreign PAM handle
+///
+/// Contexts created with
+/// [`Context::from_raw_handle()`][`crate::Context::from_raw_handle()`]
+/// don't own the conversation function of the handle; it belongs to
+/// whoever called `pam_start`. This zero-sized placeholder stands in for
+/// it, so [`Context::conversation()`][`crate::Context::conversation()`]
+/// returns this type instead of accessing foreign data.
+///
+/// Like the [null handler][`crate::conv_null::Conversation`], it fails all
+/// prompts and ignores all messages when called directly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Conversation;
+
+impl ConversationHandler for Conversation {
+	fn prompt_echo_on(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+		Err(ErrorCode::CONV_ERR)
+	}
+
+	fn prompt_echo_off(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+		Err(ErrorCode::CONV_ERR)
+	}
+
+	fn text_info(&mut self, _msg: &CStr) {}
+
+	fn error_msg(&mut self, _msg: &CStr) {}
+}
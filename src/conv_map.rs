@@ -0,0 +1,161 @@
+//! Non-interactive conversation handler answering from a prompt map
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use super::ConversationHandler;
+use crate::conv_mock::LogEntry;
+use crate::error::ErrorCode;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::vec;
+
+/// Prompt-matching implementation of `ConversationHandler`
+///
+/// When a PAM module asks for input, the prompt text is looked up in
+/// [`responses`][`Self::responses`] and the corresponding response is
+/// returned. If the prompt is unknown, [`default`][`Self::default`] is
+/// returned if set, otherwise the prompt fails with `CONV_ERR`. Unknown
+/// prompts are recorded in [`unmatched`][`Self::unmatched`] in both cases,
+/// so test authors can discover which prompts to add.
+///
+/// Prompts are matched exactly, both for echoing and non-echoing prompts.
+/// Non-UTF-8 prompt texts are matched after lossy conversion.
+///
+/// All info and error messages will be recorded in [`log`][`Self::log`].
+///
+/// # Examples
+/// ```rust
+/// use pam_client::conv_map::Conversation;
+///
+/// let handler = Conversation::with_responses(vec![
+///     ("login: ", "user"),
+///     ("Password: ", "secret"),
+///     ("Verification code: ", "123456"),
+/// ]);
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conversation {
+	/// Responses keyed by the exact prompt text
+	pub responses: HashMap<String, String>,
+	/// Response for prompts not in [`responses`][`Self::responses`]
+	pub default: Option<String>,
+	/// All received prompts not in [`responses`][`Self::responses`]
+	pub unmatched: vec::Vec<CString>,
+	/// All received info/error messages
+	pub log: vec::Vec<LogEntry>,
+}
+
+impl Conversation {
+	/// Creates a new prompt map conversation handler without responses
+	#[must_use]
+	pub fn new() -> Self {
+		Self {
+			responses: HashMap::new(),
+			default: None,
+			unmatched: vec::Vec::new(),
+			log: vec::Vec::new(),
+		}
+	}
+
+	/// Creates a new prompt map conversation handler with preset responses
+	#[must_use]
+	pub fn with_responses<K, V>(responses: impl IntoIterator<Item = (K, V)>) -> Self
+	where
+		K: Into<String>,
+		V: Into<String>,
+	{
+		Self {
+			responses: responses
+				.into_iter()
+				.map(|(k, v)| (k.into(), v.into()))
+				.collect(),
+			..Self::new()
+		}
+	}
+
+	/// Clears the error/info log and the list of unmatched prompts
+	pub fn clear_log(&mut self) {
+		self.log.clear();
+		self.unmatched.clear();
+	}
+
+	/// Looks up the response to a prompt
+	fn answer(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		let response = match self.responses.get(prompt.to_string_lossy().as_ref()) {
+			Some(response) => response,
+			None => {
+				self.unmatched.push(prompt.to_owned());
+				self.default.as_ref().ok_or(ErrorCode::CONV_ERR)?
+			}
+		};
+		Ok(CString::new(response.as_str())?)
+	}
+}
+
+impl Default for Conversation {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl ConversationHandler for Conversation {
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.answer(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.answer(prompt)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		self.log.push(LogEntry::Info(msg.to_owned()));
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		self.log.push(LogEntry::Error(msg.to_owned()));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test() {
+		let user_prompt = CString::new("login: ").unwrap();
+		let pass_prompt = CString::new("Password: ").unwrap();
+		let other_prompt = CString::new("Token: ").unwrap();
+		let mut c = Conversation::with_responses(vec![("login: ", "user"), ("Password: ", "pass")]);
+		assert_eq!(c.prompt_echo_on(&user_prompt).unwrap().to_str(), Ok("user"));
+		assert_eq!(
+			c.prompt_echo_off(&pass_prompt).unwrap().to_str(),
+			Ok("pass")
+		);
+		assert_eq!(c.prompt_echo_off(&other_prompt), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.unmatched, vec![other_prompt.clone()]);
+
+		c.default = Some("fallback".to_string());
+		assert_eq!(
+			c.prompt_echo_on(&other_prompt).unwrap().to_str(),
+			Ok("fallback")
+		);
+		assert_eq!(c.unmatched.len(), 2);
+
+		c.text_info(&user_prompt);
+		c.error_msg(&pass_prompt);
+		assert_eq!(c.log.len(), 2);
+		c.clear_log();
+		assert!(c.log.is_empty());
+		assert!(c.unmatched.is_empty());
+		assert!(format!("{:?}", c.clone()).contains("fallback"));
+	}
+}
@@ -10,20 +10,78 @@
 
 #![forbid(unsafe_code)]
 
-use super::ConversationHandler;
+use super::{BinaryType, ConversationHandler, MsgStyle};
 use crate::error::ErrorCode;
 use std::ffi::{CStr, CString};
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult, Write};
 use std::iter::FusedIterator;
+use std::sync::{Arc, Mutex};
 use std::vec;
 
 /// Elements in [`Conversation::log`]
-#[derive(Debug, Clone)]
+///
+/// Entries compare byte-exactly by kind and message, so captured logs can be
+/// compared directly with expected ones, e.g. with `assert_eq!`.
+///
+/// More kinds of entries may be added in the future, so matches need a
+/// wildcard arm.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum LogEntry {
 	Info(CString),
 	Error(CString),
+	/// Binary message with its type byte and payload (Linux specific)
+	Binary(BinaryType, Vec<u8>),
 }
 
+impl LogEntry {
+	/// The style of the message this entry was recorded for
+	#[must_use]
+	pub const fn style(&self) -> MsgStyle {
+		match self {
+			Self::Info(_) => MsgStyle::TextInfo,
+			Self::Error(_) => MsgStyle::ErrorMsg,
+			Self::Binary(..) => MsgStyle::BinaryPrompt,
+		}
+	}
+}
+
+/// Formats the entry as one line of a transcript
+///
+/// Messages are prefixed with `[INFO]` or `[ERROR]` and lossily converted
+/// to UTF-8. Binary messages only show their type byte and length.
+///
+/// ```rust
+/// # use std::ffi::CString;
+/// # use pam_client::conv_mock::LogEntry;
+/// let entry = LogEntry::Error(CString::new("Account locked").unwrap());
+/// assert_eq!(entry.to_string(), "[ERROR] Account locked");
+/// ```
+impl Display for LogEntry {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		match self {
+			Self::Info(msg) => write!(f, "[INFO] {}", msg.to_string_lossy()),
+			Self::Error(msg) => write!(f, "[ERROR] {}", msg.to_string_lossy()),
+			Self::Binary(type_, data) => {
+				write!(
+					f,
+					"[BINARY] type {}, {} bytes",
+					u8::from(*type_),
+					data.len()
+				)
+			}
+		}
+	}
+}
+
+/// Placeholder recorded in [`Conversation::responses()`] for redacted
+/// responses
+pub const REDACTED_RESPONSE: &str = "***";
+
+/// Type of external log entry receivers
+type LogSink = dyn FnMut(LogEntry) + Send;
+
 /// Non-interactive implementation of `ConversationHandler`
 ///
 /// When a PAM module asks for a non-secret string, [`username`][`Self::username`]
@@ -31,6 +89,19 @@ pub enum LogEntry {
 /// [`password`][`Self::password`] will be returned.
 ///
 /// All info and error messages will be recorded in [`log`][`Self::log`].
+/// Binary messages are recorded there too, but answered with `CONV_ERR`.
+///
+/// All responses returned to prompts are recorded in
+/// [`responses()`][`Self::responses()`] along with the prompt style, so
+/// tests can check what the module actually received. Responses to secret
+/// prompts are recorded as [`REDACTED_RESPONSE`] unless
+/// [`set_reveal_secrets()`][`Self::set_reveal_secrets()`] was enabled.
+///
+/// Each log entry has a sequence id, starting at 0 and increasing with each
+/// recorded message. UIs polling the log can use
+/// [`log_since()`][`Self::log_since()`] to only fetch new entries. The ids
+/// stay stable across [`clear_log()`][`Self::clear_log()`], but not when
+/// entries are removed from [`log`][`Self::log`] directly.
 ///
 /// # Limitations
 ///
@@ -40,16 +111,37 @@ pub enum LogEntry {
 ///
 /// Please also note that UTF-8 encoding is assumed for both username and
 /// password, so this handler may fail to authenticate on legacy non-UTF-8
-/// systems when one of the strings contains non-ASCII characters.
-#[derive(Debug, Clone)]
+/// systems when one of the strings contains non-ASCII characters. An
+/// [`EncodingPolicy`][`crate::EncodingPolicy`] on the context can convert
+/// them to the system encoding.
+///
+/// The `Debug` output shows the password as `"***"`, so the handler can be
+/// logged without leaking it.
+#[derive(Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Conversation {
 	/// The username to use
 	pub username: String,
 	/// The password to use
 	pub password: String,
-	/// All received info/error messages
+	/// All received info/error/binary messages
 	pub log: vec::Vec<LogEntry>,
+	/// Optional function normalizing the username before it is returned
+	#[cfg_attr(feature = "serde", serde(skip))]
+	normalize: Option<fn(&str) -> String>,
+	/// All responses returned to prompts with the style of the prompt
+	#[cfg_attr(feature = "serde", serde(default))]
+	responses: vec::Vec<(MsgStyle, CString)>,
+	/// Whether to record responses to secret prompts in
+	/// [`responses()`][`Self::responses()`] verbatim instead of redacting them
+	#[cfg_attr(feature = "serde", serde(default))]
+	reveal_secrets: bool,
+	/// Sequence id of the first entry in `log`
+	#[cfg_attr(feature = "serde", serde(default))]
+	log_base: u64,
+	/// External receiver of log entries replacing `log`
+	#[cfg_attr(feature = "serde", serde(skip))]
+	sink: Option<Arc<Mutex<LogSink>>>,
 }
 
 impl Conversation {
@@ -64,6 +156,11 @@ impl Conversation {
 			username: String::new(),
 			password: String::new(),
 			log: vec::Vec::new(),
+			normalize: None,
+			responses: vec::Vec::new(),
+			reveal_secrets: false,
+			log_base: 0,
+			sink: None,
 		}
 	}
 
@@ -74,29 +171,299 @@ impl Conversation {
 			username: username.into(),
 			password: password.into(),
 			log: vec::Vec::new(),
+			normalize: None,
+			responses: vec::Vec::new(),
+			reveal_secrets: false,
+			log_base: 0,
+			sink: None,
 		}
 	}
 
+	/// Copies the username and password into `other`
+	///
+	/// The log and other settings of `other` stay untouched, which makes it
+	/// easy to set up multiple handlers with the same credentials.
+	pub fn clone_credentials_into(&self, other: &mut Self) {
+		other.username.clone_from(&self.username);
+		other.password.clone_from(&self.password);
+	}
+
+	/// Sends log entries to `sink` instead of storing them in
+	/// [`log`][`Self::log`]
+	///
+	/// Avoids buffering messages twice in long-running services, e.g. when
+	/// they are forwarded to a channel or written to a file anyway. Clones
+	/// of the handler share the sink.
+	///
+	/// ```rust
+	/// use pam_client::conv_mock::Conversation;
+	/// use std::sync::mpsc;
+	///
+	/// let (sender, receiver) = mpsc::channel();
+	/// let mut handler = Conversation::with_credentials("user", "pass");
+	/// handler.set_log_sink(move |entry| {
+	///     let _ = sender.send(entry);
+	/// });
+	/// ```
+	pub fn set_log_sink<F>(&mut self, sink: F)
+	where
+		F: FnMut(LogEntry) + Send + 'static,
+	{
+		self.sink = Some(Arc::new(Mutex::new(sink)));
+	}
+
+	/// Removes the log sink, so entries are stored in [`log`][`Self::log`]
+	/// again
+	pub fn clear_log_sink(&mut self) {
+		self.sink = None;
+	}
+
+	/// Records a log entry in the sink or in `log`
+	fn push_log(&mut self, entry: LogEntry) {
+		match &self.sink {
+			None => self.log.push(entry),
+			Some(sink) => match sink.lock() {
+				Ok(mut sink) => sink(entry),
+				Err(poisoned) => poisoned.into_inner()(entry),
+			},
+		}
+	}
+
+	/// Normalizes the username with `normalize` before returning it
+	///
+	/// E.g. `|name| name.to_lowercase()` to test modules mapping usernames
+	/// case-insensitively. [`username`][`Self::username`] itself stays
+	/// unchanged.
+	///
+	/// ```rust
+	/// use pam_client::conv_mock::Conversation;
+	/// use pam_client::ConversationHandler;
+	/// use std::ffi::CString;
+	///
+	/// let mut handler = Conversation::with_credentials("Alice", "pass")
+	///     .with_normalize(|name| name.to_lowercase());
+	/// let response = handler.prompt_echo_on(&CString::new("login: ").unwrap())?;
+	/// assert_eq!(response.to_bytes(), b"alice");
+	/// # Ok::<(), pam_client::ErrorCode>(())
+	/// ```
+	#[must_use]
+	pub fn with_normalize(mut self, normalize: fn(&str) -> String) -> Self {
+		self.normalize = Some(normalize);
+		self
+	}
+
+	/// Returns all responses returned to prompts with the style of the
+	/// prompt
+	#[must_use]
+	pub fn responses(&self) -> &[(MsgStyle, CString)] {
+		&self.responses
+	}
+
+	/// Sets whether responses to secret prompts are recorded verbatim in
+	/// [`responses()`][`Self::responses()`] instead of being redacted
+	///
+	/// Only meant for tests checking the passwords the modules received.
+	pub fn set_reveal_secrets(&mut self, reveal: bool) {
+		self.reveal_secrets = reveal;
+	}
+
+	/// Clears the recorded responses
+	pub fn clear_responses(&mut self) {
+		self.responses.clear();
+	}
+
+	/// Records a response returned to a prompt of style `style`
+	fn push_response(&mut self, style: MsgStyle, response: &CString) {
+		let recorded = if style == MsgStyle::PromptEchoOff && !self.reveal_secrets {
+			CString::new(REDACTED_RESPONSE).unwrap_or_default()
+		} else {
+			response.clone()
+		};
+		self.responses.push((style, recorded));
+	}
+
 	/// Clears the error/info log
+	///
+	/// Sequence ids of entries recorded afterwards continue to increase.
 	pub fn clear_log(&mut self) {
+		self.log_base += self.log.len() as u64;
 		self.log.clear();
 	}
 
+	/// Replaces the error/info log, e.g. with one restored from a previous
+	/// session
+	///
+	/// Sequence ids restart at 0; use [`set_log()`][`Self::set_log()`] to
+	/// keep the ids of the restored entries.
+	///
+	/// ```rust
+	/// use pam_client::conv_mock::{Conversation, LogEntry};
+	/// use std::ffi::CString;
+	///
+	/// # let saved = vec![LogEntry::Info(CString::new("Welcome").unwrap())];
+	/// // `saved` was e.g. deserialized from the previous process
+	/// let handler = Conversation::with_credentials("user", "pass").with_log(saved);
+	/// assert_eq!(handler.next_log_id(), 1);
+	/// ```
+	#[must_use]
+	pub fn with_log(mut self, log: vec::Vec<LogEntry>) -> Self {
+		self.set_log(log, 0);
+		self
+	}
+
+	/// Replaces the error/info log and sets the sequence id of its first
+	/// entry
+	///
+	/// Pass the id of the first entry the restored log had, e.g. from
+	/// [`log_with_ids()`][`Self::log_with_ids()`], so clients polling with
+	/// [`log_since()`][`Self::log_since()`] continue where they left off.
+	pub fn set_log(&mut self, log: vec::Vec<LogEntry>, first_id: u64) {
+		self.log = log;
+		self.log_base = first_id;
+	}
+
+	/// Returns the sequence id the next log entry will get
+	#[must_use]
+	pub fn next_log_id(&self) -> u64 {
+		self.log_base + self.log.len() as u64
+	}
+
+	/// Lists the log entries together with their sequence ids
+	pub fn log_with_ids(&self) -> impl Iterator<Item = (u64, &LogEntry)> + FusedIterator {
+		let base = self.log_base;
+		self.log
+			.iter()
+			.enumerate()
+			.map(move |(index, entry)| (base + index as u64, entry))
+	}
+
+	/// Lists the log entries with a sequence id of at least `id`
+	///
+	/// Pass the value of [`next_log_id()`][`Self::next_log_id()`] from the
+	/// previous poll to get only new entries.
+	pub fn log_since(&self, id: u64) -> impl Iterator<Item = (u64, &LogEntry)> + FusedIterator {
+		self.log_with_ids()
+			.filter(move |(entry_id, _)| *entry_id >= id)
+	}
+
 	/// Lists only errors from the log
 	pub fn errors(&self) -> impl Iterator<Item = &CString> + FusedIterator {
 		self.log.iter().filter_map(|x| match x {
-			LogEntry::Info(_) => None,
 			LogEntry::Error(msg) => Some(msg),
+			_ => None,
 		})
 	}
 
+	/// Returns the most recent error message from the log
+	///
+	/// After a failed operation this is usually the explanation the module
+	/// gave for the failure, e.g. to show it to the user along with the
+	/// error code:
+	///
+	/// ```rust
+	/// # use pam_client::{Context, Flag};
+	/// # use pam_client::conv_mock::Conversation;
+	/// # let mut context = Context::new("test", None, Conversation::with_credentials("user", "pass")).unwrap();
+	/// if let Err(e) = context.authenticate(Flag::NONE) {
+	///     match context.conversation().last_error_message() {
+	///         Some(reason) => println!("{} ({})", reason.to_string_lossy(), e),
+	///         None => println!("{}", e),
+	///     }
+	/// }
+	/// ```
+	///
+	/// Entries passed to a [log sink][`Self::set_log_sink()`] are not
+	/// considered.
+	#[must_use]
+	pub fn last_error_message(&self) -> Option<&CString> {
+		self.errors().last()
+	}
+
 	/// Lists only info messages from the log
 	pub fn infos(&self) -> impl Iterator<Item = &CString> + FusedIterator {
 		self.log.iter().filter_map(|x| match x {
 			LogEntry::Info(msg) => Some(msg),
-			LogEntry::Error(_) => None,
+			_ => None,
 		})
 	}
+
+	/// Lists only binary messages from the log as type byte and payload
+	pub fn binaries(&self) -> impl Iterator<Item = (BinaryType, &[u8])> + FusedIterator {
+		self.log.iter().filter_map(|x| match x {
+			LogEntry::Binary(type_, data) => Some((*type_, data.as_slice())),
+			_ => None,
+		})
+	}
+
+	/// Lists only the payloads of binary messages with the type byte `type_`
+	pub fn binaries_of_type(
+		&self,
+		type_: BinaryType,
+	) -> impl Iterator<Item = &[u8]> + FusedIterator {
+		self.binaries()
+			.filter_map(move |(t, data)| if t == type_ { Some(data) } else { None })
+	}
+
+	/// Returns the number of binary messages in the log
+	#[must_use]
+	pub fn binary_count(&self) -> usize {
+		self.binaries().count()
+	}
+
+	/// Renders the log as a multi-line transcript, e.g. for bug reports
+	///
+	/// Each entry is formatted on its own line in the order it was
+	/// received, as described for the [`Display`] implementation of
+	/// [`LogEntry`]. Entries passed to a [log sink][`Self::set_log_sink()`]
+	/// are not included.
+	#[must_use]
+	pub fn transcript(&self) -> String {
+		let mut transcript = String::new();
+		for entry in &self.log {
+			let _ = writeln!(transcript, "{}", entry);
+		}
+		transcript
+	}
+
+	/// Lists only errors from the log, lossily converted to `String`s
+	///
+	/// Invalid UTF-8 sequences are replaced with `U+FFFD REPLACEMENT
+	/// CHARACTER`. Use [`errors()`][`Self::errors()`] for byte-exact access.
+	pub fn errors_lossy(&self) -> impl Iterator<Item = String> + FusedIterator + '_ {
+		self.errors().map(|msg| msg.to_string_lossy().into_owned())
+	}
+
+	/// Lists only info messages from the log, lossily converted to `String`s
+	///
+	/// Invalid UTF-8 sequences are replaced with `U+FFFD REPLACEMENT
+	/// CHARACTER`. Use [`infos()`][`Self::infos()`] for byte-exact access.
+	pub fn infos_lossy(&self) -> impl Iterator<Item = String> + FusedIterator + '_ {
+		self.infos().map(|msg| msg.to_string_lossy().into_owned())
+	}
+}
+
+impl Debug for Conversation {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("Conversation")
+			.field("username", &self.username)
+			.field("password", &"***")
+			.field("log", &self.log)
+			.field(
+				"responses",
+				&self
+					.responses
+					.iter()
+					.map(|(style, response)| match style {
+						MsgStyle::PromptEchoOff => (*style, REDACTED_RESPONSE),
+						_ => (*style, response.to_str().unwrap_or("<non-UTF-8>")),
+					})
+					.collect::<vec::Vec<_>>(),
+			)
+			.field("reveal_secrets", &self.reveal_secrets)
+			.field("normalize", &self.normalize.map(|_| "<function>"))
+			.field("sink", &self.sink.as_ref().map(|_| "<function>"))
+			.finish()
+	}
 }
 
 impl Default for Conversation {
@@ -115,30 +482,192 @@ impl ConversationHandler for Conversation {
 	}
 
 	fn prompt_echo_on(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
-		CString::new(self.username.clone()).map_err(|_| ErrorCode::CONV_ERR)
+		let username = match self.normalize {
+			None => self.username.clone(),
+			Some(normalize) => normalize(&self.username),
+		};
+		let response = CString::new(username)?;
+		self.push_response(MsgStyle::PromptEchoOn, &response);
+		Ok(response)
 	}
 
 	fn prompt_echo_off(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
-		CString::new(self.password.clone()).map_err(|_| ErrorCode::CONV_ERR)
+		let response = CString::new(self.password.clone())?;
+		self.push_response(MsgStyle::PromptEchoOff, &response);
+		Ok(response)
 	}
 
 	fn text_info(&mut self, msg: &CStr) {
-		self.log.push(LogEntry::Info(msg.to_owned()));
+		self.push_log(LogEntry::Info(msg.to_owned()));
 	}
 
 	fn error_msg(&mut self, msg: &CStr) {
-		self.log.push(LogEntry::Error(msg.to_owned()));
+		self.push_log(LogEntry::Error(msg.to_owned()));
 	}
 
 	fn radio_prompt(&mut self, _msg: &CStr) -> Result<bool, ErrorCode> {
 		Ok(false)
 	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.push_log(LogEntry::Binary(type_, data.to_vec()));
+		Err(ErrorCode::CONV_ERR)
+	}
+
+	/// Checks that username and password are non-empty and contain no NUL
+	/// bytes, without recording anything.
+	///
+	/// The username is usually filled in by [`init()`][`Self::init()`], so
+	/// call this after passing the handler to a context.
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		let valid = |s: &str| !s.is_empty() && !s.contains('\0');
+		if valid(&self.username) && valid(&self.password) {
+			Ok(())
+		} else {
+			Err(ErrorCode::CONV_ERR)
+		}
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
 
+	#[test]
+	fn test_log_sink() {
+		let text = CString::new("test").unwrap();
+		let received = Arc::new(Mutex::new(Vec::new()));
+		let sink_received = Arc::clone(&received);
+		let mut c = Conversation::new();
+		c.set_log_sink(move |entry| sink_received.lock().unwrap().push(entry));
+		c.text_info(&text);
+		c.clone().error_msg(&text);
+		assert!(c.log.is_empty());
+		assert_eq!(
+			*received.lock().unwrap(),
+			vec![LogEntry::Info(text.clone()), LogEntry::Error(text.clone())]
+		);
+		assert!(format!("{:?}", c).contains("<function>"));
+
+		c.clear_log_sink();
+		c.text_info(&text);
+		assert_eq!(c.log.len(), 1);
+		assert_eq!(received.lock().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn test_clone_credentials() {
+		let text = CString::new("test").unwrap();
+		let source = Conversation::with_credentials("user", "pass");
+		let mut target = Conversation::new();
+		target.text_info(&text);
+		source.clone_credentials_into(&mut target);
+		assert_eq!(target.username, "user");
+		assert_eq!(target.password, "pass");
+		assert_eq!(target.log, vec![LogEntry::Info(text)]);
+		assert_eq!(target.log[0].style(), MsgStyle::TextInfo);
+	}
+
+	#[test]
+	fn test_normalize() {
+		let text = CString::new("test").unwrap();
+		let mut c = Conversation::with_credentials("Alice", "pw");
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_bytes(), b"Alice");
+		let mut c = c.with_normalize(|name| name.to_lowercase());
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_bytes(), b"alice");
+		assert_eq!(c.username, "Alice");
+		assert!(format!("{:?}", c).contains("<function>"));
+	}
+
+	#[test]
+	fn test_log_ids() {
+		let text = CString::new("test").unwrap();
+		let mut c = Conversation::new();
+		assert_eq!(c.next_log_id(), 0);
+		c.text_info(&text);
+		c.error_msg(&text);
+		let ids: Vec<u64> = c.log_with_ids().map(|(id, _)| id).collect();
+		assert_eq!(ids, vec![0, 1]);
+
+		let seen = c.next_log_id();
+		c.clear_log();
+		assert_eq!(c.next_log_id(), 2);
+		c.error_msg(&text);
+		let new: Vec<_> = c.log_since(seen).collect();
+		assert_eq!(new, vec![(2, &LogEntry::Error(text.clone()))]);
+		assert_eq!(c.log_since(3).count(), 0);
+
+		// Restore the log in a new handler
+		let (first_id, _) = c.log_with_ids().next().unwrap();
+		let mut restored = Conversation::new();
+		restored.set_log(c.log.clone(), first_id);
+		assert_eq!(restored.log_with_ids().collect::<Vec<_>>(), new);
+		assert_eq!(restored.next_log_id(), 3);
+		let restored = Conversation::new().with_log(c.log.clone());
+		assert_eq!(restored.log, c.log);
+		assert_eq!(restored.next_log_id(), 1);
+	}
+
+	#[test]
+	fn test_responses() {
+		let text = CString::new("test").unwrap();
+		let mut c = Conversation::with_credentials("user", "pass");
+		c.text_info(&text);
+		assert!(c.prompt_echo_on(&text).is_ok());
+		assert!(c.prompt_echo_off(&text).is_ok());
+		assert_eq!(
+			c.responses(),
+			[
+				(MsgStyle::PromptEchoOn, CString::new("user").unwrap()),
+				(
+					MsgStyle::PromptEchoOff,
+					CString::new(REDACTED_RESPONSE).unwrap()
+				),
+			]
+		);
+
+		c.clear_responses();
+		c.set_reveal_secrets(true);
+		assert!(c.prompt_echo_off(&text).is_ok());
+		assert_eq!(
+			c.responses(),
+			[(MsgStyle::PromptEchoOff, CString::new("pass").unwrap())]
+		);
+		assert!(!format!("{:?}", c).contains("pass\""));
+
+		// Failed prompts are not recorded
+		c.password = "nul\0byte".to_string();
+		assert!(c.prompt_echo_off(&text).is_err());
+		assert_eq!(c.responses().len(), 1);
+	}
+
+	#[test]
+	fn test_self_test() {
+		assert_eq!(Conversation::new().self_test(), Err(ErrorCode::CONV_ERR));
+		let mut c = Conversation::with_credentials("user", "pass");
+		assert_eq!(c.self_test(), Ok(()));
+		assert!(c.responses().is_empty());
+		c.password = "pa\0ss".to_string();
+		assert_eq!(c.self_test(), Err(ErrorCode::CONV_ERR));
+		let mut c = Conversation::with_credentials("", "pass");
+		c.init(Some("user"));
+		assert_eq!(c.self_test(), Ok(()));
+	}
+
+	#[test]
+	fn test_debug_redacted() {
+		let c = Conversation::with_credentials("someuser", "s3cr3t-password");
+		let debug = format!("{:?}", c);
+		assert!(!debug.contains("s3cr3t-password"));
+		assert!(debug.contains("someuser"));
+		assert!(debug.contains("***"));
+		assert!(!format!("{:#?}", c).contains("s3cr3t-password"));
+	}
+
 	#[test]
 	fn test() {
 		let text = CString::new("test").unwrap();
@@ -147,10 +676,11 @@ mod tests {
 		assert!(c.prompt_echo_on(&text).is_ok());
 		assert!(c.prompt_echo_off(&text).is_ok());
 		assert!(c.radio_prompt(&text).ok() == Some(false));
-		assert!(c.binary_prompt(0, &[]).is_err());
+		assert!(c.binary_prompt(0.into(), &[]).is_err());
 		c.text_info(&text);
 		c.error_msg(&text);
-		assert_eq!(c.log.len(), 2);
+		assert_eq!(c.log.len(), 3);
+		assert_eq!(c.binary_count(), 1);
 		let v: std::vec::Vec<&CString> = c.errors().collect();
 		assert_eq!(v.len(), 1);
 		let v: std::vec::Vec<&CString> = c.infos().collect();
@@ -158,6 +688,95 @@ mod tests {
 		assert!(format!("{:?}", &c).contains("test"));
 	}
 
+	#[test]
+	fn test_log_eq() {
+		let mut c = Conversation::default();
+		c.text_info(&CString::new("info").unwrap());
+		c.error_msg(&CString::new("error").unwrap());
+		assert_eq!(
+			c.log,
+			vec![
+				LogEntry::Info(CString::new("info").unwrap()),
+				LogEntry::Error(CString::new("error").unwrap()),
+			]
+		);
+		assert_ne!(c.log[0], LogEntry::Error(CString::new("info").unwrap()));
+		assert_eq!(c.clone().log, c.log);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn test_log_serde() {
+		let log = vec![
+			LogEntry::Info(CString::new("info").unwrap()),
+			LogEntry::Error(CString::new("error").unwrap()),
+			LogEntry::Binary(BinaryType::Select, vec![1, 2, 3]),
+		];
+		let json = serde_json::to_string(&log).unwrap();
+		let restored: Vec<LogEntry> = serde_json::from_str(&json).unwrap();
+		assert_eq!(restored, log);
+	}
+
+	#[test]
+	fn test_last_error_message() {
+		let mut c = Conversation::default();
+		assert_eq!(c.last_error_message(), None);
+		c.error_msg(&CString::new("first").unwrap());
+		c.error_msg(&CString::new("second").unwrap());
+		c.text_info(&CString::new("info").unwrap());
+		assert_eq!(
+			c.last_error_message(),
+			Some(&CString::new("second").unwrap())
+		);
+	}
+
+	#[test]
+	fn test_binaries() {
+		let mut c = Conversation::default();
+		assert!(c.binary_prompt(BinaryType::Ok, b"abc").is_err());
+		c.text_info(&CString::new("info").unwrap());
+		assert!(c.binary_prompt(BinaryType::Select, b"def").is_err());
+		assert!(c.binary_prompt(BinaryType::Ok, b"").is_err());
+		assert_eq!(c.binary_count(), 3);
+		assert_eq!(
+			c.binaries().collect::<Vec<_>>(),
+			vec![
+				(BinaryType::Ok, &b"abc"[..]),
+				(BinaryType::Select, &b"def"[..]),
+				(BinaryType::Ok, &b""[..])
+			]
+		);
+		assert_eq!(
+			c.binaries_of_type(BinaryType::Ok).collect::<Vec<_>>(),
+			vec![&b"abc"[..], &b""[..]]
+		);
+		assert_eq!(c.binaries_of_type(BinaryType::Done).count(), 0);
+		assert_eq!(c.infos().count(), 1);
+		assert_eq!(c.errors().count(), 0);
+	}
+
+	#[test]
+	fn test_lossy() {
+		let mut c = Conversation::default();
+		c.text_info(&CString::new("info").unwrap());
+		c.error_msg(&CString::new(b"err\xFF".to_vec()).unwrap());
+		assert_eq!(c.infos_lossy().collect::<Vec<_>>(), vec!["info"]);
+		assert_eq!(c.errors_lossy().collect::<Vec<_>>(), vec!["err\u{FFFD}"]);
+	}
+
+	#[test]
+	fn test_transcript() {
+		let mut c = Conversation::default();
+		assert_eq!(c.transcript(), "");
+		c.text_info(&CString::new("Welcome").unwrap());
+		c.error_msg(&CString::new(b"Bad\xFF password".to_vec()).unwrap());
+		let _ = c.binary_prompt(7.into(), b"abc");
+		assert_eq!(
+			c.transcript(),
+			"[INFO] Welcome\n[ERROR] Bad\u{FFFD} password\n[BINARY] type 7, 3 bytes\n"
+		);
+	}
+
 	#[test]
 	fn test_boxed() {
 		let text = CString::new("test").unwrap();
@@ -165,10 +784,11 @@ mod tests {
 		assert!(c.prompt_echo_on(&text).is_ok());
 		assert!(c.prompt_echo_off(&text).is_ok());
 		assert!(c.radio_prompt(&text).ok() == Some(false));
-		assert!(c.binary_prompt(0, &[]).is_err());
+		assert!(c.binary_prompt(0.into(), &[]).is_err());
 		c.text_info(&text);
 		c.error_msg(&text);
-		assert_eq!(c.log.len(), 2);
+		assert_eq!(c.log.len(), 3);
+		assert_eq!(c.binary_count(), 1);
 		let v: std::vec::Vec<&CString> = c.errors().collect();
 		assert_eq!(v.len(), 1);
 		let v: std::vec::Vec<&CString> = c.infos().collect();
@@ -69,7 +69,7 @@ mod tests {
 		assert!(c.prompt_echo_on(&text).is_err());
 		assert!(c.prompt_echo_off(&text).is_err());
 		assert!(c.radio_prompt(&text).is_err());
-		assert!(c.binary_prompt(0, &[]).is_err());
+		assert!(c.binary_prompt(0.into(), &[]).is_err());
 		c.text_info(&text);
 		c.error_msg(&text);
 	}
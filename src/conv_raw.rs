@@ -0,0 +1,213 @@
+/*!
+ * Conversation handler calling a C conversation function
+ *
+ * Escape hatch for embedding this crate into existing C PAM integrations,
+ * which already have a `pam_conv` callback and expect their own
+ * `appdata_ptr` to be passed to it.
+ */
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+use super::ConversationHandler;
+use crate::error::ErrorCode;
+use crate::PAM_SUCCESS;
+
+use libc::{c_int, c_void};
+use pam_sys::{pam_message as PamMessage, pam_response as PamResponse};
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+/// Signature of C conversation functions, as in `struct pam_conv`
+pub type ConvFn = unsafe extern "C" fn(
+	num_msg: c_int,
+	msg: *mut *const PamMessage,
+	resp: *mut *mut PamResponse,
+	appdata_ptr: *mut c_void,
+) -> c_int;
+
+/// Implementation of `ConversationHandler` forwarding to a C function
+///
+/// Each message is passed on to `conv` as a separate call with
+/// `appdata_ptr` as the application data, exactly as PAM would call it.
+/// The context itself still installs its own `pam_conv` structure, so
+/// modules never see `appdata_ptr` directly.
+///
+/// Binary prompts are not forwarded and fail with
+/// [`ErrorCode::CONV_ERR`].
+///
+/// # Memory management
+///
+/// `conv` must follow the PAM conversation contract: the response array
+/// and the response strings are allocated with `malloc`/`calloc` and
+/// ownership passes to the caller. This handler frees both, overwriting
+/// the response strings with zeros first. The memory behind `appdata_ptr`
+/// is never accessed, freed or otherwise managed by this handler.
+///
+/// Error codes returned by `conv` are passed on to the PAM module.
+#[derive(Debug)]
+pub struct Conversation {
+	conv: ConvFn,
+	appdata_ptr: *mut c_void,
+}
+
+impl Conversation {
+	/// Creates a handler forwarding all messages to `conv` along with
+	/// `appdata_ptr`.
+	///
+	/// # Safety
+	/// `conv` must be safe to call with `appdata_ptr` for as long as the
+	/// handler exists and must follow the memory management contract
+	/// described in the [type documentation][`Self`]. The handler isn't
+	/// `Send`, so `appdata_ptr` stays on the thread creating the handler
+	/// and `conv` is only called from there.
+	pub const unsafe fn new(conv: ConvFn, appdata_ptr: *mut c_void) -> Self {
+		Self { conv, appdata_ptr }
+	}
+
+	/// Returns the application data pointer passed to the function.
+	#[must_use]
+	pub const fn appdata_ptr(&self) -> *mut c_void {
+		self.appdata_ptr
+	}
+
+	/// Calls the conversation function with a single message.
+	fn converse(&self, style: c_int, msg: &CStr) -> Result<Option<CString>, ErrorCode> {
+		let message = PamMessage {
+			msg_style: style as _,
+			msg: msg.as_ptr(),
+		};
+		let mut msg_ptr: *const PamMessage = &message;
+		let mut resp: *mut PamResponse = ptr::null_mut();
+		// Safety: guaranteed by the caller of `new()`
+		let code = unsafe { (self.conv)(1, &mut msg_ptr, &mut resp, self.appdata_ptr) };
+		// Safety: `conv` passed ownership of the response to us
+		let text = unsafe { take_response(resp) };
+		if code == PAM_SUCCESS {
+			Ok(text)
+		} else {
+			Err(ErrorCode::try_from(code).unwrap_or(ErrorCode::CONV_ERR))
+		}
+	}
+
+	/// Calls the conversation function with a prompt.
+	fn prompt(&self, style: c_int, msg: &CStr) -> Result<CString, ErrorCode> {
+		self.converse(style, msg)?.ok_or(ErrorCode::CONV_ERR)
+	}
+}
+
+/// Internal: Takes the text out of a response array with one element and
+/// frees the array.
+///
+/// # Safety
+/// `resp` must be null or point to a `malloc`-allocated response whose
+/// text is null or `malloc`-allocated.
+unsafe fn take_response(resp: *mut PamResponse) -> Option<CString> {
+	if resp.is_null() {
+		return None;
+	}
+	let raw = (*resp).resp;
+	let text = if raw.is_null() {
+		None
+	} else {
+		let text = CStr::from_ptr(raw).to_owned();
+		for i in 0..text.as_bytes().len() {
+			ptr::write_volatile(raw.add(i), 0);
+		}
+		libc::free(raw.cast());
+		Some(text)
+	};
+	libc::free(resp.cast());
+	text
+}
+
+impl ConversationHandler for Conversation {
+	fn prompt_echo_on(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+		self.prompt(pam_sys::PAM_PROMPT_ECHO_ON as c_int, msg)
+	}
+
+	fn prompt_echo_off(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+		self.prompt(pam_sys::PAM_PROMPT_ECHO_OFF as c_int, msg)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		let _ = self.converse(pam_sys::PAM_TEXT_INFO as c_int, msg);
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		let _ = self.converse(pam_sys::PAM_ERROR_MSG as c_int, msg);
+	}
+
+	#[cfg(target_os = "linux")]
+	fn radio_prompt(&mut self, msg: &CStr) -> Result<bool, ErrorCode> {
+		let answer = self.prompt(pam_sys::PAM_RADIO_TYPE as c_int, msg)?;
+		Ok(matches!(
+			answer.as_bytes_with_nul()[0],
+			b'Y' | b'y' | b'j' | b'J'
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Records the messages in `appdata_ptr` and answers prompts with
+	/// "answer", except prompts saying "fail".
+	unsafe extern "C" fn record(
+		num_msg: c_int,
+		msg: *mut *const PamMessage,
+		resp: *mut *mut PamResponse,
+		appdata_ptr: *mut c_void,
+	) -> c_int {
+		assert_eq!(num_msg, 1);
+		let log = &mut *appdata_ptr.cast::<Vec<(c_int, CString)>>();
+		let message = &**msg;
+		let text = CStr::from_ptr(message.msg).to_owned();
+		if text.as_bytes() == b"fail" {
+			return pam_sys::PAM_ABORT as c_int;
+		}
+		log.push((message.msg_style as c_int, text));
+		let response: *mut PamResponse = libc::calloc(1, std::mem::size_of::<PamResponse>()).cast();
+		if message.msg_style as c_int != pam_sys::PAM_TEXT_INFO as c_int {
+			(*response).resp = libc::strdup(b"yes\0".as_ptr().cast());
+		}
+		*resp = response;
+		PAM_SUCCESS
+	}
+
+	#[test]
+	fn test_forwarding() {
+		let text = CString::new("test").unwrap();
+		let fail = CString::new("fail").unwrap();
+		let mut log: Vec<(c_int, CString)> = Vec::new();
+		let appdata = (&mut log as *mut Vec<(c_int, CString)>).cast();
+		let mut c = unsafe { Conversation::new(record, appdata) };
+		assert_eq!(c.appdata_ptr(), appdata);
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_bytes(), b"yes");
+		assert_eq!(c.prompt_echo_off(&text).unwrap().to_bytes(), b"yes");
+		assert_eq!(c.radio_prompt(&text), Ok(true));
+		c.text_info(&text);
+		assert_eq!(c.prompt_echo_on(&fail), Err(ErrorCode::ABORT));
+		assert_eq!(c.binary_prompt(1.into(), b""), Err(ErrorCode::CONV_ERR));
+		assert!(format!("{:?}", c).contains("Conversation"));
+		drop(c);
+
+		let styles: Vec<c_int> = log.iter().map(|(style, _)| *style).collect();
+		assert_eq!(
+			&styles[..2],
+			&[
+				pam_sys::PAM_PROMPT_ECHO_ON as c_int,
+				pam_sys::PAM_PROMPT_ECHO_OFF as c_int
+			]
+		);
+		assert_eq!(styles.last(), Some(&(pam_sys::PAM_TEXT_INFO as c_int)));
+		assert!(log.iter().all(|(_, msg)| msg == &text));
+	}
+}
@@ -12,12 +12,80 @@
 
 use crate::error::ErrorCode;
 use std::ffi::{CStr, CString};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::hash::{Hash, Hasher};
 use std::result::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+/// Default maximum payload size of binary messages in bytes.
+///
+/// See [`ConversationHandler::max_binary_len()`].
+pub const DEFAULT_MAX_BINARY_LEN: usize = 64 * 1024;
+
+/// Position of a prompt within a conversation call
+///
+/// PAM modules may send several messages in one call to the conversation
+/// function, e.g. an info message followed by a password prompt. Passed to
+/// [`ConversationHandler::prompt_echo_on_with_context()`] and
+/// [`ConversationHandler::prompt_echo_off_with_context()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PromptContext {
+	/// Zero-based index of the message within the call
+	pub index: usize,
+	/// Number of messages in the call, including info and error messages
+	pub total: usize,
+}
+
+/// Signals that the user cancelled the conversation
+///
+/// Return the result of this function as the error of a prompt method when
+/// the user aborted the input, e.g. by pressing Ctrl-C or closing a
+/// dialog:
+///
+/// ```rust
+/// # use std::ffi::{CStr, CString};
+/// # use pam_client::{ConversationHandler, ErrorCode, user_cancelled};
+/// # struct Dialog;
+/// # impl Dialog { fn ask(&self, _: &CStr) -> Option<CString> { None } }
+/// # impl ConversationHandler for Dialog {
+/// fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+///     self.ask(prompt).ok_or_else(user_cancelled)
+/// }
+/// # fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+/// #     self.ask(prompt).ok_or_else(user_cancelled)
+/// # }
+/// # fn text_info(&mut self, _: &CStr) {}
+/// # fn error_msg(&mut self, _: &CStr) {}
+/// # }
+/// ```
+///
+/// The PAM module still sees [`ErrorCode::CONV_ERR`], but an error of the
+/// operation in progress will return `true` for
+/// [`Error::is_user_cancelled()`][`crate::Error::is_user_cancelled()`],
+/// so applications can report "cancelled" instead of an authentication
+/// failure.
+///
+/// The cancellation is recorded per thread, so this must be called from
+/// within the handler method invoked by PAM, not from another thread. It
+/// only counts if the conversation callback fails with it: if a wrapper
+/// like [`FallbackConversation`] recovers and answers the prompt, or a
+/// later callback of the same operation succeeds, it is discarded.
+#[must_use]
+pub fn user_cancelled() -> ErrorCode {
+	crate::ffi::set_user_cancelled();
+	ErrorCode::CONV_ERR
+}
 
 /// Trait for PAM conversation functions
 ///
 /// Implement this for custom behaviour when a PAM module asks for usernames,
 /// passwords, etc. or wants to show a message to the user
+///
+/// Handlers return [`user_cancelled()`] from prompt methods to signal that
+/// the user aborted the input instead of a conversation failure.
 #[rustversion::attr(since(1.48), doc(alias = "pam_conv"))]
 pub trait ConversationHandler {
 	/// Called by [`Context`][`crate::Context`] directly after taking ownership
@@ -54,6 +122,41 @@ pub trait ConversationHandler {
 	///   try again later.
 	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode>;
 
+	/// Obtains a string whilst echoing text, knowing the position of the
+	/// prompt within the conversation call.
+	///
+	/// Called by the conversation callback instead of
+	/// [`prompt_echo_on()`][`Self::prompt_echo_on()`]. Override this to
+	/// e.g. show progress in interactive user interfaces. The default
+	/// implementation ignores `context` and calls `prompt_echo_on`.
+	///
+	/// # Errors
+	/// Same as [`prompt_echo_on()`][`Self::prompt_echo_on()`].
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		_context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.prompt_echo_on(prompt)
+	}
+
+	/// Obtains a string without echoing any text, knowing the position of
+	/// the prompt within the conversation call.
+	///
+	/// Called by the conversation callback instead of
+	/// [`prompt_echo_off()`][`Self::prompt_echo_off()`]. The default
+	/// implementation ignores `context` and calls `prompt_echo_off`.
+	///
+	/// # Errors
+	/// Same as [`prompt_echo_off()`][`Self::prompt_echo_off()`].
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		_context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.prompt_echo_off(prompt)
+	}
+
 	/// Displays some text.
 	fn text_info(&mut self, msg: &CStr);
 
@@ -90,9 +193,51 @@ pub trait ConversationHandler {
 	/// - [`ErrorCode::CONV_AGAIN`]: no result yet, the PAM library should
 	///   pass [`ErrorCode::INCOMPLETE`] to the application and let it
 	///   try again later.
-	fn binary_prompt(&mut self, _type: u8, _data: &[u8]) -> Result<(u8, Vec<u8>), ErrorCode> {
+	fn binary_prompt(
+		&mut self,
+		_type: BinaryType,
+		_data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
 		Err(ErrorCode::CONV_ERR)
 	}
+
+	/// Maximum payload size in bytes accepted for binary messages (Linux
+	/// specific, experimental).
+	///
+	/// Binary messages from PAM modules claiming a larger payload are
+	/// rejected with [`ErrorCode::CONV_ERR`] before
+	/// [`binary_prompt()`][`Self::binary_prompt()`] is called, so `data`
+	/// is never longer than this limit.
+	///
+	/// The default implementation returns [`DEFAULT_MAX_BINARY_LEN`]
+	/// (64 KiB). Override this method to raise or lower the limit.
+	fn max_binary_len(&self) -> usize {
+		DEFAULT_MAX_BINARY_LEN
+	}
+
+	/// Checks that the handler answers prompts, e.g. before using it in
+	/// production.
+	///
+	/// The default implementation sends a synthetic echo-on and echo-off
+	/// prompt through [`prompt_echo_on()`][`Self::prompt_echo_on()`] and
+	/// [`prompt_echo_off()`][`Self::prompt_echo_off()`] and discards the
+	/// responses. **Caution:** Interactive handlers will show these prompts
+	/// to the user; override this method to check such handlers without
+	/// user interaction.
+	///
+	/// # Errors
+	/// The error code returned by the first failing prompt.
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.prompt_echo_on(self_test_prompt())?;
+		self.prompt_echo_off(self_test_prompt())?;
+		Ok(())
+	}
+}
+
+/// Prompt used by the default implementation of
+/// [`ConversationHandler::self_test()`]
+fn self_test_prompt() -> &'static CStr {
+	CStr::from_bytes_with_nul(b"Self test: \0").unwrap()
 }
 
 macro_rules! impl_for_wrapper {
@@ -116,6 +261,24 @@ macro_rules! impl_for_wrapper {
 				(**self).prompt_echo_off(prompt)
 			}
 
+			#[inline]
+			fn prompt_echo_on_with_context(
+				&mut self,
+				prompt: &CStr,
+				context: PromptContext,
+			) -> Result<CString, ErrorCode> {
+				(**self).prompt_echo_on_with_context(prompt, context)
+			}
+
+			#[inline]
+			fn prompt_echo_off_with_context(
+				&mut self,
+				prompt: &CStr,
+				context: PromptContext,
+			) -> Result<CString, ErrorCode> {
+				(**self).prompt_echo_off_with_context(prompt, context)
+			}
+
 			#[inline]
 			fn text_info(&mut self, msg: &CStr) {
 				(**self).text_info(msg)
@@ -134,13 +297,1737 @@ macro_rules! impl_for_wrapper {
 			#[inline]
 			fn binary_prompt(
 				&mut self,
-				type_: u8,
+				type_: BinaryType,
 				data: &[u8],
-			) -> Result<(u8, Vec<u8>), ErrorCode> {
+			) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
 				(**self).binary_prompt(type_, data)
 			}
+
+			#[inline]
+			fn max_binary_len(&self) -> usize {
+				(**self).max_binary_len()
+			}
+
+			#[inline]
+			fn self_test(&mut self) -> Result<(), ErrorCode> {
+				(**self).self_test()
+			}
 		}
 	};
 }
 impl_for_wrapper!(&'a mut T, <'a, T: ConversationHandler + ?Sized>);
 impl_for_wrapper!(Box<T>, <T: ConversationHandler + ?Sized>);
+
+/// Locks a shared conversation handler, ignoring lock poisoning.
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+	mutex
+		.lock()
+		.unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Shared conversation handler.
+///
+/// Allows multiple [`Context`][`crate::Context`]s to use the same handler,
+/// e.g. to authenticate against several PAM services with the same
+/// credentials and a common message log. The mutex is locked for the
+/// duration of each callback.
+///
+/// **Caution:** Calling into a context using the same handler from within
+/// the handler (or a PAM module somehow re-entering the conversation)
+/// deadlocks.
+impl<T: ConversationHandler + ?Sized> ConversationHandler for Arc<Mutex<T>> {
+	fn init(&mut self, default_user: Option<&str>) {
+		lock(self).init(default_user)
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		lock(self).prompt_echo_on(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		lock(self).prompt_echo_off(prompt)
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		lock(self).prompt_echo_on_with_context(prompt, context)
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		lock(self).prompt_echo_off_with_context(prompt, context)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		lock(self).text_info(msg)
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		lock(self).error_msg(msg)
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		lock(self).radio_prompt(prompt)
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		lock(self).binary_prompt(type_, data)
+	}
+
+	fn max_binary_len(&self) -> usize {
+		lock(self).max_binary_len()
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		lock(self).self_test()
+	}
+}
+
+/// Conversation handler wrapper limiting the number of prompts
+///
+/// Passes all calls to the wrapped handler, until `max_prompts` prompts
+/// (of any kind, including radio and binary prompts) were answered. Further
+/// prompts fail with [`ErrorCode::CONV_ERR`] without calling the wrapped
+/// handler. Info and error messages are always passed through.
+///
+/// Protects automated environments against misbehaving modules prompting
+/// in a loop. [`self_test()`][`ConversationHandler::self_test()`] is
+/// passed on without counting prompts.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{BoundedConversation, Context};
+/// use pam_client::conv_mock::Conversation;
+///
+/// let handler = BoundedConversation::new(Conversation::with_credentials("user", "pass"), 5);
+/// # let _ = Context::new("my-service", None, handler).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BoundedConversation<H> {
+	handler: H,
+	max_prompts: usize,
+	prompts: usize,
+}
+
+impl<H> BoundedConversation<H> {
+	/// Wraps `handler`, allowing at most `max_prompts` prompts
+	#[must_use]
+	pub const fn new(handler: H, max_prompts: usize) -> Self {
+		Self {
+			handler,
+			max_prompts,
+			prompts: 0,
+		}
+	}
+
+	/// The number of prompts received so far, including rejected ones
+	#[must_use]
+	pub const fn prompts(&self) -> usize {
+		self.prompts
+	}
+
+	/// The maximum number of prompts passed to the wrapped handler
+	#[must_use]
+	pub const fn max_prompts(&self) -> usize {
+		self.max_prompts
+	}
+
+	/// Resets the prompt counter, e.g. before a new operation
+	pub fn reset(&mut self) {
+		self.prompts = 0;
+	}
+
+	/// Returns a reference to the wrapped handler
+	#[must_use]
+	pub const fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the wrapped handler
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Unwraps the wrapped handler
+	#[must_use]
+	pub fn into_inner(self) -> H {
+		self.handler
+	}
+
+	/// Counts a prompt and checks if the limit is exceeded
+	fn check_limit(&mut self) -> Result<(), ErrorCode> {
+		self.prompts = self.prompts.saturating_add(1);
+		if self.prompts > self.max_prompts {
+			Err(ErrorCode::CONV_ERR)
+		} else {
+			Ok(())
+		}
+	}
+}
+
+impl<H: ConversationHandler> ConversationHandler for BoundedConversation<H> {
+	fn init(&mut self, default_user: Option<&str>) {
+		self.handler.init(default_user)
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_limit()?;
+		self.handler.prompt_echo_on(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_limit()?;
+		self.handler.prompt_echo_off(prompt)
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_limit()?;
+		self.handler.prompt_echo_on_with_context(prompt, context)
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_limit()?;
+		self.handler.prompt_echo_off_with_context(prompt, context)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		self.handler.text_info(msg)
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		self.handler.error_msg(msg)
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.check_limit()?;
+		self.handler.radio_prompt(prompt)
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.check_limit()?;
+		self.handler.binary_prompt(type_, data)
+	}
+
+	fn max_binary_len(&self) -> usize {
+		self.handler.max_binary_len()
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.handler.self_test()
+	}
+}
+
+/// Style of a message sent by a PAM module
+///
+/// Corresponds to the `msg_style` field of PAM conversation messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MsgStyle {
+	/// Prompt for visible input (`PAM_PROMPT_ECHO_ON`)
+	PromptEchoOn,
+	/// Prompt for hidden input (`PAM_PROMPT_ECHO_OFF`)
+	PromptEchoOff,
+	/// Error message (`PAM_ERROR_MSG`)
+	ErrorMsg,
+	/// Informational text (`PAM_TEXT_INFO`)
+	TextInfo,
+	/// Yes/no question (`PAM_RADIO_TYPE`, Linux specific)
+	Radio,
+	/// Binary message (`PAM_BINARY_PROMPT`, Linux specific)
+	BinaryPrompt,
+}
+
+/// Type byte of a binary message (Linux specific, experimental)
+///
+/// Linux-PAM prefixes the payload of binary messages and their responses
+/// with a control byte. The known values are the ones of the `libpamc`
+/// binary prompt protocol (`PAM_BPC_*`); all other values are kept as
+/// [`Other`][`Self::Other`], so converting from and to `u8` never loses
+/// information. Values are compared and hashed by their type byte, and
+/// serialized as it with the `serde` feature.
+///
+/// ```rust
+/// use pam_client::BinaryType;
+///
+/// assert_eq!(BinaryType::from(2), BinaryType::Select);
+/// assert_eq!(u8::from(BinaryType::Other(0x42)), 0x42);
+/// assert_eq!(BinaryType::Other(1), BinaryType::Ok);
+/// ```
+#[derive(Debug, Clone, Copy, Eq)]
+#[cfg_attr(
+	feature = "serde",
+	derive(serde::Serialize, serde::Deserialize),
+	serde(from = "u8", into = "u8")
+)]
+pub enum BinaryType {
+	/// Continuation packet (`PAM_BPC_OK`)
+	Ok,
+	/// Initialization packet selecting an agent (`PAM_BPC_SELECT`)
+	Select,
+	/// Termination packet (`PAM_BPC_DONE`)
+	Done,
+	/// Failure to execute the request (`PAM_BPC_FAIL`)
+	Fail,
+	/// Any other type byte
+	///
+	/// Never holds one of the values of the other variants when converted
+	/// from `u8`. If constructed with one, it equals that variant.
+	Other(u8),
+}
+
+impl PartialEq for BinaryType {
+	fn eq(&self, other: &Self) -> bool {
+		u8::from(*self) == u8::from(*other)
+	}
+}
+
+impl Hash for BinaryType {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		u8::from(*self).hash(state);
+	}
+}
+
+impl From<u8> for BinaryType {
+	fn from(value: u8) -> Self {
+		match value {
+			1 => Self::Ok,
+			2 => Self::Select,
+			3 => Self::Done,
+			4 => Self::Fail,
+			other => Self::Other(other),
+		}
+	}
+}
+
+impl From<BinaryType> for u8 {
+	fn from(value: BinaryType) -> Self {
+		match value {
+			BinaryType::Ok => 1,
+			BinaryType::Select => 2,
+			BinaryType::Done => 3,
+			BinaryType::Fail => 4,
+			BinaryType::Other(other) => other,
+		}
+	}
+}
+
+/// Conversation handler wrapper recording the style of each message
+///
+/// Passes all calls to the wrapped handler and records the
+/// [style][`MsgStyle`] and content of every message, including prompts.
+/// Responses are never recorded. For binary messages the content is the
+/// type byte followed by the payload.
+///
+/// Helps module authors verify that their modules send the right message
+/// styles.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{MsgStyle, RecordingConversation};
+/// use pam_client::conv_mock::Conversation;
+///
+/// let handler = RecordingConversation::new(Conversation::with_credentials("user", "pass"));
+/// // ... authenticate with the handler and take it back ...
+/// for (style, content) in handler.messages() {
+///     println!("{:?}: {}", style, String::from_utf8_lossy(content));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordingConversation<H> {
+	handler: H,
+	messages: Vec<(MsgStyle, Vec<u8>)>,
+}
+
+impl<H> RecordingConversation<H> {
+	/// Wraps `handler` with an empty record
+	#[must_use]
+	pub const fn new(handler: H) -> Self {
+		Self {
+			handler,
+			messages: Vec::new(),
+		}
+	}
+
+	/// The recorded messages with their styles
+	#[must_use]
+	pub fn messages(&self) -> &[(MsgStyle, Vec<u8>)] {
+		&self.messages
+	}
+
+	/// Lists only the styles of the recorded messages
+	pub fn styles(&self) -> impl Iterator<Item = MsgStyle> + '_ {
+		self.messages.iter().map(|(style, _)| *style)
+	}
+
+	/// Clears the recorded messages
+	pub fn clear(&mut self) {
+		self.messages.clear();
+	}
+
+	/// Returns a reference to the wrapped handler
+	#[must_use]
+	pub const fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the wrapped handler
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Unwraps the wrapped handler
+	#[must_use]
+	pub fn into_inner(self) -> H {
+		self.handler
+	}
+
+	/// Records a message
+	fn record(&mut self, style: MsgStyle, msg: &CStr) {
+		self.messages.push((style, msg.to_bytes().to_vec()));
+	}
+}
+
+impl<H: ConversationHandler> ConversationHandler for RecordingConversation<H> {
+	fn init(&mut self, default_user: Option<&str>) {
+		self.handler.init(default_user)
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.record(MsgStyle::PromptEchoOn, prompt);
+		self.handler.prompt_echo_on(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.record(MsgStyle::PromptEchoOff, prompt);
+		self.handler.prompt_echo_off(prompt)
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.record(MsgStyle::PromptEchoOn, prompt);
+		self.handler.prompt_echo_on_with_context(prompt, context)
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.record(MsgStyle::PromptEchoOff, prompt);
+		self.handler.prompt_echo_off_with_context(prompt, context)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		self.record(MsgStyle::TextInfo, msg);
+		self.handler.text_info(msg)
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		self.record(MsgStyle::ErrorMsg, msg);
+		self.handler.error_msg(msg)
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.record(MsgStyle::Radio, prompt);
+		self.handler.radio_prompt(prompt)
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.messages
+			.push((MsgStyle::BinaryPrompt, [&[u8::from(type_)], data].concat()));
+		self.handler.binary_prompt(type_, data)
+	}
+
+	fn max_binary_len(&self) -> usize {
+		self.handler.max_binary_len()
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.handler.self_test()
+	}
+}
+
+/// Returns the bit of `style` in the mask of [`StyleFilterConversation`]
+const fn style_bit(style: MsgStyle) -> u8 {
+	match style {
+		MsgStyle::PromptEchoOn => 1,
+		MsgStyle::PromptEchoOff => 1 << 1,
+		MsgStyle::ErrorMsg => 1 << 2,
+		MsgStyle::TextInfo => 1 << 3,
+		MsgStyle::Radio => 1 << 4,
+		MsgStyle::BinaryPrompt => 1 << 5,
+	}
+}
+
+/// Conversation handler wrapper answering only allowed message styles
+///
+/// Passes messages with an allowed [style][`MsgStyle`] to the wrapped
+/// handler. Prompts of other styles fail with [`ErrorCode::CONV_ERR`]
+/// without calling the wrapped handler; info and error messages of other
+/// styles are silently dropped.
+///
+/// All styles are allowed by default. Hardened non-interactive handlers
+/// can restrict what a module can extract, e.g. answer password prompts
+/// but refuse visible prompts to prevent username injection.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{Context, MsgStyle, StyleFilterConversation};
+/// use pam_client::conv_mock::Conversation;
+///
+/// let handler = StyleFilterConversation::with_allowed(
+///     Conversation::with_credentials("user", "pass"),
+///     &[MsgStyle::PromptEchoOff, MsgStyle::ErrorMsg, MsgStyle::TextInfo],
+/// );
+/// # let _ = Context::new("my-service", Some("user"), handler).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StyleFilterConversation<H> {
+	handler: H,
+	allowed: u8,
+}
+
+impl<H> StyleFilterConversation<H> {
+	/// Wraps `handler`, allowing all styles
+	#[must_use]
+	pub const fn new(handler: H) -> Self {
+		Self {
+			handler,
+			allowed: u8::MAX,
+		}
+	}
+
+	/// Wraps `handler`, allowing only the styles in `allowed`
+	#[must_use]
+	pub fn with_allowed(handler: H, allowed: &[MsgStyle]) -> Self {
+		Self {
+			handler,
+			allowed: allowed
+				.iter()
+				.fold(0, |mask, style| mask | style_bit(*style)),
+		}
+	}
+
+	/// Allows messages of `style`
+	pub fn allow(&mut self, style: MsgStyle) {
+		self.allowed |= style_bit(style);
+	}
+
+	/// Refuses messages of `style`
+	pub fn deny(&mut self, style: MsgStyle) {
+		self.allowed &= !style_bit(style);
+	}
+
+	/// Returns whether messages of `style` are passed to the wrapped handler
+	#[must_use]
+	pub const fn is_allowed(&self, style: MsgStyle) -> bool {
+		self.allowed & style_bit(style) != 0
+	}
+
+	/// Returns a reference to the wrapped handler
+	#[must_use]
+	pub const fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the wrapped handler
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Unwraps the wrapped handler
+	#[must_use]
+	pub fn into_inner(self) -> H {
+		self.handler
+	}
+
+	/// Checks if a prompt of `style` may be answered
+	fn check_style(&self, style: MsgStyle) -> Result<(), ErrorCode> {
+		if self.is_allowed(style) {
+			Ok(())
+		} else {
+			Err(ErrorCode::CONV_ERR)
+		}
+	}
+}
+
+impl<H: ConversationHandler> ConversationHandler for StyleFilterConversation<H> {
+	fn init(&mut self, default_user: Option<&str>) {
+		self.handler.init(default_user)
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_style(MsgStyle::PromptEchoOn)?;
+		self.handler.prompt_echo_on(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_style(MsgStyle::PromptEchoOff)?;
+		self.handler.prompt_echo_off(prompt)
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_style(MsgStyle::PromptEchoOn)?;
+		self.handler.prompt_echo_on_with_context(prompt, context)
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_style(MsgStyle::PromptEchoOff)?;
+		self.handler.prompt_echo_off_with_context(prompt, context)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		if self.is_allowed(MsgStyle::TextInfo) {
+			self.handler.text_info(msg)
+		}
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		if self.is_allowed(MsgStyle::ErrorMsg) {
+			self.handler.error_msg(msg)
+		}
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.check_style(MsgStyle::Radio)?;
+		self.handler.radio_prompt(prompt)
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.check_style(MsgStyle::BinaryPrompt)?;
+		self.handler.binary_prompt(type_, data)
+	}
+
+	fn max_binary_len(&self) -> usize {
+		self.handler.max_binary_len()
+	}
+
+	/// Fails if echo-on or echo-off prompts are refused, otherwise passes
+	/// the check on to the wrapped handler.
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.check_style(MsgStyle::PromptEchoOn)?;
+		self.check_style(MsgStyle::PromptEchoOff)?;
+		self.handler.self_test()
+	}
+}
+
+/// Conversation handler trying a primary handler and falling back to another
+///
+/// Passes prompts to the primary handler `A`. If it fails with the fallback
+/// code ([`ErrorCode::CONV_ERR`] by default), the same prompt is passed to
+/// the fallback handler `B` and its result is returned. Other errors are
+/// returned unchanged. Info and error messages are passed to both handlers.
+///
+/// Allows composing handlers for graceful degradation, e.g. answering from
+/// a credentials cache and prompting interactively on a cache miss.
+///
+/// # Re-prompt semantics
+/// The retry happens within a single conversation callback, so the PAM
+/// module only ever receives one response per prompt and never learns that
+/// a second handler was asked. However, the answers to a multi-prompt
+/// exchange (e.g. username and password) may come from different handlers,
+/// and the fallback handler sees a prompt without the messages and prompts
+/// the primary handler answered before. The primary handler is asked again
+/// on each prompt, even after it failed once.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{Context, FallbackConversation};
+/// use pam_client::conv_cli::Conversation as CliConversation;
+/// use pam_client::conv_mock::Conversation as MockConversation;
+///
+/// let handler = FallbackConversation::new(MockConversation::new(), CliConversation::new());
+/// # let _ = Context::new("my-service", None, handler).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FallbackConversation<A, B> {
+	primary: A,
+	fallback: B,
+	code: ErrorCode,
+}
+
+impl<A, B> FallbackConversation<A, B> {
+	/// Wraps `primary`, falling back to `fallback` on `CONV_ERR`
+	#[must_use]
+	pub const fn new(primary: A, fallback: B) -> Self {
+		Self::with_code(primary, fallback, ErrorCode::CONV_ERR)
+	}
+
+	/// Wraps `primary`, falling back to `fallback` on `code`
+	#[must_use]
+	pub const fn with_code(primary: A, fallback: B, code: ErrorCode) -> Self {
+		Self {
+			primary,
+			fallback,
+			code,
+		}
+	}
+
+	/// The error code of the primary handler triggering the fallback
+	#[must_use]
+	pub const fn fallback_code(&self) -> ErrorCode {
+		self.code
+	}
+
+	/// Returns a reference to the primary handler
+	#[must_use]
+	pub const fn primary(&self) -> &A {
+		&self.primary
+	}
+
+	/// Returns a mutable reference to the primary handler
+	pub fn primary_mut(&mut self) -> &mut A {
+		&mut self.primary
+	}
+
+	/// Returns a reference to the fallback handler
+	#[must_use]
+	pub const fn fallback(&self) -> &B {
+		&self.fallback
+	}
+
+	/// Returns a mutable reference to the fallback handler
+	pub fn fallback_mut(&mut self) -> &mut B {
+		&mut self.fallback
+	}
+
+	/// Unwraps the primary and the fallback handler
+	#[must_use]
+	pub fn into_inner(self) -> (A, B) {
+		(self.primary, self.fallback)
+	}
+}
+
+impl<A, B> FallbackConversation<A, B>
+where
+	A: ConversationHandler,
+	B: ConversationHandler,
+{
+	/// Calls `func` on the primary handler and on the fallback handler if
+	/// the primary one failed with the fallback code
+	fn try_both<T>(
+		&mut self,
+		func: impl Fn(&mut dyn ConversationHandler) -> Result<T, ErrorCode>,
+	) -> Result<T, ErrorCode> {
+		match func(&mut self.primary) {
+			Err(code) if code == self.code => {
+				// A cancellation of the primary handler is superseded
+				let _ = crate::ffi::take_user_cancelled();
+				func(&mut self.fallback)
+			}
+			result => result,
+		}
+	}
+}
+
+impl<A, B> ConversationHandler for FallbackConversation<A, B>
+where
+	A: ConversationHandler,
+	B: ConversationHandler,
+{
+	fn init(&mut self, default_user: Option<&str>) {
+		self.primary.init(default_user);
+		self.fallback.init(default_user);
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.try_both(|handler| handler.prompt_echo_on(prompt))
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.try_both(|handler| handler.prompt_echo_off(prompt))
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.try_both(|handler| handler.prompt_echo_on_with_context(prompt, context))
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.try_both(|handler| handler.prompt_echo_off_with_context(prompt, context))
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		self.primary.text_info(msg);
+		self.fallback.text_info(msg);
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		self.primary.error_msg(msg);
+		self.fallback.error_msg(msg);
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.try_both(|handler| handler.radio_prompt(prompt))
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.try_both(|handler| handler.binary_prompt(type_, data))
+	}
+
+	/// The smaller limit of both handlers, as either may get the message
+	fn max_binary_len(&self) -> usize {
+		self.primary
+			.max_binary_len()
+			.min(self.fallback.max_binary_len())
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.try_both(|handler| handler.self_test())
+	}
+}
+
+/// Clock of [`RateLimitedConversation`]
+type Clock = dyn FnMut() -> Instant + Send;
+
+/// Conversation handler wrapper limiting the rate of prompts
+///
+/// Passes all calls to the wrapped handler as long as prompts (of any
+/// kind, including radio and binary prompts) arrive within the configured
+/// rate. Prompts exceeding it fail with [`ErrorCode::CONV_ERR`] without
+/// calling the wrapped handler. Info and error messages are always passed
+/// through.
+///
+/// The rate is enforced with a token bucket: it holds up to `burst`
+/// prompts and is refilled with `per_second` prompts per second. It starts
+/// full, so a module can e.g. ask for username and password in quick
+/// succession with a `burst` of 2.
+///
+/// Unlike [`BoundedConversation`], this protects long-running automated
+/// environments against modules flooding prompts without capping the total.
+/// [`self_test()`][`ConversationHandler::self_test()`] is passed on without
+/// taking tokens.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{Context, RateLimitedConversation};
+/// use pam_client::conv_mock::Conversation;
+///
+/// // At most 2 prompts at once, refilled with one prompt per second
+/// let handler = RateLimitedConversation::new(Conversation::with_credentials("user", "pass"), 1.0, 2);
+/// # let _ = Context::new("my-service", None, handler).unwrap();
+/// ```
+pub struct RateLimitedConversation<H> {
+	handler: H,
+	per_second: f64,
+	burst: f64,
+	tokens: f64,
+	last_refill: Instant,
+	clock: Box<Clock>,
+}
+
+impl<H> RateLimitedConversation<H> {
+	/// Wraps `handler`, allowing `per_second` prompts per second on average
+	/// and up to `burst` prompts at once
+	///
+	/// # Panics
+	/// Panics if `per_second` is negative or not finite.
+	#[must_use]
+	pub fn new(handler: H, per_second: f64, burst: u32) -> Self {
+		assert!(
+			per_second.is_finite() && per_second >= 0.0,
+			"invalid prompt rate"
+		);
+		Self {
+			handler,
+			per_second,
+			burst: f64::from(burst),
+			tokens: f64::from(burst),
+			last_refill: Instant::now(),
+			clock: Box::new(Instant::now),
+		}
+	}
+
+	/// Replaces the clock used to refill the bucket, e.g. with a fake
+	/// clock in tests
+	#[must_use]
+	pub fn with_clock(mut self, mut clock: impl FnMut() -> Instant + Send + 'static) -> Self {
+		self.last_refill = clock();
+		self.clock = Box::new(clock);
+		self
+	}
+
+	/// The number of prompts per second on average
+	#[must_use]
+	pub const fn per_second(&self) -> f64 {
+		self.per_second
+	}
+
+	/// Returns a reference to the wrapped handler
+	#[must_use]
+	pub const fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the wrapped handler
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Unwraps the wrapped handler
+	#[must_use]
+	pub fn into_inner(self) -> H {
+		self.handler
+	}
+
+	/// Refills the bucket and takes a token for a prompt
+	fn check_rate(&mut self) -> Result<(), ErrorCode> {
+		let now = (self.clock)();
+		let elapsed = now.saturating_duration_since(self.last_refill);
+		self.last_refill = now;
+		self.tokens = (self.tokens + elapsed.as_secs_f64() * self.per_second).min(self.burst);
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			Ok(())
+		} else {
+			Err(ErrorCode::CONV_ERR)
+		}
+	}
+}
+
+impl<H: Debug> Debug for RateLimitedConversation<H> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("RateLimitedConversation")
+			.field("handler", &self.handler)
+			.field("per_second", &self.per_second)
+			.field("burst", &self.burst)
+			.field("tokens", &self.tokens)
+			.field("last_refill", &self.last_refill)
+			.field("clock", &"<function>")
+			.finish()
+	}
+}
+
+impl<H: ConversationHandler> ConversationHandler for RateLimitedConversation<H> {
+	fn init(&mut self, default_user: Option<&str>) {
+		self.handler.init(default_user)
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_rate()?;
+		self.handler.prompt_echo_on(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_rate()?;
+		self.handler.prompt_echo_off(prompt)
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_rate()?;
+		self.handler.prompt_echo_on_with_context(prompt, context)
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_rate()?;
+		self.handler.prompt_echo_off_with_context(prompt, context)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		self.handler.text_info(msg)
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		self.handler.error_msg(msg)
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.check_rate()?;
+		self.handler.radio_prompt(prompt)
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.check_rate()?;
+		self.handler.binary_prompt(type_, data)
+	}
+
+	fn max_binary_len(&self) -> usize {
+		self.handler.max_binary_len()
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.handler.self_test()
+	}
+}
+
+/// Receiver of messages in [`LoggingConversation`]
+type MessageHook = dyn FnMut(&CStr) + Send;
+
+/// Conversation handler wrapper routing info and error messages separately
+///
+/// PAM distinguishes informational text (`PAM_TEXT_INFO`) from error
+/// messages (`PAM_ERROR_MSG`), so both channels have their own hook and
+/// can independently be passed on to the wrapped handler or not. This
+/// allows e.g. logging all messages but only showing errors to the user.
+/// Prompts are always passed on unchanged.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{Context, LoggingConversation};
+/// use pam_client::conv_mock::Conversation;
+///
+/// // Show errors to the user, only log informational text
+/// let handler = LoggingConversation::new(Conversation::with_credentials("user", "pass"))
+///     .with_info_hook(|msg| eprintln!("PAM info: {}", msg.to_string_lossy()))
+///     .forward_info(false);
+/// # let _ = Context::new("my-service", None, handler).unwrap();
+/// ```
+pub struct LoggingConversation<H> {
+	handler: H,
+	info_hook: Option<Box<MessageHook>>,
+	error_hook: Option<Box<MessageHook>>,
+	forward_info: bool,
+	forward_errors: bool,
+}
+
+impl<H> LoggingConversation<H> {
+	/// Wraps `handler` without hooks, passing on all messages
+	#[must_use]
+	pub const fn new(handler: H) -> Self {
+		Self {
+			handler,
+			info_hook: None,
+			error_hook: None,
+			forward_info: true,
+			forward_errors: true,
+		}
+	}
+
+	/// Sets the hook receiving info messages
+	#[must_use]
+	pub fn with_info_hook(mut self, hook: impl FnMut(&CStr) + Send + 'static) -> Self {
+		self.info_hook = Some(Box::new(hook));
+		self
+	}
+
+	/// Sets the hook receiving error messages
+	#[must_use]
+	pub fn with_error_hook(mut self, hook: impl FnMut(&CStr) + Send + 'static) -> Self {
+		self.error_hook = Some(Box::new(hook));
+		self
+	}
+
+	/// Sets whether info messages are passed on to the wrapped handler
+	#[must_use]
+	pub fn forward_info(mut self, forward: bool) -> Self {
+		self.forward_info = forward;
+		self
+	}
+
+	/// Sets whether error messages are passed on to the wrapped handler
+	#[must_use]
+	pub fn forward_errors(mut self, forward: bool) -> Self {
+		self.forward_errors = forward;
+		self
+	}
+
+	/// Returns a reference to the wrapped handler
+	#[must_use]
+	pub const fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the wrapped handler
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Unwraps the wrapped handler
+	#[must_use]
+	pub fn into_inner(self) -> H {
+		self.handler
+	}
+}
+
+impl<H: Debug> Debug for LoggingConversation<H> {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("LoggingConversation")
+			.field("handler", &self.handler)
+			.field("info_hook", &self.info_hook.as_ref().map(|_| "<function>"))
+			.field(
+				"error_hook",
+				&self.error_hook.as_ref().map(|_| "<function>"),
+			)
+			.field("forward_info", &self.forward_info)
+			.field("forward_errors", &self.forward_errors)
+			.finish()
+	}
+}
+
+impl<H: ConversationHandler> ConversationHandler for LoggingConversation<H> {
+	fn init(&mut self, default_user: Option<&str>) {
+		self.handler.init(default_user)
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.handler.prompt_echo_on(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.handler.prompt_echo_off(prompt)
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.handler.prompt_echo_on_with_context(prompt, context)
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.handler.prompt_echo_off_with_context(prompt, context)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		if let Some(hook) = &mut self.info_hook {
+			hook(msg);
+		}
+		if self.forward_info {
+			self.handler.text_info(msg)
+		}
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		if let Some(hook) = &mut self.error_hook {
+			hook(msg);
+		}
+		if self.forward_errors {
+			self.handler.error_msg(msg)
+		}
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.handler.radio_prompt(prompt)
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.handler.binary_prompt(type_, data)
+	}
+
+	fn max_binary_len(&self) -> usize {
+		self.handler.max_binary_len()
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.handler.self_test()
+	}
+}
+
+/// Handle to abort the conversation of a [`CancelableConversation`]
+///
+/// Clones share the same state, so the handle can be moved to another
+/// thread, e.g. the one noticing that the client connection dropped,
+/// while the PAM operation is running.
+#[derive(Debug, Clone, Default)]
+pub struct ConversationCanceller(Arc<AtomicBool>);
+
+impl ConversationCanceller {
+	/// Creates a new canceller that isn't tripped
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Trips the canceller, making further callbacks of the conversation
+	/// fail.
+	pub fn cancel(&self) {
+		self.0.store(true, Ordering::SeqCst);
+	}
+
+	/// Returns whether the canceller was tripped.
+	#[must_use]
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(Ordering::SeqCst)
+	}
+
+	/// Resets the canceller, e.g. to reuse the conversation for another
+	/// attempt.
+	pub fn reset(&self) {
+		self.0.store(false, Ordering::SeqCst);
+	}
+}
+
+/// Conversation handler wrapper that can be aborted from another thread
+///
+/// Once the [`ConversationCanceller`] is tripped, all prompts fail with
+/// [`ErrorCode::CONV_ERR`] without calling the wrapped handler and info and
+/// error messages are discarded, so PAM modules abort the running
+/// operation.
+///
+/// PAM has no way to interrupt a module, so the abort only takes effect
+/// at the next callback into the conversation handler. A module blocking
+/// in between, e.g. while waiting for a network service or inside the
+/// wrapped handler itself, isn't interrupted, and an operation that
+/// needs no further callbacks completes normally.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{CancelableConversation, Context, Flag};
+/// use pam_client::conv_mock::Conversation;
+///
+/// let handler = CancelableConversation::new(Conversation::with_credentials("user", "pass"));
+/// let canceller = handler.canceller();
+/// // Move `canceller` to the thread watching the connection...
+/// canceller.cancel();
+/// # let mut context = Context::new("test", Some("user"), handler).unwrap();
+/// # let _ = context.authenticate(Flag::NONE);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelableConversation<H> {
+	handler: H,
+	canceller: ConversationCanceller,
+}
+
+impl<H> CancelableConversation<H> {
+	/// Wraps `handler` with a new canceller
+	#[must_use]
+	pub fn new(handler: H) -> Self {
+		Self::with_canceller(handler, ConversationCanceller::new())
+	}
+
+	/// Wraps `handler` with an existing canceller, e.g. to abort several
+	/// conversations at once
+	#[must_use]
+	pub const fn with_canceller(handler: H, canceller: ConversationCanceller) -> Self {
+		Self { handler, canceller }
+	}
+
+	/// Returns a handle to abort the conversation
+	#[must_use]
+	pub fn canceller(&self) -> ConversationCanceller {
+		self.canceller.clone()
+	}
+
+	/// Returns a reference to the wrapped handler
+	#[must_use]
+	pub const fn handler(&self) -> &H {
+		&self.handler
+	}
+
+	/// Returns a mutable reference to the wrapped handler
+	pub fn handler_mut(&mut self) -> &mut H {
+		&mut self.handler
+	}
+
+	/// Unwraps the wrapped handler
+	#[must_use]
+	pub fn into_inner(self) -> H {
+		self.handler
+	}
+
+	/// Fails if the canceller was tripped
+	fn check_cancelled(&self) -> Result<(), ErrorCode> {
+		if self.canceller.is_cancelled() {
+			Err(ErrorCode::CONV_ERR)
+		} else {
+			Ok(())
+		}
+	}
+}
+
+impl<H: ConversationHandler> ConversationHandler for CancelableConversation<H> {
+	fn init(&mut self, default_user: Option<&str>) {
+		self.handler.init(default_user)
+	}
+
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_cancelled()?;
+		self.handler.prompt_echo_on(prompt)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.check_cancelled()?;
+		self.handler.prompt_echo_off(prompt)
+	}
+
+	fn prompt_echo_on_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_cancelled()?;
+		self.handler.prompt_echo_on_with_context(prompt, context)
+	}
+
+	fn prompt_echo_off_with_context(
+		&mut self,
+		prompt: &CStr,
+		context: PromptContext,
+	) -> Result<CString, ErrorCode> {
+		self.check_cancelled()?;
+		self.handler.prompt_echo_off_with_context(prompt, context)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		if self.check_cancelled().is_ok() {
+			self.handler.text_info(msg)
+		}
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		if self.check_cancelled().is_ok() {
+			self.handler.error_msg(msg)
+		}
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.check_cancelled()?;
+		self.handler.radio_prompt(prompt)
+	}
+
+	fn binary_prompt(
+		&mut self,
+		type_: BinaryType,
+		data: &[u8],
+	) -> Result<(BinaryType, Vec<u8>), ErrorCode> {
+		self.check_cancelled()?;
+		self.handler.binary_prompt(type_, data)
+	}
+
+	fn max_binary_len(&self) -> usize {
+		self.handler.max_binary_len()
+	}
+
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		self.check_cancelled()?;
+		self.handler.self_test()
+	}
+}
+
+/// Internal: Conversation handler asking a one-shot closure for the secret
+///
+/// Answers username prompts with `username` and the first secret prompt by
+/// calling `provider`. Further secret prompts and all other prompts fail
+/// with [`ErrorCode::CONV_ERR`]. Messages are discarded.
+pub(crate) struct ProviderConversation<F> {
+	username: Option<CString>,
+	provider: Option<F>,
+}
+
+impl<F> ProviderConversation<F>
+where
+	F: FnOnce(&CStr) -> Result<CString, ErrorCode>,
+{
+	/// Creates a handler answering secret prompts once with `provider`
+	pub(crate) fn new(username: Option<CString>, provider: F) -> Self {
+		Self {
+			username,
+			provider: Some(provider),
+		}
+	}
+}
+
+impl<F> ConversationHandler for ProviderConversation<F>
+where
+	F: FnOnce(&CStr) -> Result<CString, ErrorCode>,
+{
+	fn prompt_echo_on(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+		self.username.clone().ok_or(ErrorCode::CONV_ERR)
+	}
+
+	fn prompt_echo_off(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+		match self.provider.take() {
+			Some(provider) => provider(msg),
+			None => Err(ErrorCode::CONV_ERR),
+		}
+	}
+
+	fn text_info(&mut self, _msg: &CStr) {}
+
+	fn error_msg(&mut self, _msg: &CStr) {}
+
+	fn radio_prompt(&mut self, _msg: &CStr) -> Result<bool, ErrorCode> {
+		Err(ErrorCode::CONV_ERR)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::conv_mock::Conversation;
+
+	#[test]
+	fn test_recording() {
+		let text = CString::new("test").unwrap();
+		let mut c = RecordingConversation::new(crate::conv_mock::Conversation::with_credentials(
+			"user", "pass",
+		));
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_bytes(), b"user");
+		assert_eq!(c.prompt_echo_off(&text).unwrap().to_bytes(), b"pass");
+		c.text_info(&text);
+		c.error_msg(&text);
+		assert_eq!(c.radio_prompt(&text), Ok(false));
+		assert!(c.binary_prompt(BinaryType::Ok, &[2, 3]).is_err());
+		assert_eq!(
+			c.styles().collect::<Vec<_>>(),
+			vec![
+				MsgStyle::PromptEchoOn,
+				MsgStyle::PromptEchoOff,
+				MsgStyle::TextInfo,
+				MsgStyle::ErrorMsg,
+				MsgStyle::Radio,
+				MsgStyle::BinaryPrompt,
+			]
+		);
+		assert_eq!(c.messages()[0].1, b"test");
+		assert_eq!(c.messages()[5].1, [1, 2, 3]);
+		// Responses are never recorded
+		assert!(!format!("{:?}", c.messages()).contains("pass"));
+		assert_eq!(c.handler().log.len(), 3);
+		c.handler_mut().clear_log();
+		c.clear();
+		assert!(c.messages().is_empty());
+		assert_eq!(c.max_binary_len(), DEFAULT_MAX_BINARY_LEN);
+		let _ = c.into_inner();
+	}
+
+	#[test]
+	fn test_provider() {
+		let text = CString::new("test").unwrap();
+		let mut c = ProviderConversation::new(None, |prompt: &CStr| {
+			assert_eq!(prompt.to_bytes(), b"test");
+			Ok(CString::new("secret").unwrap())
+		});
+		assert_eq!(c.prompt_echo_on(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.prompt_echo_off(&text).unwrap().to_bytes(), b"secret");
+		assert_eq!(c.prompt_echo_off(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.radio_prompt(&text), Err(ErrorCode::CONV_ERR));
+		c.text_info(&text);
+		c.error_msg(&text);
+
+		let mut c =
+			ProviderConversation::new(Some(text.clone()), |_: &CStr| Err(ErrorCode::CONV_AGAIN));
+		assert_eq!(c.prompt_echo_on(&text), Ok(text.clone()));
+		assert_eq!(c.prompt_echo_off(&text), Err(ErrorCode::CONV_AGAIN));
+	}
+
+	#[test]
+	fn test_bounded() {
+		let prompt = CString::new("Password: ").unwrap();
+		let mut c = BoundedConversation::new(Conversation::with_credentials("user", "pass"), 3);
+		assert_eq!(c.max_prompts(), 3);
+		for _ in 0..3 {
+			assert_eq!(c.prompt_echo_off(&prompt).unwrap().to_str(), Ok("pass"));
+		}
+		assert_eq!(c.prompt_echo_on(&prompt), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.radio_prompt(&prompt), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.prompts(), 5);
+
+		// Messages still pass through
+		c.text_info(&prompt);
+		assert_eq!(c.handler().log.len(), 1);
+
+		c.reset();
+		assert_eq!(c.prompt_echo_on(&prompt).unwrap().to_str(), Ok("user"));
+		c.handler_mut().clear_log();
+		assert!(c.clone().into_inner().log.is_empty());
+	}
+
+	#[test]
+	fn test_style_filter() {
+		let prompt = CString::new("Login: ").unwrap();
+		let mut c = StyleFilterConversation::with_allowed(
+			Conversation::with_credentials("user", "pass"),
+			&[MsgStyle::PromptEchoOff, MsgStyle::ErrorMsg],
+		);
+		assert!(c.is_allowed(MsgStyle::PromptEchoOff));
+		assert!(!c.is_allowed(MsgStyle::PromptEchoOn));
+		assert_eq!(c.prompt_echo_on(&prompt), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.prompt_echo_off(&prompt).unwrap().to_str(), Ok("pass"));
+		assert_eq!(c.radio_prompt(&prompt), Err(ErrorCode::CONV_ERR));
+		assert_eq!(
+			c.binary_prompt(BinaryType::Ok, &[]),
+			Err(ErrorCode::CONV_ERR)
+		);
+		c.text_info(&prompt);
+		c.error_msg(&prompt);
+		assert_eq!(c.handler().log.len(), 1);
+
+		c.allow(MsgStyle::PromptEchoOn);
+		assert_eq!(c.prompt_echo_on(&prompt).unwrap().to_str(), Ok("user"));
+		c.deny(MsgStyle::PromptEchoOff);
+		assert_eq!(c.prompt_echo_off(&prompt), Err(ErrorCode::CONV_ERR));
+
+		// Everything is allowed by default
+		let mut c = StyleFilterConversation::new(c.into_inner());
+		c.handler_mut().clear_log();
+		assert_eq!(c.prompt_echo_on(&prompt).unwrap().to_str(), Ok("user"));
+		assert_eq!(c.radio_prompt(&prompt), Ok(false));
+		c.text_info(&prompt);
+		assert_eq!(c.clone().into_inner().log.len(), 1);
+	}
+
+	#[test]
+	fn test_fallback() {
+		let prompt = CString::new("Password: ").unwrap();
+		let mut c = FallbackConversation::new(
+			crate::conv_null::Conversation::new(),
+			Conversation::with_credentials("user", "pass"),
+		);
+		assert_eq!(c.fallback_code(), ErrorCode::CONV_ERR);
+		assert_eq!(c.prompt_echo_off(&prompt).unwrap().to_str(), Ok("pass"));
+		assert_eq!(c.prompt_echo_on(&prompt).unwrap().to_str(), Ok("user"));
+		assert_eq!(c.radio_prompt(&prompt), Ok(false));
+		c.error_msg(&prompt);
+		assert_eq!(c.fallback().log.len(), 1);
+		c.fallback_mut().clear_log();
+
+		// Answers of the primary handler are used as they are
+		let mut c = FallbackConversation::new(
+			Conversation::with_credentials("cached", "secret"),
+			crate::conv_null::Conversation::new(),
+		);
+		assert_eq!(c.prompt_echo_off(&prompt).unwrap().to_str(), Ok("secret"));
+		c.primary_mut().clear_log();
+
+		// Other errors don't trigger the fallback
+		let mut c = FallbackConversation::with_code(
+			crate::conv_null::Conversation::new(),
+			Conversation::with_credentials("user", "pass"),
+			ErrorCode::CONV_AGAIN,
+		);
+		assert_eq!(c.prompt_echo_off(&prompt), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.max_binary_len(), DEFAULT_MAX_BINARY_LEN);
+		let (_, fallback) = c.into_inner();
+		assert!(fallback.log.is_empty());
+	}
+
+	#[test]
+	fn test_rate_limited() {
+		use std::time::Duration;
+
+		let prompt = CString::new("Password: ").unwrap();
+		let now = Arc::new(Mutex::new(Instant::now()));
+		let clock = Arc::clone(&now);
+		let mut c =
+			RateLimitedConversation::new(Conversation::with_credentials("user", "pass"), 2.0, 2)
+				.with_clock(move || *clock.lock().unwrap());
+		assert!((c.per_second() - 2.0).abs() < f64::EPSILON);
+
+		// The bucket starts full
+		assert_eq!(c.prompt_echo_on(&prompt).unwrap().to_str(), Ok("user"));
+		assert_eq!(c.prompt_echo_off(&prompt).unwrap().to_str(), Ok("pass"));
+		assert_eq!(c.prompt_echo_off(&prompt), Err(ErrorCode::CONV_ERR));
+		c.text_info(&prompt);
+		assert_eq!(c.handler().log.len(), 1);
+
+		// Refilled with two prompts per second
+		*now.lock().unwrap() += Duration::from_millis(500);
+		assert!(c.prompt_echo_off(&prompt).is_ok());
+		assert_eq!(c.radio_prompt(&prompt), Err(ErrorCode::CONV_ERR));
+
+		// Never more than `burst` at once
+		*now.lock().unwrap() += Duration::from_secs(60);
+		assert!(c.prompt_echo_off(&prompt).is_ok());
+		assert!(c.prompt_echo_off(&prompt).is_ok());
+		assert_eq!(c.prompt_echo_off(&prompt), Err(ErrorCode::CONV_ERR));
+
+		assert!(format!("{:?}", c).contains("<function>"));
+		c.handler_mut().clear_log();
+		assert!(c.into_inner().log.is_empty());
+	}
+
+	#[test]
+	fn test_self_test() {
+		// Default implementation
+		assert_eq!(
+			crate::conv_null::Conversation::new().self_test(),
+			Err(ErrorCode::CONV_ERR)
+		);
+		let mut c = StyleFilterConversation::new(Conversation::with_credentials("user", "pass"));
+		assert_eq!(c.self_test(), Ok(()));
+		assert!(c.handler().responses().is_empty());
+		c.deny(MsgStyle::PromptEchoOff);
+		assert_eq!(c.self_test(), Err(ErrorCode::CONV_ERR));
+		let mut c = FallbackConversation::new(
+			crate::conv_null::Conversation::new(),
+			Conversation::with_credentials("user", "pass"),
+		);
+		assert_eq!(c.self_test(), Ok(()));
+		assert!(c.fallback().responses().is_empty());
+
+		// Forwarded without counting
+		let mut c = BoundedConversation::new(Conversation::with_credentials("user", "pass"), 0);
+		assert_eq!(c.self_test(), Ok(()));
+		assert_eq!(c.prompts(), 0);
+		let mut c: Box<dyn ConversationHandler> = Box::new(Conversation::new());
+		assert_eq!(c.self_test(), Err(ErrorCode::CONV_ERR));
+		let mut c = Arc::new(Mutex::new(RecordingConversation::new(
+			Conversation::with_credentials("user", "pass"),
+		)));
+		assert_eq!(c.self_test(), Ok(()));
+		assert!(c.lock().unwrap().messages().is_empty());
+	}
+
+	#[test]
+	fn test_logging() {
+		let info = CString::new("info").unwrap();
+		let error = CString::new("error").unwrap();
+		let infos = Arc::new(Mutex::new(Vec::new()));
+		let errors = Arc::new(Mutex::new(Vec::new()));
+		let (info_log, error_log) = (Arc::clone(&infos), Arc::clone(&errors));
+		let mut c = LoggingConversation::new(Conversation::with_credentials("user", "pass"))
+			.with_info_hook(move |msg| info_log.lock().unwrap().push(msg.to_owned()))
+			.with_error_hook(move |msg| error_log.lock().unwrap().push(msg.to_owned()))
+			.forward_info(false);
+		c.text_info(&info);
+		c.error_msg(&error);
+		assert_eq!(c.prompt_echo_off(&info).unwrap().to_str(), Ok("pass"));
+		assert_eq!(*infos.lock().unwrap(), vec![info.clone()]);
+		assert_eq!(*errors.lock().unwrap(), vec![error.clone()]);
+		assert_eq!(c.handler().infos().count(), 0);
+		assert_eq!(c.handler().errors().collect::<Vec<_>>(), vec![&error]);
+		assert!(format!("{:?}", c).contains("<function>"));
+
+		let mut c = c.forward_info(true).forward_errors(false);
+		c.text_info(&info);
+		c.error_msg(&error);
+		assert_eq!(infos.lock().unwrap().len(), 2);
+		assert_eq!(errors.lock().unwrap().len(), 2);
+		c.handler_mut().clear_log();
+		assert!(c.into_inner().log.is_empty());
+	}
+
+	#[test]
+	fn test_cancelable() {
+		let text = CString::new("test").unwrap();
+		let mut c = CancelableConversation::new(Conversation::with_credentials("user", "pass"));
+		let canceller = c.canceller();
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_str(), Ok("user"));
+		c.text_info(&text);
+		assert!(c.self_test().is_ok());
+
+		std::thread::spawn(move || canceller.cancel())
+			.join()
+			.unwrap();
+		assert!(c.canceller().is_cancelled());
+		assert_eq!(c.prompt_echo_off(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(c.radio_prompt(&text), Err(ErrorCode::CONV_ERR));
+		assert_eq!(
+			c.binary_prompt(BinaryType::Ok, b""),
+			Err(ErrorCode::CONV_ERR)
+		);
+		assert_eq!(c.self_test(), Err(ErrorCode::CONV_ERR));
+		c.text_info(&text);
+		c.error_msg(&text);
+		assert_eq!(c.handler().log.len(), 1);
+
+		c.canceller().reset();
+		assert_eq!(c.prompt_echo_off(&text).unwrap().to_str(), Ok("pass"));
+		let shared = ConversationCanceller::new();
+		let c = CancelableConversation::with_canceller(c.into_inner(), shared.clone());
+		shared.cancel();
+		assert!(c.canceller().is_cancelled());
+	}
+
+	#[test]
+	fn test_binary_type() {
+		use std::collections::hash_map::DefaultHasher;
+
+		let hash = |value: BinaryType| {
+			let mut hasher = DefaultHasher::new();
+			value.hash(&mut hasher);
+			hasher.finish()
+		};
+		for byte in 0..=u8::MAX {
+			assert_eq!(u8::from(BinaryType::from(byte)), byte);
+			assert_eq!(BinaryType::Other(byte), BinaryType::from(byte));
+			assert_eq!(hash(BinaryType::Other(byte)), hash(BinaryType::from(byte)));
+		}
+		assert_ne!(BinaryType::Ok, BinaryType::Done);
+		assert_ne!(BinaryType::Other(0x42), BinaryType::Fail);
+	}
+
+	#[cfg(feature = "serde")]
+	#[test]
+	fn test_binary_type_serde() {
+		use serde::de::value::{Error, U8Deserializer};
+		use serde::de::IntoDeserializer;
+		use serde::Deserialize;
+
+		let deserializer: U8Deserializer<Error> = 3_u8.into_deserializer();
+		assert_eq!(BinaryType::deserialize(deserializer), Ok(BinaryType::Done));
+		let deserializer: U8Deserializer<Error> = 0x42_u8.into_deserializer();
+		assert_eq!(
+			BinaryType::deserialize(deserializer),
+			Ok(BinaryType::Other(0x42))
+		);
+	}
+}
@@ -0,0 +1,150 @@
+//! Character encoding of conversation texts
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::error::ErrorCode;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+
+/// Legacy character set for [`EncodingPolicy::Transcode`]
+///
+/// Only Latin-1 is supported at the moment.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Encoding {
+	/// ISO-8859-1, mapping each byte to the Unicode code point of the same
+	/// value
+	Latin1,
+}
+
+/// Handling of texts that aren't valid UTF-8 in conversations
+///
+/// PAM passes texts as plain byte strings in the encoding of the system,
+/// while the conversation handlers of this crate assume UTF-8. The policy
+/// of a [`Context`][`crate::Context`] is applied to the texts of all
+/// messages before they are passed to the conversation handler and to the
+/// responses of the handler before they are passed to the module. See
+/// [`Context::set_encoding_policy()`][`crate::Context::set_encoding_policy()`].
+///
+/// Binary messages are never converted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EncodingPolicy {
+	/// Fails the conversation with [`ErrorCode::CONV_ERR`] if a message
+	/// or response isn't valid UTF-8.
+	Strict,
+	/// Passes messages and responses on unchanged.
+	///
+	/// This is the default and the behaviour without any policy. Handlers
+	/// receive the original bytes and replace invalid UTF-8 sequences with
+	/// `U+FFFD REPLACEMENT CHARACTER` when they convert a text to `str`,
+	/// e.g. with `CStr::to_string_lossy()`.
+	Lossy,
+	/// Converts messages from the given character set to UTF-8 and
+	/// responses back.
+	///
+	/// Latin-1 ([`Encoding::Latin1`]) is the only supported legacy
+	/// character set.
+	///
+	/// Responses that aren't valid UTF-8 or contain characters not
+	/// representable in the character set fail with
+	/// [`ErrorCode::CONV_ERR`].
+	Transcode(Encoding),
+}
+
+impl Default for EncodingPolicy {
+	fn default() -> Self {
+		Self::Lossy
+	}
+}
+
+impl EncodingPolicy {
+	/// Converts the text of a message for the conversation handler.
+	pub(crate) fn decode(self, text: &CStr) -> Result<Cow<'_, CStr>, ErrorCode> {
+		let bytes = text.to_bytes();
+		match self {
+			Self::Strict => match std::str::from_utf8(bytes) {
+				Ok(_) => Ok(Cow::Borrowed(text)),
+				Err(_) => Err(ErrorCode::CONV_ERR),
+			},
+			Self::Lossy => Ok(Cow::Borrowed(text)),
+			Self::Transcode(Encoding::Latin1) => {
+				if bytes.is_ascii() {
+					return Ok(Cow::Borrowed(text));
+				}
+				let string: String = bytes.iter().map(|&b| char::from(b)).collect();
+				to_cstring(string.into_bytes()).map(Cow::Owned)
+			}
+		}
+	}
+
+	/// Converts a response of the conversation handler for the module.
+	pub(crate) fn encode(self, response: CString) -> Result<CString, ErrorCode> {
+		match self {
+			Self::Lossy => Ok(response),
+			Self::Strict => match response.to_str() {
+				Ok(_) => Ok(response),
+				Err(_) => Err(ErrorCode::CONV_ERR),
+			},
+			Self::Transcode(Encoding::Latin1) => {
+				let text = response.to_str().map_err(|_| ErrorCode::CONV_ERR)?;
+				if text.is_ascii() {
+					return Ok(response);
+				}
+				let bytes = text
+					.chars()
+					.map(|c| u8::try_from(u32::from(c)).map_err(|_| ErrorCode::CONV_ERR))
+					.collect::<Result<Vec<u8>, _>>()?;
+				to_cstring(bytes)
+			}
+		}
+	}
+}
+
+/// Builds a `CString` from converted text without NUL bytes.
+fn to_cstring(bytes: Vec<u8>) -> Result<CString, ErrorCode> {
+	Ok(CString::new(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_policies() {
+		let valid = CString::new("Passwört: ").unwrap();
+		let invalid = CString::new(b"Passw\xF6rt: ".to_vec()).unwrap();
+
+		let strict = EncodingPolicy::Strict;
+		assert_eq!(strict.decode(&valid).unwrap().as_ref(), valid.as_ref());
+		assert_eq!(strict.decode(&invalid), Err(ErrorCode::CONV_ERR));
+		assert_eq!(strict.encode(valid.clone()), Ok(valid.clone()));
+		assert_eq!(strict.encode(invalid.clone()), Err(ErrorCode::CONV_ERR));
+
+		let lossy = EncodingPolicy::default();
+		assert_eq!(lossy, EncodingPolicy::Lossy);
+		assert!(matches!(lossy.decode(&valid), Ok(Cow::Borrowed(_))));
+		assert!(matches!(lossy.decode(&invalid), Ok(Cow::Borrowed(_))));
+		assert_eq!(lossy.decode(&invalid).unwrap().as_ref(), invalid.as_ref());
+		assert_eq!(lossy.encode(invalid.clone()), Ok(invalid.clone()));
+
+		let latin1 = EncodingPolicy::Transcode(Encoding::Latin1);
+		assert_eq!(latin1.decode(&invalid).unwrap().as_ref(), valid.as_ref());
+		assert_eq!(latin1.encode(valid), Ok(invalid.clone()));
+		assert_eq!(latin1.encode(invalid), Err(ErrorCode::CONV_ERR));
+		assert_eq!(
+			latin1.encode(CString::new("€").unwrap()),
+			Err(ErrorCode::CONV_ERR)
+		);
+	}
+}
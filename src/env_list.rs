@@ -16,7 +16,7 @@ use std::ffi::{CStr, CString, OsStr, OsString};
 use std::iter::FusedIterator;
 use std::ops::Index;
 use std::os::unix::ffi::OsStrExt;
-use std::{fmt, slice};
+use std::{fmt, mem, slice};
 
 /// Item in a PAM environment list.
 ///
@@ -46,7 +46,7 @@ impl EnvItem {
 			.unwrap_or(element.len());
 		(
 			OsStr::from_bytes(&element[..sep]),
-			OsStr::from_bytes(&element[sep + 1..]),
+			OsStr::from_bytes(element.get(sep + 1..).unwrap_or_default()),
 		)
 	}
 }
@@ -182,15 +182,28 @@ impl EnvList {
 	/// Creates an `EnvList` from a pointer as returned by
 	/// `pam_getenvlist()`.
 	///
+	/// `pam_getenvlist()` returns null on errors, which results in an
+	/// empty list.
+	///
 	/// # Panics
-	/// Panics if `data` is null.
+	/// Panics if `data` is null and memory allocation fails.
 	#[must_use]
 	pub(crate) unsafe fn new(data: *mut *mut c_char) -> Self {
-		assert!(!data.is_null());
+		if data.is_null() {
+			return Self::empty();
+		}
 		let len = count_items(data as *const *const c_char);
 		Self(CBox::from_raw_slice(data.cast(), len))
 	}
 
+	/// Internal: Creates an empty `EnvList`.
+	fn empty() -> Self {
+		// Allocate the null terminator only, so the list stays a valid
+		// C array.
+		let data = unsafe { libc::calloc(1, mem::size_of::<*mut c_char>()) };
+		Self(unsafe { CBox::from_raw_slice(data.cast(), 0) })
+	}
+
 	/// Returns a reference to the value of the named environment variable.
 	///
 	/// Returns `None` if the variable doesn't exist in this list.
@@ -236,6 +249,76 @@ impl EnvList {
 	pub fn iter_tuples(&self) -> TupleIter {
 		TupleIter(self.0.iter())
 	}
+
+	/// Returns all variables as owned `"KEY=VALUE"` strings.
+	///
+	/// Suitable for building a `char *const envp[]` array for calling
+	/// `execve()` directly. Variables without a value are rendered as
+	/// `"KEY="`.
+	///
+	/// ```no_run
+	/// # use pam_client::Context;
+	/// # let mut context = Context::new("dummy", None, pam_client::conv_null::Conversation::new()).unwrap();
+	/// let env = context.envlist().as_execve_env();
+	/// let mut envp: Vec<*const libc::c_char> = env.iter().map(|s| s.as_ptr()).collect();
+	/// envp.push(std::ptr::null());
+	/// ```
+	#[must_use]
+	pub fn as_execve_env(&self) -> Vec<CString> {
+		self.iter_tuples()
+			.map(|(key, value)| {
+				let mut entry = Vec::with_capacity(key.len() + value.len() + 1);
+				entry.extend_from_slice(key.as_bytes());
+				entry.push(b'=');
+				entry.extend_from_slice(value.as_bytes());
+				CString::new(entry).expect("environment items never contain null bytes")
+			})
+			.collect()
+	}
+
+	/// Returns the environment of the current process overlaid with the
+	/// variables in this list.
+	///
+	/// Implements the common "inherit and let PAM override" policy for
+	/// spawning child processes: on conflicts the value from the PAM
+	/// environment wins. The process variables keep their order, variables
+	/// only set by PAM are appended.
+	///
+	/// ```no_run
+	/// # use pam_client::Context;
+	/// # let mut context = Context::new("dummy", None, pam_client::conv_null::Conversation::new()).unwrap();
+	/// let env = context.envlist().merged_with_current();
+	/// std::process::Command::new("/usr/bin/some_program")
+	///     .env_clear()
+	///     .envs(env)
+	///     .status();
+	/// ```
+	#[must_use]
+	pub fn merged_with_current(&self) -> Vec<(OsString, OsString)> {
+		self.merged_with(std::env::vars_os())
+	}
+
+	/// Internal: Overlays the variables in this list on `base`.
+	fn merged_with(
+		&self,
+		base: impl IntoIterator<Item = (OsString, OsString)>,
+	) -> Vec<(OsString, OsString)> {
+		let mut result: Vec<(OsString, OsString)> = base.into_iter().collect();
+		let mut positions: HashMap<OsString, usize> = result
+			.iter()
+			.enumerate()
+			.map(|(i, (key, _))| (key.clone(), i))
+			.collect();
+		for (key, value) in self.iter_tuples() {
+			if let Some(&i) = positions.get(key) {
+				result[i].1 = value.to_owned();
+			} else {
+				positions.insert(key.to_owned(), result.len());
+				result.push((key.to_owned(), value.to_owned()));
+			}
+		}
+		result
+	}
 }
 
 /// Display and string conversion of the environment list.
@@ -382,3 +465,45 @@ impl<'a> Iterator for TupleIter<'a> {
 
 impl FusedIterator for TupleIter<'_> {}
 impl ExactSizeIterator for TupleIter<'_> {}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::ptr;
+
+	#[test]
+	fn test_null() {
+		let list = unsafe { EnvList::new(ptr::null_mut()) };
+		assert!(list.is_empty());
+		assert_eq!(list.iter().count(), 0);
+		assert_eq!(list.get("PATH"), None);
+		assert!(format!("{:?}", list).contains("EnvList"));
+	}
+
+	#[test]
+	fn test_merge() {
+		let items = ["HOME=/home/user", "PAM=1"];
+		let data = CBox::<*mut c_char>::new_zeroed_slice(items.len() + 1);
+		let data = CBox::into_raw_unsized(data).cast::<*mut c_char>();
+		for (i, item) in items.iter().enumerate() {
+			let item = CString::new(*item).unwrap();
+			unsafe { *data.add(i) = libc::strdup(item.as_ptr()) };
+		}
+		let list = unsafe { EnvList::new(data) };
+		let base = vec![
+			(OsString::from("PATH"), OsString::from("/bin")),
+			(OsString::from("HOME"), OsString::from("/root")),
+		];
+		let empty = unsafe { EnvList::new(ptr::null_mut()) };
+		assert_eq!(empty.merged_with(base.clone()), base);
+		assert!(list.merged_with_current().len() >= list.len());
+		assert_eq!(
+			list.merged_with(base),
+			vec![
+				(OsString::from("PATH"), OsString::from("/bin")),
+				(OsString::from("HOME"), OsString::from("/home/user")),
+				(OsString::from("PAM"), OsString::from("1")),
+			]
+		);
+	}
+}
@@ -10,17 +10,97 @@
 
 use crate::char_ptr_to_str;
 use crate::context::PamHandle;
+use crate::Context;
 #[doc(no_inline)]
 pub use crate::ErrorCode;
 use pam_sys::pam_strerror;
 
 use std::any::type_name;
+use std::borrow::Cow;
 use std::cmp::{Eq, PartialEq};
 use std::error;
+use std::ffi::NulError;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use std::hash::{Hash, Hasher};
 use std::io;
 use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Arc, Once, PoisonError, RwLock};
+
+/// Type of the functions installed with [`set_message_resolver()`]
+type MessageResolver = dyn Fn(ErrorCode) -> Option<String> + Send + Sync;
+
+/// Slot holding the currently installed message resolver
+type ResolverSlot = RwLock<Option<Arc<MessageResolver>>>;
+
+/// Returns the global message resolver slot, creating it on first use.
+fn message_resolver() -> &'static ResolverSlot {
+	static INIT: Once = Once::new();
+	static SLOT: AtomicPtr<ResolverSlot> = AtomicPtr::new(ptr::null_mut());
+	INIT.call_once(|| {
+		let slot = Box::new(RwLock::new(None));
+		SLOT.store(Box::into_raw(slot), Ordering::Release);
+	});
+	// Safety: The slot was created above and is never freed
+	unsafe { &*SLOT.load(Ordering::Acquire) }
+}
+
+/// Installs a global function providing custom error messages.
+///
+/// The messages of errors returned by PAM are usually looked up with
+/// `pam_strerror()`, which returns the English (or sometimes localized)
+/// text of the PAM library. When a resolver is installed, it is asked
+/// first and the `pam_strerror()` text is only used if it returns `None`.
+/// This allows branded or localized messages without post-processing
+/// every error.
+///
+/// # Thread safety
+/// The resolver is global for all threads and contexts and may be called
+/// concurrently from multiple threads, hence the `Send + Sync` bounds.
+/// Installing and clearing is thread-safe, but errors created concurrently
+/// may still use the previous resolver. A replaced resolver is dropped as
+/// soon as no thread uses it anymore.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{set_message_resolver, ErrorCode};
+///
+/// set_message_resolver(|code| match code {
+///     ErrorCode::AUTH_ERR => Some("Anmeldung fehlgeschlagen".to_string()),
+///     _ => None,
+/// });
+/// ```
+pub fn set_message_resolver<F>(resolver: F)
+where
+	F: Fn(ErrorCode) -> Option<String> + Send + Sync + 'static,
+{
+	replace_message_resolver(message_resolver(), Some(Arc::new(resolver)));
+}
+
+/// Removes the resolver installed with [`set_message_resolver()`].
+///
+/// Afterwards all messages are looked up with `pam_strerror()` again.
+pub fn clear_message_resolver() {
+	replace_message_resolver(message_resolver(), None);
+}
+
+/// Installs `resolver` in `slot` and drops the previous one outside of the lock.
+fn replace_message_resolver(slot: &ResolverSlot, resolver: Option<Arc<MessageResolver>>) {
+	let previous = {
+		let mut slot = slot.write().unwrap_or_else(PoisonError::into_inner);
+		std::mem::replace(&mut *slot, resolver)
+	};
+	drop(previous);
+}
+
+/// Looks up a custom message with the resolver in `slot`, if any.
+fn resolve_message(slot: &ResolverSlot, code: ErrorCode) -> Option<String> {
+	// The lock isn't held while calling the resolver, so it may replace
+	// itself without deadlocking.
+	let resolver = slot.read().unwrap_or_else(PoisonError::into_inner).clone();
+	resolver.and_then(|resolver| resolver(code))
+}
 
 /// The error payload type for errors that never have payloads.
 ///
@@ -81,6 +161,16 @@ impl<T> Debug for DisplayHelper<T> {
 ///
 /// Errors originate from the PAM library, PAM modules or helper structs
 /// in this crate. Currently no custom instances are supported.
+///
+/// # `no_std` environments
+///
+/// This type always carries the message returned by `pam_strerror()` as a
+/// `String`, so it requires an allocator. Splitting off an allocation-free
+/// core isn't worthwhile, as the rest of this crate requires `std` anyway
+/// (it links to the system PAM library and uses `std::ffi` types). Code
+/// that only needs the code can use [`ErrorCode`], which is `Copy` and
+/// doesn't allocate, and convert raw return values with its
+/// `TryFrom<c_int>` implementation.
 #[must_use]
 #[derive(Clone)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -88,6 +178,8 @@ pub struct ErrorWith<T> {
 	code: ErrorCode,
 	msg: String,
 	payload: Option<T>,
+	#[cfg_attr(feature = "serde", serde(default))]
+	user_cancelled: bool,
 }
 
 impl<T> ErrorWith<T> {
@@ -100,12 +192,26 @@ impl<T> ErrorWith<T> {
 		code: ErrorCode,
 		payload: Option<T>,
 	) -> ErrorWith<T> {
+		Self::with_resolver(message_resolver(), handle, code, payload)
+	}
+
+	/// Creates a new [`Error`] using the message resolver in `slot`.
+	fn with_resolver(
+		slot: &ResolverSlot,
+		handle: PamHandle,
+		code: ErrorCode,
+		payload: Option<T>,
+	) -> ErrorWith<T> {
+		let msg = resolve_message(slot, code).unwrap_or_else(|| {
+			char_ptr_to_str(unsafe { pam_strerror(handle.into(), code.repr()) })
+				.unwrap_or("")
+				.into()
+		});
 		Self {
 			code,
-			msg: char_ptr_to_str(unsafe { pam_strerror(handle.into(), code.repr()) })
-				.unwrap_or("")
-				.into(),
+			msg,
 			payload,
+			user_cancelled: false,
 		}
 	}
 
@@ -114,6 +220,55 @@ impl<T> ErrorWith<T> {
 		self.code
 	}
 
+	/// The raw value of the error code, as returned by the PAM library.
+	///
+	/// For interoperability with other code using `pam_sys` return codes.
+	#[must_use]
+	pub const fn code_raw(&self) -> i32 {
+		self.code as i32
+	}
+
+	/// Returns whether the authentication backend was unreachable.
+	///
+	/// True for `AUTHINFO_UNAVAIL`, which modules return when they can't
+	/// retrieve authentication information, e.g. because an LDAP server or
+	/// the network is down. This is a transient infrastructure failure, not
+	/// a wrong password, and shouldn't be counted as a failed login attempt.
+	/// Accordingly it maps to [`io::ErrorKind::Other`] instead of
+	/// [`io::ErrorKind::PermissionDenied`].
+	#[must_use]
+	pub fn is_backend_unavailable(&self) -> bool {
+		self.code == ErrorCode::AUTHINFO_UNAVAIL
+	}
+
+	/// Returns whether no module of the stack had an opinion.
+	///
+	/// True for `IGNORE`, which only reaches the application with some PAM
+	/// implementations and configurations; see [`ErrorCode::IGNORE`].
+	/// It is not a denial by a module, but must not be mistaken for
+	/// success either.
+	#[must_use]
+	pub fn is_ignored(&self) -> bool {
+		self.code == ErrorCode::IGNORE
+	}
+
+	/// Returns whether the user cancelled the conversation.
+	///
+	/// True if the conversation handler signalled a cancellation with
+	/// [`user_cancelled()`][`crate::user_cancelled()`] during the failed
+	/// operation. The code is whatever the module made of the
+	/// conversation failure, usually `CONV_ERR` or `AUTH_ERR`.
+	#[must_use]
+	pub const fn is_user_cancelled(&self) -> bool {
+		self.user_cancelled
+	}
+
+	/// Marks the error as caused by a cancellation of the user.
+	pub(crate) fn into_user_cancelled(mut self) -> Self {
+		self.user_cancelled = true;
+		self
+	}
+
 	/// Text representation of the error code, if available.
 	pub fn message(&self) -> Option<&str> {
 		if self.msg.is_empty() {
@@ -123,6 +278,54 @@ impl<T> ErrorWith<T> {
 		}
 	}
 
+	/// Text representation of the error code, looked up on demand.
+	///
+	/// Returns the [`message()`][`Self::message()`] if present. Otherwise
+	/// the message is looked up now like it would have been on creation of
+	/// the error, e.g. for errors of a context with
+	/// [`set_populate_messages(false)`][`crate::Context::set_populate_messages()`].
+	pub fn message_with<C>(&self, context: &Context<C>) -> Cow<'_, str> {
+		if self.msg.is_empty() {
+			Cow::Owned(Error::new(context.handle(), self.code).msg)
+		} else {
+			Cow::Borrowed(&self.msg)
+		}
+	}
+
+	/// Guesses the name of the PAM module that caused the error.
+	///
+	/// Looks for a module name like `pam_unix` in the message, as e.g.
+	/// logged by Linux-PAM modules in the form `pam_unix(login:auth): ...`.
+	///
+	/// This is a best-effort heuristic for log triage, not a guarantee: PAM
+	/// doesn't report which module of a stack failed and the messages from
+	/// `pam_strerror()` usually don't contain module names at all.
+	///
+	/// ```rust
+	/// # use pam_client::{Error, ErrorCode};
+	/// let error = Error::from(ErrorCode::AUTH_ERR);
+	/// assert_eq!(error.module_hint(), None);
+	/// ```
+	#[must_use]
+	pub fn module_hint(&self) -> Option<&str> {
+		let msg = self.msg.as_str();
+		let mut offset = 0;
+		while let Some(pos) = msg[offset..].find("pam_") {
+			let start = offset + pos;
+			let len = msg[start..]
+				.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+				.unwrap_or(msg.len() - start);
+			// Require a word boundary before and a name after the prefix
+			let at_boundary =
+				!msg[..start].ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+			if at_boundary && len > "pam_".len() {
+				return Some(&msg[start..start + len]);
+			}
+			offset = start + len.max(1);
+		}
+		None
+	}
+
 	/// Returns a reference to an optional payload.
 	#[rustversion::attr(since(1.48), const)]
 	pub fn payload(&self) -> Option<&T> {
@@ -134,6 +337,14 @@ impl<T> ErrorWith<T> {
 	/// If a payload exists in this error, it will be moved into the returned
 	/// [`Option`]. All further calls to [`payload()`][`Self::payload()`] and
 	/// [`take_payload()`][`Self::take_payload()`] will return [`None`].
+	///
+	/// ```rust
+	/// # use pam_client::{Error, ErrorCode};
+	/// let mut error = Error::from(ErrorCode::ABORT).into_with_payload("context");
+	/// assert_eq!(error.take_payload(), Some("context"));
+	/// assert_eq!(error.take_payload(), None);
+	/// assert_eq!(error.code(), ErrorCode::ABORT);
+	/// ```
 	pub fn take_payload(&mut self) -> Option<T> {
 		match self.payload {
 			Some(_) => self.payload.take(),
@@ -141,12 +352,62 @@ impl<T> ErrorWith<T> {
 		}
 	}
 
+	/// Puts a payload into the error message, returning the previous one.
+	///
+	/// Allows handing ownership back into an error after inspecting or
+	/// repairing the payload taken with
+	/// [`take_payload()`][`Self::take_payload()`].
+	///
+	/// ```rust
+	/// # use pam_client::{Error, ErrorCode};
+	/// let mut error = Error::from(ErrorCode::ABORT).into_with_payload(1);
+	/// assert_eq!(error.replace_payload(2), Some(1));
+	/// assert_eq!(error.payload(), Some(&2));
+	/// ```
+	pub fn replace_payload(&mut self, payload: T) -> Option<T> {
+		self.payload.replace(payload)
+	}
+
+	/// Creates a copy of the error with a payload derived from a reference
+	/// to the payload of this one.
+	///
+	/// Unlike [`map()`][`Self::map()`] this leaves `self` untouched, e.g.
+	/// to report a description of a payload that can't be cloned while
+	/// keeping the original error for recovery. Without a payload, `func`
+	/// isn't called and the copy has no payload either.
+	///
+	/// ```rust
+	/// # use pam_client::{Error, ErrorCode};
+	/// let error = Error::from(ErrorCode::ABORT).into_with_payload(vec![1, 2, 3]);
+	/// let summary = error.map_payload_ref(Vec::len);
+	/// assert_eq!(summary.payload(), Some(&3));
+	/// assert_eq!(error.payload(), Some(&vec![1, 2, 3]));
+	/// ```
+	pub fn map_payload_ref<U>(&self, func: impl FnOnce(&T) -> U) -> ErrorWith<U> {
+		ErrorWith::<U> {
+			code: self.code,
+			msg: self.msg.clone(),
+			payload: self.payload.as_ref().map(func),
+			user_cancelled: self.user_cancelled,
+		}
+	}
+
 	/// Maps the error payload to another type
+	///
+	/// `func` is only called if the error still has a payload, i.e. it
+	/// wasn't taken with [`take_payload()`][`Self::take_payload()`] before.
+	///
+	/// ```rust
+	/// # use pam_client::{Error, ErrorCode};
+	/// let error = Error::from(ErrorCode::ABORT).into_with_payload(21);
+	/// assert_eq!(error.map(|x| x * 2).payload(), Some(&42));
+	/// ```
 	pub fn map<U>(self, func: impl FnOnce(T) -> U) -> ErrorWith<U> {
 		ErrorWith::<U> {
 			code: self.code,
 			msg: self.msg,
 			payload: self.payload.map(func),
+			user_cancelled: self.user_cancelled,
 		}
 	}
 
@@ -157,6 +418,7 @@ impl<T> ErrorWith<T> {
 			code: self.code,
 			msg: self.msg,
 			payload: None,
+			user_cancelled: self.user_cancelled,
 		}
 	}
 }
@@ -169,12 +431,14 @@ impl<T> Debug for ErrorWith<T> {
 			f.debug_struct("pam_client::Error")
 				.field("code", &self.code)
 				.field("msg", &self.msg)
+				.field("user_cancelled", &self.user_cancelled)
 				.finish()
 		} else {
 			f.debug_struct("pam_client::ErrorWith")
 				.field("code", &self.code)
 				.field("msg", &self.msg)
 				.field("payload", &DisplayHelper::new(&self.payload))
+				.field("user_cancelled", &self.user_cancelled)
 				.finish()
 		}
 	}
@@ -191,6 +455,18 @@ impl Error {
 		Self::with_payload(handle, code, None)
 	}
 
+	/// Creates a new [`Error`] with a custom message.
+	///
+	/// Used for errors detected by this crate instead of the PAM library.
+	pub(crate) fn with_message(code: ErrorCode, msg: impl Into<String>) -> Error {
+		Error {
+			code,
+			msg: msg.into(),
+			payload: None,
+			user_cancelled: false,
+		}
+	}
+
 	/// Adds the payload to the error message and returns a corresponding
 	/// [`ErrorWith<T>`] instance.
 	pub fn into_with_payload<T>(self, payload: T) -> ErrorWith<T> {
@@ -198,6 +474,34 @@ impl Error {
 			code: self.code,
 			msg: self.msg,
 			payload: Some(payload),
+			user_cancelled: self.user_cancelled,
+		}
+	}
+
+	/// Recovers a PAM error wrapped in an [`io::Error`].
+	///
+	/// Performs the reverse of the `From<Error>` implementation of
+	/// [`io::Error`], so code and message aren't lost in code using
+	/// `io::Error` throughout.
+	///
+	/// Returns `None` if `err` doesn't wrap an [`Error`]. This includes
+	/// errors converted from an [`ErrorWith<T>`] with a payload type other
+	/// than the default, as the payload type can't be recovered.
+	///
+	/// ```rust
+	/// # use std::io;
+	/// # use pam_client::{Error, ErrorCode};
+	/// let io_error = io::Error::from(Error::from(ErrorCode::PERM_DENIED));
+	/// let error = Error::from_io(&io_error).unwrap();
+	/// assert_eq!(error.code(), ErrorCode::PERM_DENIED);
+	/// ```
+	#[must_use]
+	pub fn from_io(err: &io::Error) -> Option<Error> {
+		let inner = err.get_ref()?;
+		// The `From` implementation boxes the error once more
+		match inner.downcast_ref::<Box<Error>>() {
+			Some(error) => Some(Error::clone(error)),
+			None => inner.downcast_ref::<Error>().cloned(),
 		}
 	}
 
@@ -208,6 +512,7 @@ impl Error {
 			code: self.code,
 			msg: self.msg,
 			payload: None,
+			user_cancelled: self.user_cancelled,
 		}
 	}
 }
@@ -215,7 +520,7 @@ impl Error {
 impl<T> Display for ErrorWith<T> {
 	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
 		if self.msg.is_empty() {
-			write!(f, "<{}>", self.code as i32)
+			write!(f, "<{}>", self.code_raw())
 		} else {
 			f.write_str(&self.msg)
 		}
@@ -271,10 +576,55 @@ impl From<ErrorCode> for Error {
 			code,
 			msg: String::new(),
 			payload: None,
+			user_cancelled: false,
 		}
 	}
 }
 
+/// Conversion of a [`NulError`] into a [`Error`] with code `BUF_ERR`.
+///
+/// Allows using `?` when building `CString`s from strings, like the item
+/// setters do when passed a string with a null byte.
+///
+/// ```rust
+/// # use std::ffi::CString;
+/// # use pam_client::{Error, ErrorCode};
+/// fn to_cstring(name: &str) -> Result<CString, Error> {
+///     Ok(CString::new(name)?)
+/// }
+///
+/// let error = to_cstring("us\0er").unwrap_err();
+/// assert_eq!(error.code(), ErrorCode::BUF_ERR);
+/// assert_eq!(error.message(), Some("string contains a null byte"));
+/// ```
+impl From<NulError> for Error {
+	#[inline]
+	fn from(_: NulError) -> Self {
+		Error::with_message(ErrorCode::BUF_ERR, "string contains a null byte")
+	}
+}
+
+/// Conversion of a [`NulError`] into the code `CONV_ERR`.
+///
+/// Allows using `?` when building responses in conversation handlers,
+/// whose methods return a bare [`ErrorCode`].
+///
+/// ```rust
+/// # use std::ffi::CString;
+/// # use pam_client::ErrorCode;
+/// fn respond(answer: String) -> Result<CString, ErrorCode> {
+///     Ok(CString::new(answer)?)
+/// }
+///
+/// assert_eq!(respond("a\0b".into()), Err(ErrorCode::CONV_ERR));
+/// ```
+impl From<NulError> for ErrorCode {
+	#[inline]
+	fn from(_: NulError) -> Self {
+		ErrorCode::CONV_ERR
+	}
+}
+
 /// Automatic wrapping in [`std::io::Error`] (if payload type is compatible).
 ///
 /// ```rust
@@ -299,17 +649,44 @@ impl From<ErrorCode> for Error {
 /// ```
 impl<T: Send + Sync + Debug + 'static> From<ErrorWith<T>> for io::Error {
 	fn from(error: ErrorWith<T>) -> Self {
-		io::Error::new(
-			match error.code {
-				ErrorCode::INCOMPLETE => io::ErrorKind::Interrupted,
-				ErrorCode::BAD_ITEM | ErrorCode::USER_UNKNOWN => io::ErrorKind::NotFound,
-				ErrorCode::CRED_INSUFFICIENT | ErrorCode::PERM_DENIED => {
-					io::ErrorKind::PermissionDenied
-				}
-				_ => io::ErrorKind::Other,
-			},
-			Box::new(error),
-		)
+		io::Error::new(io_error_kind(error.code), Box::new(error))
+	}
+}
+
+/// Maps an [`ErrorCode`] to the closest matching [`io::ErrorKind`].
+///
+/// Intentionally without wildcard, so new codes have to be mapped
+/// explicitly.
+const fn io_error_kind(code: ErrorCode) -> io::ErrorKind {
+	match code {
+		ErrorCode::INCOMPLETE => io::ErrorKind::Interrupted,
+		ErrorCode::CONV_AGAIN | ErrorCode::AUTHTOK_LOCK_BUSY => io::ErrorKind::WouldBlock,
+		ErrorCode::BAD_ITEM | ErrorCode::USER_UNKNOWN | ErrorCode::MODULE_UNKNOWN => {
+			io::ErrorKind::NotFound
+		}
+		ErrorCode::CRED_INSUFFICIENT
+		| ErrorCode::PERM_DENIED
+		| ErrorCode::AUTH_ERR
+		| ErrorCode::MAXTRIES
+		| ErrorCode::ACCT_EXPIRED
+		| ErrorCode::NEW_AUTHTOK_REQD
+		| ErrorCode::AUTHTOK_EXPIRED
+		| ErrorCode::CRED_EXPIRED => io::ErrorKind::PermissionDenied,
+		ErrorCode::AUTHTOK_ERR => io::ErrorKind::InvalidInput,
+		ErrorCode::ABORT => io::ErrorKind::ConnectionAborted,
+		ErrorCode::OPEN_ERR
+		| ErrorCode::SYMBOL_ERR
+		| ErrorCode::SERVICE_ERR
+		| ErrorCode::SYSTEM_ERR
+		| ErrorCode::BUF_ERR
+		| ErrorCode::AUTHINFO_UNAVAIL
+		| ErrorCode::SESSION_ERR
+		| ErrorCode::CRED_UNAVAIL
+		| ErrorCode::CRED_ERR
+		| ErrorCode::CONV_ERR
+		| ErrorCode::AUTHTOK_RECOVERY_ERR
+		| ErrorCode::AUTHTOK_DISABLE_AGING
+		| ErrorCode::IGNORE => io::ErrorKind::Other,
 	}
 }
 
@@ -318,6 +695,88 @@ mod tests {
 	use super::*;
 	use crate::conv_null::Conversation;
 	use crate::Context;
+	use std::cell::Cell;
+	use std::rc::Rc;
+
+	#[test]
+	fn test_backend_unavailable() {
+		let error = Error::from(ErrorCode::AUTHINFO_UNAVAIL);
+		assert!(error.is_backend_unavailable());
+		assert_ne!(
+			io::Error::from(error).kind(),
+			io::ErrorKind::PermissionDenied
+		);
+		assert!(!Error::from(ErrorCode::AUTH_ERR).is_backend_unavailable());
+		assert!(!Error::from(ErrorCode::USER_UNKNOWN).is_backend_unavailable());
+	}
+
+	#[test]
+	fn test_ignored() {
+		let error = Error::from(ErrorCode::IGNORE);
+		assert!(error.is_ignored());
+		assert_eq!(error.code().code_name(), "IGNORE");
+		assert_eq!(io::Error::from(error).kind(), io::ErrorKind::Other);
+		assert!(!Error::from(ErrorCode::PERM_DENIED).is_ignored());
+	}
+
+	#[test]
+	fn test_user_cancelled() {
+		let error = Error::from(ErrorCode::CONV_ERR);
+		assert!(!error.is_user_cancelled());
+		let error = error.into_user_cancelled();
+		assert!(error.is_user_cancelled());
+		let error = error.into_with_payload(1).map(|x| x + 1);
+		assert!(error.is_user_cancelled());
+		assert!(error.into_without_payload().is_user_cancelled());
+	}
+
+	/// Payload counting its drops
+	struct Tracked(Rc<Cell<usize>>);
+
+	impl Drop for Tracked {
+		fn drop(&mut self) {
+			self.0.set(self.0.get() + 1);
+		}
+	}
+
+	#[test]
+	fn test_payload_flows() {
+		let drops = Rc::new(Cell::new(0));
+		let error = Error::from(ErrorCode::ABORT).into_user_cancelled();
+
+		// Taking moves the payload out without dropping it
+		let mut with = error.clone().into_with_payload(Tracked(Rc::clone(&drops)));
+		let taken = with.take_payload();
+		assert!(taken.is_some() && with.payload().is_none());
+		assert_eq!(drops.get(), 0);
+
+		// Mapping after taking doesn't call the function
+		let mapped = with.map(|_| -> u8 { unreachable!() });
+		assert!(mapped.payload().is_none());
+		assert_eq!(mapped.code(), ErrorCode::ABORT);
+		assert!(mapped.is_user_cancelled());
+
+		// Replacing hands back the previous payload
+		let mut with = error.into_with_payload(Tracked(Rc::clone(&drops)));
+		let previous = with.replace_payload(taken.unwrap());
+		assert!(previous.is_some());
+		drop(previous);
+		assert_eq!(drops.get(), 1);
+
+		// Borrowed mapping keeps the original payload
+		let count = with.map_payload_ref(|tracked| Rc::strong_count(&tracked.0));
+		assert_eq!(count.payload(), Some(&2));
+		assert!(with.payload().is_some());
+		assert_eq!(count.message(), with.message());
+		assert!(count.is_user_cancelled());
+
+		// Mapping consumes the payload, dropping it unless returned
+		let unit = with.map(drop);
+		assert_eq!(drops.get(), 2);
+		assert_eq!(unit.payload(), Some(&()));
+		let without: Error = Error::from(ErrorCode::ABORT);
+		assert!(without.map_payload_ref(|_| 1).payload().is_none());
+	}
 
 	#[test]
 	fn test_basic() {
@@ -352,6 +811,125 @@ mod tests {
 		assert_eq!(io::Error::from(error).kind(), io::ErrorKind::NotFound);
 	}
 
+	#[test]
+	fn test_nul_error() {
+		let error = Error::from(std::ffi::CString::new("a\0b").unwrap_err());
+		assert_eq!(error.code(), ErrorCode::BUF_ERR);
+		assert_eq!(error.message(), Some("string contains a null byte"));
+		assert_eq!(format!("{}", error), "string contains a null byte");
+		let code = ErrorCode::from(std::ffi::CString::new("a\0b").unwrap_err());
+		assert_eq!(code, ErrorCode::CONV_ERR);
+	}
+
+	#[test]
+	fn test_code_conversion() {
+		use std::convert::TryFrom;
+		let code = ErrorCode::try_from(ErrorCode::CONV_ERR as libc::c_int);
+		assert_eq!(code, Ok(ErrorCode::CONV_ERR));
+		assert_eq!(Error::from(code.unwrap()).code(), ErrorCode::CONV_ERR);
+		assert_eq!(
+			Error::from(ErrorCode::CONV_ERR).code_raw(),
+			pam_sys::PAM_CONV_ERR as i32
+		);
+		assert_eq!(ErrorCode::try_from(-1), Err(-1));
+		assert_eq!(ErrorCode::AUTH_ERR.code_name(), "AUTH_ERR");
+		assert_eq!(ErrorCode::INCOMPLETE.code_name(), "INCOMPLETE");
+		assert_eq!(
+			ErrorCode::CONV_ERR.code_name(),
+			format!("{:?}", ErrorCode::CONV_ERR)
+		);
+	}
+
+	#[test]
+	fn test_message_resolver() {
+		// A separate slot keeps the resolver away from concurrently running
+		// tests creating errors.
+		let slot: ResolverSlot = RwLock::new(None);
+		let context = Context::new("test", None, Conversation::default()).unwrap();
+		let new_error = |code| Error::with_resolver(&slot, context.handle(), code, None);
+		replace_message_resolver(
+			&slot,
+			Some(Arc::new(|code| match code {
+				ErrorCode::AUTHTOK_DISABLE_AGING => Some("custom message".to_string()),
+				_ => None,
+			})),
+		);
+		let custom = new_error(ErrorCode::AUTHTOK_DISABLE_AGING);
+		let fallback = new_error(ErrorCode::AUTHTOK_LOCK_BUSY);
+		replace_message_resolver(&slot, None);
+		let cleared = new_error(ErrorCode::AUTHTOK_DISABLE_AGING);
+		assert_eq!(custom.message(), Some("custom message"));
+		assert_ne!(fallback.message(), Some("custom message"));
+		assert_ne!(cleared.message(), Some("custom message"));
+	}
+
+	#[test]
+	fn test_from_io() {
+		let context = Context::new("test", None, Conversation::default()).unwrap();
+		let error = Error::new(context.handle(), ErrorCode::AUTH_ERR);
+		let io_error = io::Error::from(error.clone());
+		let recovered = Error::from_io(&io_error).unwrap();
+		assert_eq!(recovered, error);
+		assert_eq!(recovered.code(), ErrorCode::AUTH_ERR);
+		assert_eq!(recovered.message(), error.message());
+
+		let with_payload = io::Error::from(error.into_with_payload(1_u8));
+		assert!(Error::from_io(&with_payload).is_none());
+		assert!(Error::from_io(&io::Error::from(io::ErrorKind::Other)).is_none());
+	}
+
+	#[test]
+	fn test_io_error_kinds() {
+		let mut codes = 0;
+		for raw in -1..=64 {
+			let code = match ErrorCode::from_repr(raw) {
+				Some(code) => code,
+				None => continue,
+			};
+			codes += 1;
+			let error = io::Error::from(Error::from(code));
+			assert_eq!(error.kind(), io_error_kind(code));
+			assert_eq!(Error::from_io(&error).unwrap().code(), code);
+		}
+		assert_eq!(codes, 29);
+
+		let kind = |code| io::Error::from(Error::from(code)).kind();
+		assert_eq!(
+			kind(ErrorCode::ACCT_EXPIRED),
+			io::ErrorKind::PermissionDenied
+		);
+		assert_eq!(
+			kind(ErrorCode::NEW_AUTHTOK_REQD),
+			io::ErrorKind::PermissionDenied
+		);
+		assert_eq!(kind(ErrorCode::ABORT), io::ErrorKind::ConnectionAborted);
+		assert_eq!(kind(ErrorCode::SYSTEM_ERR), io::ErrorKind::Other);
+		// The message is passed on unchanged
+		let error = Error::from(ErrorCode::SERVICE_ERR);
+		let message = error.to_string();
+		assert_eq!(io::Error::from(error).to_string(), message);
+	}
+
+	#[test]
+	fn test_module_hint() {
+		let hint = |msg: &str| {
+			Error::with_message(ErrorCode::AUTH_ERR, msg)
+				.module_hint()
+				.map(str::to_owned)
+		};
+		assert_eq!(
+			hint("pam_unix(login:auth): authentication failure").as_deref(),
+			Some("pam_unix")
+		);
+		assert_eq!(hint("denied by pam_access").as_deref(), Some("pam_access"));
+		assert_eq!(
+			hint("libpam_foo and pam_ and pam_sss").as_deref(),
+			Some("pam_sss")
+		);
+		assert_eq!(hint("Authentication failure"), None);
+		assert_eq!(Error::from(ErrorCode::AUTH_ERR).module_hint(), None);
+	}
+
 	/// Check if a hash can be calculated and equality works
 	#[test]
 	fn test_traits() {
@@ -0,0 +1,247 @@
+//! Handling of delays after failed authentication
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use std::cell::Cell;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Receiver of the delays PAM modules request after failures
+///
+/// PAM modules may request a delay after a failed authentication to slow
+/// down brute force attacks. When a sink is installed with
+/// [`Context::set_delay_sink()`][`crate::Context::set_delay_sink()`], the
+/// PAM library doesn't sleep itself, but the requested delay is passed to
+/// the sink after the PAM operation returned.
+///
+/// Implemented for closures taking a [`Duration`].
+pub trait DelaySink: Send {
+	/// Handles a delay of `duration` requested by PAM.
+	fn fail_delay(&self, duration: Duration);
+}
+
+impl<F> DelaySink for F
+where
+	F: Fn(Duration) + Send,
+{
+	fn fail_delay(&self, duration: Duration) {
+		self(duration)
+	}
+}
+
+/// Delay sink sleeping for the requested duration
+///
+/// Behaves like the default behaviour of the PAM library.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SleepingDelay;
+
+impl DelaySink for SleepingDelay {
+	fn fail_delay(&self, duration: Duration) {
+		thread::sleep(duration);
+	}
+}
+
+/// Delay sink recording the requested delays without sleeping
+///
+/// Useful to check the delay behaviour in tests without slowing them down.
+/// Clones share the same record, so a clone can be kept for inspection
+/// while the original is installed in a [`Context`][`crate::Context`].
+///
+/// # Examples
+/// ```rust
+/// use pam_client::RecordingDelay;
+///
+/// let recorder = RecordingDelay::new();
+/// # #[cfg(target_os = "linux")]
+/// # {
+/// # let mut context = pam_client::Context::new("test", None, pam_client::conv_null::Conversation::new()).unwrap();
+/// context.set_delay_sink(recorder.clone()).unwrap();
+/// # }
+/// // ... authenticate ...
+/// for delay in recorder.delays() {
+///     println!("PAM requested a delay of {:?}", delay);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RecordingDelay {
+	delays: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl RecordingDelay {
+	/// Creates a new recording delay sink without recorded delays
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Returns all delays recorded so far
+	#[must_use]
+	pub fn delays(&self) -> Vec<Duration> {
+		match self.delays.lock() {
+			Ok(delays) => delays.clone(),
+			Err(poisoned) => poisoned.into_inner().clone(),
+		}
+	}
+
+	/// Clears the recorded delays
+	pub fn clear(&self) {
+		match self.delays.lock() {
+			Ok(mut delays) => delays.clear(),
+			Err(poisoned) => poisoned.into_inner().clear(),
+		}
+	}
+}
+
+impl DelaySink for RecordingDelay {
+	fn fail_delay(&self, duration: Duration) {
+		match self.delays.lock() {
+			Ok(mut delays) => delays.push(duration),
+			Err(poisoned) => poisoned.into_inner().push(duration),
+		}
+	}
+}
+
+/// Source of the jitter applied to fail delays
+///
+/// Linux-PAM randomizes the delays requested by modules by up to 25% to
+/// make timing attacks harder, so the durations passed to a [`DelaySink`]
+/// vary between runs. A jitter source installed with
+/// [`Context::set_delay_jitter()`][`crate::Context::set_delay_jitter()`]
+/// replaces each duration before it reaches the sink, e.g. with a fixed
+/// value or a [`SeededJitter`], which makes timing tests deterministic.
+///
+/// Without a jitter source the durations are passed on unchanged.
+///
+/// Implemented for closures mapping a [`Duration`] to a [`Duration`].
+pub trait DelayJitter: Send {
+	/// Returns the delay to pass to the sink instead of `duration`.
+	fn jitter(&self, duration: Duration) -> Duration;
+}
+
+impl<F> DelayJitter for F
+where
+	F: Fn(Duration) -> Duration + Send,
+{
+	fn jitter(&self, duration: Duration) -> Duration {
+		self(duration)
+	}
+}
+
+/// Replayable pseudo-random jitter source
+///
+/// Randomizes the durations requested by PAM by up to 25%, like Linux-PAM
+/// does, but with a seeded generator: two instances created with the same
+/// seed yield the same sequence of delays for the same requests.
+///
+/// Not suitable for production use, as the delays are predictable.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{DelayJitter, SeededJitter};
+/// use std::time::Duration;
+///
+/// let first = SeededJitter::new(42);
+/// let replay = SeededJitter::new(42);
+/// let delay = first.jitter(Duration::from_secs(2));
+/// assert!(delay >= Duration::from_millis(1500) && delay <= Duration::from_millis(2500));
+/// assert_eq!(delay, replay.jitter(Duration::from_secs(2)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SeededJitter {
+	state: Cell<u64>,
+}
+
+impl SeededJitter {
+	/// Creates a jitter source with a generator seeded with `seed`.
+	#[must_use]
+	pub fn new(seed: u64) -> Self {
+		Self {
+			// xorshift gets stuck at zero
+			state: Cell::new(if seed == 0 {
+				0x9E37_79B9_7F4A_7C15
+			} else {
+				seed
+			}),
+		}
+	}
+
+	/// Internal: Advances the xorshift64 generator.
+	fn next(&self) -> u64 {
+		let mut x = self.state.get();
+		x ^= x << 13;
+		x ^= x >> 7;
+		x ^= x << 17;
+		self.state.set(x);
+		x
+	}
+}
+
+impl DelayJitter for SeededJitter {
+	fn jitter(&self, duration: Duration) -> Duration {
+		// Scale by a factor between 75% and 125% in 1/1000 steps
+		let permille = 750 + (self.next() % 501) as u32;
+		duration * permille / 1000
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_recording() {
+		let recorder = RecordingDelay::new();
+		let sink: Box<dyn DelaySink> = Box::new(recorder.clone());
+		sink.fail_delay(Duration::from_secs(2));
+		sink.fail_delay(Duration::from_millis(1));
+		assert_eq!(
+			recorder.delays(),
+			vec![Duration::from_secs(2), Duration::from_millis(1)]
+		);
+		recorder.clear();
+		assert!(recorder.delays().is_empty());
+		assert!(format!("{:?}", recorder).contains("RecordingDelay"));
+	}
+
+	#[test]
+	fn test_closure() {
+		let total = Arc::new(Mutex::new(Duration::from_secs(0)));
+		let sink_total = Arc::clone(&total);
+		let sink = move |duration| *sink_total.lock().unwrap() += duration;
+		sink.fail_delay(Duration::from_secs(1));
+		sink.fail_delay(Duration::from_secs(1));
+		assert_eq!(*total.lock().unwrap(), Duration::from_secs(2));
+		SleepingDelay.fail_delay(Duration::from_micros(1));
+	}
+
+	#[test]
+	fn test_seeded_jitter() {
+		let base = Duration::from_secs(2);
+		let jitter = SeededJitter::new(1);
+		let delays: Vec<_> = (0..32).map(|_| jitter.jitter(base)).collect();
+		assert!(delays
+			.iter()
+			.all(|d| *d >= base * 3 / 4 && *d <= base * 5 / 4));
+		assert!(delays.iter().any(|d| *d != delays[0]));
+		let replay = SeededJitter::new(1);
+		let replayed: Vec<_> = (0..32).map(|_| replay.jitter(base)).collect();
+		assert_eq!(delays, replayed);
+		assert_ne!(SeededJitter::new(0).jitter(base), Duration::default());
+		// The requested duration is scaled
+		let short = SeededJitter::new(1).jitter(Duration::from_millis(4));
+		assert!(short >= Duration::from_millis(3) && short <= Duration::from_millis(5));
+		assert_eq!(jitter.jitter(Duration::default()), Duration::default());
+
+		let fixed = |_| Duration::from_millis(5);
+		assert_eq!(fixed.jitter(base), Duration::from_millis(5));
+	}
+}
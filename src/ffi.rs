@@ -8,25 +8,120 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
  ***********************************************************************/
 
+use crate::encoding::EncodingPolicy;
 use crate::error::ErrorCode;
 use crate::resp_buf::ResponseBuffer;
-use crate::ConversationHandler;
 use crate::PAM_SUCCESS;
+use crate::{ConversationHandler, PromptContext};
 
-use libc::{c_char, c_int, c_void};
+use libc::{c_char, c_int, c_uint, c_void};
 use pam_sys::PAM_BUF_ERR;
 use pam_sys::{
 	pam_conv as PamConversation, pam_message as PamMessage, pam_response as PamResponse,
 };
+use std::cell::Cell;
 use std::ffi::{CStr, CString};
 use std::mem::size_of;
+use std::panic::{self, AssertUnwindSafe};
 use std::slice;
+use std::time::Duration;
+
+thread_local! {
+	/// Last error returned by a conversation handler on this thread
+	static HANDLER_ERROR: Cell<Option<ErrorCode>> = Cell::new(None);
+}
+
+/// Returns and resets the last error a conversation handler returned on
+/// this thread.
+pub(crate) fn take_handler_error() -> Option<ErrorCode> {
+	HANDLER_ERROR.with(Cell::take)
+}
+
+/// Records an error returned by a conversation handler.
+fn set_handler_error(code: ErrorCode) {
+	HANDLER_ERROR.with(|cell| cell.set(Some(code)));
+}
+
+thread_local! {
+	/// Whether a conversation handler on this thread reported a cancellation
+	static USER_CANCELLED: Cell<bool> = Cell::new(false);
+}
+
+/// Returns and resets whether a conversation handler on this thread
+/// reported that the user cancelled.
+pub(crate) fn take_user_cancelled() -> bool {
+	USER_CANCELLED.with(Cell::take)
+}
+
+/// Records that the user cancelled the conversation.
+pub(crate) fn set_user_cancelled() {
+	USER_CANCELLED.with(|cell| cell.set(true));
+}
+
+thread_local! {
+	/// Encoding policy of the context running an operation on this thread
+	static ENCODING_POLICY: Cell<EncodingPolicy> = Cell::new(EncodingPolicy::Lossy);
+}
+
+/// Sets the encoding policy applied to conversations on this thread and
+/// returns the previous one.
+pub(crate) fn set_encoding_policy(policy: EncodingPolicy) -> EncodingPolicy {
+	ENCODING_POLICY.with(|cell| cell.replace(policy))
+}
+
+/// Guard restoring the previous encoding policy of this thread on drop,
+/// also when the operation using the policy panics.
+pub(crate) struct EncodingPolicyGuard(EncodingPolicy);
+
+impl EncodingPolicyGuard {
+	/// Sets the encoding policy applied to conversations on this thread
+	/// until the guard is dropped.
+	pub(crate) fn new(policy: EncodingPolicy) -> Self {
+		Self(set_encoding_policy(policy))
+	}
+}
+
+impl Drop for EncodingPolicyGuard {
+	fn drop(&mut self) {
+		set_encoding_policy(self.0);
+	}
+}
+
+thread_local! {
+	/// Last fail delay requested by PAM on this thread
+	static FAIL_DELAY: Cell<Option<Duration>> = Cell::new(None);
+}
+
+/// Returns and resets the last fail delay PAM requested on this thread.
+pub(crate) fn take_fail_delay() -> Option<Duration> {
+	FAIL_DELAY.with(Cell::take)
+}
+
+/// Callback for `PAM_FAIL_DELAY` recording the requested delay instead of
+/// sleeping.
+///
+/// The delay is passed on to the delay sink of the context after the PAM
+/// operation returned, as the context isn't reachable from here.
+pub(crate) extern "C" fn fail_delay_callback(
+	_retval: c_int,
+	usec_delay: c_uint,
+	_appdata_ptr: *mut c_void,
+) {
+	FAIL_DELAY.with(|cell| cell.set(Some(Duration::from_micros(u64::from(usec_delay)))));
+}
 
 /// Wraps `callback` along with [`pam_converse<T>`] for handing to libpam.
 pub(crate) fn into_pam_conv<T: ConversationHandler>(callback: Box<T>) -> PamConversation {
+	raw_pam_conv(Box::into_raw(callback))
+}
+
+/// Wraps an already owned `callback` pointer along with [`pam_converse<T>`].
+///
+/// Like [`into_pam_conv()`] but without transferring ownership.
+pub(crate) fn raw_pam_conv<T: ConversationHandler>(callback: *mut T) -> PamConversation {
 	PamConversation {
 		conv: Some(pam_converse::<T>),
-		appdata_ptr: Box::into_raw(callback).cast(),
+		appdata_ptr: callback.cast(),
 	}
 }
 
@@ -137,21 +232,31 @@ unsafe fn msg_content_to_cstr(msg: &*const c_char) -> &CStr {
 ///
 /// NULL pointers are converted into empty data as a safety measure.
 ///
+/// # Errors
+/// Fails with `CONV_ERR` if the payload length claimed in the header
+/// exceeds `max_len`.
+///
 /// # Safety
 /// This is sound as long as the message type implies a binary message
 /// and the PAM modules play by the rules.
 #[allow(clippy::cast_sign_loss)]
-unsafe fn msg_content_to_bin(msg: &*const c_char) -> (u8, &[u8]) {
+unsafe fn msg_content_to_bin(
+	msg: &*const c_char,
+	max_len: usize,
+) -> Result<(u8, &[u8]), ErrorCode> {
 	if msg.is_null() {
-		(0, &[])
+		Ok((0, &[]))
 	} else {
 		// Decode length and data
 		// Sound as long as the PAM modules set the length correctly
 		let len = u32::from_be_bytes(*(*msg).cast());
-		let len = len.saturating_sub(5); // Subtract header length
+		let len = len.saturating_sub(5) as usize; // Subtract header length
+		if len > max_len {
+			return Err(ErrorCode::CONV_ERR);
+		}
 		let type_ = *msg.add(4) as u8;
-		let data = slice::from_raw_parts(msg.add(5).cast(), len as usize);
-		(type_, data)
+		let data = slice::from_raw_parts(msg.add(5).cast(), len);
+		Ok((type_, data))
 	}
 }
 
@@ -160,11 +265,33 @@ unsafe fn msg_content_to_bin(msg: &*const c_char) -> (u8, &[u8]) {
 /// Will be called by C code when a conversation is requested. Does sanity
 /// checks, prepares a response buffer and calls the conversation function
 /// identified by `T` and `appdata_ptr` for each message.
+///
+/// Panics of the conversation handler must not unwind into C code; they
+/// are caught and reported to the module as `CONV_ERR`.
 pub(crate) unsafe extern "C" fn pam_converse<T: ConversationHandler>(
 	num_msg: c_int,
 	msg: *mut *const PamMessage,
 	out_resp: *mut *mut PamResponse,
 	appdata_ptr: *mut c_void,
+) -> c_int {
+	let result = panic::catch_unwind(AssertUnwindSafe(|| {
+		converse::<T>(num_msg, msg, out_resp, appdata_ptr)
+	}));
+	result.unwrap_or_else(|_| {
+		set_handler_error(ErrorCode::CONV_ERR);
+		ErrorCode::CONV_ERR.repr()
+	})
+}
+
+/// Implementation of [`pam_converse()`] without the panic guard.
+///
+/// # Safety
+/// Same as the callback in `struct pam_conv`.
+unsafe fn converse<T: ConversationHandler>(
+	num_msg: c_int,
+	msg: *mut *const PamMessage,
+	out_resp: *mut *mut PamResponse,
+	appdata_ptr: *mut c_void,
 ) -> c_int {
 	const MAX_MSG_NUM: isize = max_msg_num();
 
@@ -193,70 +320,262 @@ pub(crate) unsafe extern "C" fn pam_converse<T: ConversationHandler>(
 	// Cast `msg` with `num_msg` to a slice for easy iteration.
 	let messages = msg_to_slice(&msg, num_msg);
 
+	let prompt_context = |index| PromptContext {
+		index,
+		total: messages.len(),
+	};
+	let policy = ENCODING_POLICY.with(Cell::get);
+
 	// Call conversation handler for each message
 	for (i, message) in messages.iter().enumerate() {
+		// A cancellation only counts if this call fails because of it
+		let _ = take_user_cancelled();
+
+		// Only the style is recorded, never message contents or responses
+		#[cfg(feature = "tracing")]
+		tracing::trace!(msg_style = message.msg_style, "PAM conversation message");
+
 		match message.msg_style as c_int {
 			// Special case: experimental binary messages (Linux)
 			#[cfg(target_os = "linux")]
 			pam_sys::PAM_BINARY_PROMPT => {
-				let (type_, data) = msg_content_to_bin(&message.msg);
-				let result = handler.binary_prompt(type_, data);
+				let result = msg_content_to_bin(&message.msg, handler.max_binary_len())
+					.and_then(|(type_, data)| handler.binary_prompt(type_.into(), data));
 				match result {
-					Ok(response) => responses.put_binary(i, response.0, &response.1),
-					Err(code) => return code.repr(),
+					Ok(response) => responses.put_binary(i, response.0.into(), &response.1),
+					Err(code) => {
+						set_handler_error(code);
+						return code.repr();
+					}
 				}
 			}
 			// All other cases
 			_ => {
 				// Delegate to the correct handler method based on `msg_style`
-				let result = match message.msg_style as c_int {
-					pam_sys::PAM_PROMPT_ECHO_ON => {
-						let text = msg_content_as_cstr(&message.msg);
-						handler.prompt_echo_on(text).map(map_conv_string)
-					}
-					pam_sys::PAM_PROMPT_ECHO_OFF => {
-						let text = msg_content_as_cstr(&message.msg);
-						handler.prompt_echo_off(text).map(map_conv_string)
-					}
-					pam_sys::PAM_TEXT_INFO => {
-						let text = msg_content_as_cstr(&message.msg);
-						handler.text_info(text);
-						Ok(None)
-					}
-					pam_sys::PAM_ERROR_MSG => {
-						let text = msg_content_as_cstr(&message.msg);
-						handler.error_msg(text);
-						Ok(None)
-					}
-					#[cfg(target_os = "linux")]
-					pam_sys::PAM_RADIO_TYPE => {
-						let text = msg_content_to_cstr(&message.msg);
-						handler.radio_prompt(text).map(|b| {
-							if b {
-								CString::new("yes").ok()
-							} else {
-								CString::new("no").ok()
-							}
-						})
-					}
-					_ => Err(ErrorCode::CONV_ERR),
-				};
+				let result =
+					match message.msg_style as c_int {
+						pam_sys::PAM_PROMPT_ECHO_ON => policy
+							.decode(msg_content_as_cstr(&message.msg))
+							.and_then(|text| {
+								handler.prompt_echo_on_with_context(&text, prompt_context(i))
+							})
+							.and_then(|response| policy.encode(response))
+							.map(map_conv_string),
+						pam_sys::PAM_PROMPT_ECHO_OFF => policy
+							.decode(msg_content_as_cstr(&message.msg))
+							.and_then(|text| {
+								handler.prompt_echo_off_with_context(&text, prompt_context(i))
+							})
+							.and_then(|response| policy.encode(response))
+							.map(map_conv_string),
+						pam_sys::PAM_TEXT_INFO => policy
+							.decode(msg_content_as_cstr(&message.msg))
+							.map(|text| {
+								handler.text_info(&text);
+								None
+							}),
+						pam_sys::PAM_ERROR_MSG => policy
+							.decode(msg_content_as_cstr(&message.msg))
+							.map(|text| {
+								handler.error_msg(&text);
+								None
+							}),
+						#[cfg(target_os = "linux")]
+						pam_sys::PAM_RADIO_TYPE => {
+							let text = policy.decode(msg_content_to_cstr(&message.msg));
+							text.and_then(|text| handler.radio_prompt(&text)).map(|b| {
+								if b {
+									CString::new("yes").ok()
+								} else {
+									CString::new("no").ok()
+								}
+							})
+						}
+						_ => Err(ErrorCode::CONV_ERR),
+					};
 
 				// Process response and bail out on errors
 				match result {
 					Ok(response) => responses.put(i, response),
-					Err(code) => return code.repr(),
+					Err(code) => {
+						set_handler_error(code);
+						return code.repr();
+					}
 				}
 			}
 		}
 	}
 
+	// Handlers recovered from any cancellation
+	let _ = take_user_cancelled();
+
 	// Transfer responses to caller and return.
 	// Sound as long as the PAM modules play by the rules..
 	*out_resp = responses.into();
 	PAM_SUCCESS
 }
 
+/// Test helpers for calling [`pam_converse()`] without a PAM stack.
+///
+/// [`Messages`] builds the `pam_message` array a module would pass and
+/// [`Responses`] inspects (and frees) the `pam_response` array returned.
+#[cfg(test)]
+pub(crate) mod testing {
+	use super::*;
+	use libc::free;
+	use std::convert::TryFrom;
+	use std::ptr;
+
+	/// Owned array of `pam_message` structs
+	#[derive(Default)]
+	pub(crate) struct Messages {
+		/// Message contents, kept alive as long as the structs point to them
+		contents: Vec<Vec<u8>>,
+		messages: Vec<PamMessage>,
+	}
+
+	impl Messages {
+		/// Creates an empty message array.
+		pub fn new() -> Self {
+			Self::default()
+		}
+
+		/// Appends a text message with `style`.
+		///
+		/// # Panics
+		/// Panics if `text` contains null bytes.
+		pub fn text(self, style: c_int, text: &str) -> Self {
+			let text = CString::new(text).unwrap();
+			self.raw(style, Some(text.into_bytes_with_nul()))
+		}
+
+		/// Appends a message with `style` and a null `msg` pointer.
+		pub fn null(self, style: c_int) -> Self {
+			self.raw(style, None)
+		}
+
+		/// Appends a binary prompt with the pseudo-struct
+		/// `{length: u32, type: u8, data: [u8]}` as content.
+		#[cfg(target_os = "linux")]
+		pub fn binary(self, type_: u8, data: &[u8]) -> Self {
+			let len = u32::try_from(data.len() + 5).unwrap();
+			let mut content = len.to_be_bytes().to_vec();
+			content.push(type_);
+			content.extend_from_slice(data);
+			self.raw(pam_sys::PAM_BINARY_PROMPT, Some(content))
+		}
+
+		/// Appends a message with `style` and arbitrary `content`.
+		pub fn raw(mut self, style: c_int, content: Option<Vec<u8>>) -> Self {
+			// The heap buffer of `content` doesn't move with the vector
+			let msg = content.as_ref().map_or(ptr::null(), |c| c.as_ptr().cast());
+			self.contents.extend(content);
+			self.messages.push(PamMessage {
+				msg_style: style as _,
+				msg,
+			});
+			self
+		}
+
+		/// Returns the number of messages.
+		pub fn len(&self) -> usize {
+			self.messages.len()
+		}
+
+		/// Calls [`pam_converse<T>`] with the messages and `handler` as
+		/// `appdata_ptr`, exactly as a module would.
+		///
+		/// Returns the PAM return code and the responses, if any.
+		#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+		pub fn converse<T: ConversationHandler>(&self, handler: &mut T) -> (c_int, Responses) {
+			let conv = raw_pam_conv(handler as *mut T);
+			// Linux and similar expect an array of pointers, Solaris a
+			// pointer to an array.
+			#[cfg(not(target_os = "solaris"))]
+			let mut pointers: Vec<*const PamMessage> = self.messages.iter().map(|m| m as *const _).collect();
+			#[cfg(target_os = "solaris")]
+			let mut pointers = vec![self.messages.as_ptr()];
+			let mut resp: *mut PamResponse = ptr::null_mut();
+			let code = unsafe {
+				conv.conv.unwrap()(
+					self.len() as c_int,
+					pointers.as_mut_ptr(),
+					&mut resp,
+					conv.appdata_ptr,
+				)
+			};
+			(
+				code,
+				Responses {
+					ptr: resp,
+					len: self.len(),
+				},
+			)
+		}
+	}
+
+	/// `pam_response` array returned by the conversation callback
+	///
+	/// The array and all responses are freed on drop.
+	pub(crate) struct Responses {
+		ptr: *mut PamResponse,
+		len: usize,
+	}
+
+	impl Responses {
+		/// Returns whether no response array was returned.
+		pub fn is_null(&self) -> bool {
+			self.ptr.is_null()
+		}
+
+		fn get(&self, index: usize) -> &PamResponse {
+			assert!(!self.ptr.is_null(), "no responses returned");
+			assert!(index < self.len, "response index out of bounds");
+			unsafe { &*self.ptr.add(index) }
+		}
+
+		/// Returns the `resp_retcode` of response `index`.
+		pub fn retcode(&self, index: usize) -> c_int {
+			self.get(index).resp_retcode
+		}
+
+		/// Returns the text of response `index`, if not null.
+		pub fn text(&self, index: usize) -> Option<&CStr> {
+			let resp = self.get(index).resp;
+			if resp.is_null() {
+				None
+			} else {
+				Some(unsafe { CStr::from_ptr(resp) })
+			}
+		}
+
+		/// Decodes response `index` as binary pseudo-struct, if not null.
+		pub fn binary(&self, index: usize) -> Option<(u8, &[u8])> {
+			let resp = self.get(index).resp;
+			if resp.is_null() {
+				None
+			} else {
+				// Sound as long as the handler set the length correctly
+				let header: [u8; 4] = unsafe { *resp.cast() };
+				let len = (u32::from_be_bytes(header) as usize).saturating_sub(5);
+				let data = unsafe { slice::from_raw_parts(resp.add(5).cast(), len) };
+				Some((unsafe { *resp.add(4) } as u8, data))
+			}
+		}
+	}
+
+	impl Drop for Responses {
+		fn drop(&mut self) {
+			if !self.ptr.is_null() {
+				for i in 0..self.len {
+					unsafe { free((*self.ptr.add(i)).resp.cast()) };
+				}
+				unsafe { free(self.ptr.cast()) };
+			}
+		}
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -476,6 +795,9 @@ mod tests {
 			responses.is_null(),
 			"response is not null after conversation with error"
 		);
+
+		assert_eq!(take_handler_error(), Some(ErrorCode::CONV_ERR));
+		assert_eq!(take_handler_error(), None);
 	}
 
 	/// Check if `pam_conv` correctly answers an echoing prompt
@@ -652,4 +974,311 @@ mod tests {
 			"pam_conv returned unexpected error code `left`"
 		);
 	}
+
+	/// Conversation handler echoing binary messages with a small size limit
+	#[cfg(target_os = "linux")]
+	struct BinaryEcho;
+
+	#[cfg(target_os = "linux")]
+	impl ConversationHandler for BinaryEcho {
+		fn prompt_echo_on(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			Err(ErrorCode::CONV_ERR)
+		}
+		fn prompt_echo_off(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			Err(ErrorCode::CONV_ERR)
+		}
+		fn text_info(&mut self, _msg: &CStr) {}
+		fn error_msg(&mut self, _msg: &CStr) {}
+		fn binary_prompt(
+			&mut self,
+			type_: crate::BinaryType,
+			data: &[u8],
+		) -> Result<(crate::BinaryType, Vec<u8>), ErrorCode> {
+			Ok((type_, data.to_vec()))
+		}
+		fn max_binary_len(&self) -> usize {
+			4
+		}
+	}
+
+	/// Check if `pam_conv` answers binary messages within the size limit and
+	/// rejects bigger ones
+	#[test]
+	#[cfg(target_os = "linux")]
+	fn test_binary_limit() {
+		let pam_conv = into_pam_conv(Box::new(BinaryEcho));
+		let c_callback = pam_conv.conv.unwrap();
+		let appdata = pam_conv.appdata_ptr;
+
+		for (buffer, expected) in [
+			(vec![0, 0, 0, 7, 0x01, 0x42, 0x43], PAM_SUCCESS),
+			(
+				vec![0, 0, 0, 10, 0x01, 0, 0, 0, 0, 0],
+				pam_sys::PAM_CONV_ERR as c_int,
+			),
+			(
+				vec![0xFF, 0xFF, 0xFF, 0xFF, 0x01],
+				pam_sys::PAM_CONV_ERR as c_int,
+			),
+		]
+		.iter()
+		{
+			let msg = PamMessage {
+				msg_style: pam_sys::PAM_BINARY_PROMPT as c_int,
+				msg: buffer.as_ptr() as *const _,
+			};
+			let mut msg_ptr = &msg as *const _;
+
+			let mut responses: *mut PamResponse = ptr::null_mut();
+
+			let code = unsafe {
+				c_callback(
+					1,
+					&mut msg_ptr as *mut *const _,
+					&mut responses as *mut *mut _,
+					appdata,
+				)
+			};
+
+			assert_eq!(
+				code, *expected,
+				"pam_conv returned unexpected error code `left`"
+			);
+
+			if code == PAM_SUCCESS {
+				let response =
+					unsafe { slice::from_raw_parts((*responses).resp as *const u8, buffer.len()) };
+				assert_eq!(response, &buffer[..]);
+				unsafe {
+					free((*responses).resp as *mut _);
+					free(responses as *mut _);
+				}
+			}
+		}
+
+		drop(unsafe { Box::from_raw(from_pam_conv::<BinaryEcho>(&pam_conv)) });
+	}
+
+	/// Check a conversation with several messages in one call
+	#[test]
+	fn test_round_trip() {
+		use super::testing::Messages;
+
+		let mut handler = Conversation::with_credentials("user", "secret");
+		let messages = Messages::new()
+			.text(pam_sys::PAM_PROMPT_ECHO_ON, "login: ")
+			.text(pam_sys::PAM_TEXT_INFO, "hello")
+			.text(pam_sys::PAM_PROMPT_ECHO_OFF, "password: ")
+			.null(pam_sys::PAM_ERROR_MSG);
+		let (code, responses) = messages.converse(&mut handler);
+		assert_eq!(code, PAM_SUCCESS);
+		assert_eq!(responses.text(0).unwrap().to_bytes(), b"user");
+		assert_eq!(responses.text(1), None);
+		assert_eq!(responses.text(2).unwrap().to_bytes(), b"secret");
+		assert_eq!(responses.text(3), None);
+		assert!((0..messages.len()).all(|i| responses.retcode(i) == 0));
+		drop(responses);
+		assert_eq!(handler.log.len(), 2);
+
+		let messages = Messages::new()
+			.text(pam_sys::PAM_TEXT_INFO, "first")
+			.raw(65535, None);
+		let (code, responses) = messages.converse(&mut handler);
+		assert_eq!(code, ErrorCode::CONV_ERR.repr());
+		assert!(responses.is_null());
+		assert_eq!(take_handler_error(), Some(ErrorCode::CONV_ERR));
+	}
+
+	/// Check a binary round trip through the test helpers
+	#[test]
+	#[cfg(target_os = "linux")]
+	fn test_round_trip_binary() {
+		use super::testing::Messages;
+
+		let (code, responses) = Messages::new()
+			.binary(0x07, b"abc")
+			.converse(&mut BinaryEcho);
+		assert_eq!(code, PAM_SUCCESS);
+		assert_eq!(responses.binary(0), Some((0x07, &b"abc"[..])));
+	}
+
+	/// Conversation handler panicking on prompts
+	struct Panicking;
+
+	impl ConversationHandler for Panicking {
+		fn prompt_echo_on(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			panic!("prompt_echo_on")
+		}
+		fn prompt_echo_off(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			panic!("prompt_echo_off")
+		}
+		fn text_info(&mut self, _msg: &CStr) {}
+		fn error_msg(&mut self, _msg: &CStr) {}
+	}
+
+	/// Check if panics of the handler are turned into `CONV_ERR` instead
+	/// of unwinding into C code
+	#[test]
+	fn test_panic() {
+		use super::testing::Messages;
+
+		let (code, responses) = Messages::new()
+			.text(pam_sys::PAM_TEXT_INFO, "info")
+			.text(pam_sys::PAM_PROMPT_ECHO_OFF, "password: ")
+			.converse(&mut Panicking);
+		assert_eq!(code, ErrorCode::CONV_ERR.repr());
+		assert!(responses.is_null());
+		assert_eq!(take_handler_error(), Some(ErrorCode::CONV_ERR));
+	}
+
+	/// Check if the encoding policies are applied to messages and responses
+	/// and restored by the guard
+	#[test]
+	fn test_encoding_policy() {
+		use super::testing::Messages;
+		use crate::{Encoding, RecordingConversation};
+
+		let prompt = Some(b"Passw\xF6rt: \0".to_vec());
+		let messages = Messages::new().raw(pam_sys::PAM_PROMPT_ECHO_ON, prompt);
+		let mut handler =
+			RecordingConversation::new(Conversation::with_credentials("usér", "pass"));
+
+		let previous = set_encoding_policy(EncodingPolicy::Strict);
+		let (code, _) = messages.converse(&mut handler);
+		assert_eq!(code, ErrorCode::CONV_ERR.repr());
+		assert!(handler.messages().is_empty());
+		assert_eq!(take_handler_error(), Some(ErrorCode::CONV_ERR));
+
+		set_encoding_policy(EncodingPolicy::Transcode(Encoding::Latin1));
+		let (code, responses) = messages.converse(&mut handler);
+		assert_eq!(code, PAM_SUCCESS);
+		assert_eq!(responses.text(0).unwrap().to_bytes(), b"us\xE9r");
+		assert_eq!(handler.messages()[0].1, "Passwört: ".as_bytes());
+
+		set_encoding_policy(EncodingPolicy::Lossy);
+		let (code, responses) = messages.converse(&mut handler);
+		assert_eq!(code, PAM_SUCCESS);
+		assert_eq!(responses.text(0).unwrap().to_str(), Ok("usér"));
+		assert_eq!(handler.messages()[1].1, b"Passw\xF6rt: ");
+		set_encoding_policy(previous);
+
+		// The guard restores the policy even if the operation panics
+		let result = std::panic::catch_unwind(|| {
+			let _guard = EncodingPolicyGuard::new(EncodingPolicy::Strict);
+			panic!("operation failed");
+		});
+		assert!(result.is_err());
+		assert_eq!(set_encoding_policy(previous), previous);
+	}
+
+	/// Conversation handler cancelling all prompts
+	struct Cancelling;
+
+	impl ConversationHandler for Cancelling {
+		fn prompt_echo_on(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			Err(crate::user_cancelled())
+		}
+		fn prompt_echo_off(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			Err(crate::user_cancelled())
+		}
+		fn text_info(&mut self, _msg: &CStr) {}
+		fn error_msg(&mut self, _msg: &CStr) {}
+	}
+
+	#[test]
+	fn test_user_cancelled() {
+		use super::testing::Messages;
+
+		let _ = take_user_cancelled();
+		let (code, _) = Messages::new()
+			.text(pam_sys::PAM_TEXT_INFO, "info")
+			.converse(&mut Cancelling);
+		assert_eq!(code, PAM_SUCCESS);
+		assert!(!take_user_cancelled());
+
+		let (code, responses) = Messages::new()
+			.text(pam_sys::PAM_PROMPT_ECHO_OFF, "password: ")
+			.converse(&mut Cancelling);
+		assert_eq!(code, ErrorCode::CONV_ERR.repr());
+		assert!(responses.is_null());
+		assert_eq!(take_handler_error(), Some(ErrorCode::CONV_ERR));
+		assert!(take_user_cancelled());
+		assert!(!take_user_cancelled());
+
+		// A wrapper recovering from the cancellation clears it
+		let mut fallback = crate::FallbackConversation::new(
+			Cancelling,
+			crate::conv_mock::Conversation::with_credentials("user", "pass"),
+		);
+		let (code, responses) = Messages::new()
+			.text(pam_sys::PAM_PROMPT_ECHO_OFF, "password: ")
+			.converse(&mut fallback);
+		assert_eq!(code, PAM_SUCCESS);
+		assert_eq!(responses.text(0).unwrap().to_str(), Ok("pass"));
+		assert!(!take_user_cancelled());
+
+		// An earlier cancellation doesn't stick to later callbacks
+		set_user_cancelled();
+		let (code, _) = Messages::new()
+			.text(pam_sys::PAM_PROMPT_ECHO_OFF, "password: ")
+			.converse(&mut crate::conv_null::Conversation::new());
+		assert_eq!(code, ErrorCode::CONV_ERR.repr());
+		assert!(!take_user_cancelled());
+	}
+
+	/// Conversation handler recording the context of each prompt
+	#[derive(Default)]
+	struct ContextRecorder(Vec<PromptContext>);
+
+	impl ConversationHandler for ContextRecorder {
+		fn prompt_echo_on(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			Err(ErrorCode::CONV_ERR)
+		}
+		fn prompt_echo_off(&mut self, _msg: &CStr) -> Result<CString, ErrorCode> {
+			Err(ErrorCode::CONV_ERR)
+		}
+		fn prompt_echo_on_with_context(
+			&mut self,
+			_msg: &CStr,
+			context: PromptContext,
+		) -> Result<CString, ErrorCode> {
+			self.0.push(context);
+			Ok(CString::default())
+		}
+		fn prompt_echo_off_with_context(
+			&mut self,
+			_msg: &CStr,
+			context: PromptContext,
+		) -> Result<CString, ErrorCode> {
+			self.0.push(context);
+			Ok(CString::default())
+		}
+		fn text_info(&mut self, _msg: &CStr) {}
+		fn error_msg(&mut self, _msg: &CStr) {}
+	}
+
+	/// Check if prompts get their position within the call, also through
+	/// wrappers
+	#[test]
+	fn test_prompt_context() {
+		use super::testing::Messages;
+
+		let messages = Messages::new()
+			.text(pam_sys::PAM_PROMPT_ECHO_ON, "login: ")
+			.text(pam_sys::PAM_TEXT_INFO, "info")
+			.text(pam_sys::PAM_PROMPT_ECHO_OFF, "password: ");
+		let mut handler = Box::new(crate::BoundedConversation::new(
+			ContextRecorder::default(),
+			2,
+		));
+		let (code, _) = messages.converse(&mut handler);
+		assert_eq!(code, PAM_SUCCESS);
+		assert_eq!(
+			handler.handler().0,
+			vec![
+				PromptContext { index: 0, total: 3 },
+				PromptContext { index: 2, total: 3 }
+			]
+		);
+	}
 }
@@ -0,0 +1,169 @@
+/*!
+ * Temporary PAM service policies for tests
+ *
+ * *This module is unavailable if pam-client is built without the
+ * `"test-fixtures"` feature.*
+ */
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::{Context, ConversationHandler, Result};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Service name used for the policy written by [`ServiceFixture`].
+pub const SERVICE: &str = "pam-client-test";
+
+/// Counter making fixture directories of one process unique
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary PAM configuration directory with a single service policy
+///
+/// Writes a policy for the service [`SERVICE`] into a new directory below
+/// [`std::env::temp_dir()`], so tests don't depend on (or modify) the
+/// system PAM configuration. The directory is removed on drop.
+///
+/// The policy uses `pam_permit` or `pam_deny` for all module types, so
+/// every operation either succeeds or fails.
+///
+/// Contexts using the fixture are created with `pam_start_confdir`, which
+/// requires Linux-PAM 1.4 or later; `SYMBOL_ERR` is returned otherwise.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::fixtures::ServiceFixture;
+/// use pam_client::{conv_null, Flag};
+///
+/// let fixture = ServiceFixture::permit().unwrap();
+/// if let Ok(mut context) = fixture.context(Some("user"), conv_null::Conversation::new()) {
+///     context.authenticate(Flag::NONE).expect("pam_permit always succeeds");
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ServiceFixture {
+	confdir: PathBuf,
+}
+
+impl ServiceFixture {
+	/// Creates a fixture with a policy permitting everything.
+	///
+	/// # Errors
+	/// Returns the underlying I/O error if the policy cannot be written.
+	pub fn permit() -> io::Result<Self> {
+		Self::with_module("pam_permit.so")
+	}
+
+	/// Creates a fixture with a policy denying everything.
+	///
+	/// # Errors
+	/// Returns the underlying I/O error if the policy cannot be written.
+	pub fn deny() -> io::Result<Self> {
+		Self::with_module("pam_deny.so")
+	}
+
+	/// Creates a fixture with a policy using `module` for all module types.
+	///
+	/// # Errors
+	/// Returns the underlying I/O error if the policy cannot be written.
+	pub fn with_module(module: &str) -> io::Result<Self> {
+		let policy = ["auth", "account", "password", "session"]
+			.iter()
+			.map(|kind| format!("{}\trequired\t{}\n", kind, module))
+			.collect::<String>();
+		Self::with_policy(&policy)
+	}
+
+	/// Creates a fixture with a custom policy in `pam.d` syntax.
+	///
+	/// # Errors
+	/// Returns the underlying I/O error if the policy cannot be written.
+	pub fn with_policy(policy: &str) -> io::Result<Self> {
+		let confdir = std::env::temp_dir().join(format!(
+			"pam-client-fixture-{}-{}",
+			process::id(),
+			COUNTER.fetch_add(1, Ordering::Relaxed)
+		));
+		fs::create_dir(&confdir)?;
+		// From here on dropping `fixture` cleans up
+		let fixture = Self { confdir };
+		fs::write(fixture.confdir.join(SERVICE), policy)?;
+		Ok(fixture)
+	}
+
+	/// The temporary configuration directory
+	#[must_use]
+	pub fn confdir(&self) -> &Path {
+		&self.confdir
+	}
+
+	/// Creates a PAM context for [`SERVICE`] using this fixture.
+	///
+	/// See [`Context::new()`] for the parameters.
+	///
+	/// # Errors
+	/// Same as [`Context::new()`]. Additionally `SYMBOL_ERR` is returned if
+	/// the PAM library doesn't support alternative configuration
+	/// directories.
+	pub fn context<ConvT: ConversationHandler>(
+		&self,
+		username: Option<&str>,
+		conversation: ConvT,
+	) -> Result<Context<ConvT>> {
+		Context::start(
+			SERVICE,
+			username,
+			Box::new(conversation),
+			Some(&self.confdir),
+		)
+	}
+}
+
+impl Drop for ServiceFixture {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.confdir);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{conv_null, ErrorCode, Flag};
+
+	#[test]
+	fn test_fixture() {
+		let permit = ServiceFixture::permit().unwrap();
+		let deny = ServiceFixture::deny().unwrap();
+		let confdir = permit.confdir().to_path_buf();
+		assert_ne!(confdir, deny.confdir());
+		let policy = fs::read_to_string(confdir.join(SERVICE)).unwrap();
+		assert!(policy.contains("auth\trequired\tpam_permit.so"));
+
+		match permit.context(Some("user"), conv_null::Conversation::new()) {
+			Ok(mut context) => {
+				assert_eq!(context.resolved_config_path(), Some(confdir.join(SERVICE)));
+				context.authenticate(Flag::NONE).unwrap();
+				context.acct_mgmt(Flag::NONE).unwrap();
+				let mut context = deny
+					.context(Some("user"), conv_null::Conversation::new())
+					.unwrap();
+				assert!(context.authenticate(Flag::NONE).is_err());
+			}
+			Err(e) => assert_eq!(e.code(), ErrorCode::SYMBOL_ERR),
+		}
+
+		drop(permit);
+		assert!(!confdir.exists());
+	}
+}
@@ -0,0 +1,238 @@
+//! Toolkit-independent GUI prompter trait and conversation adapter
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::conversation::user_cancelled;
+use crate::error::ErrorCode;
+use crate::ConversationHandler;
+
+use std::ffi::{CStr, CString};
+
+/// Dialogs of a graphical user interface, as used by [`GuiConversation`]
+///
+/// Implement this trait with the widgets of your toolkit to answer PAM
+/// prompts, e.g. in a desktop login manager. All texts are passed as
+/// UTF-8; invalid sequences are replaced with `U+FFFD REPLACEMENT
+/// CHARACTER` unless the [`EncodingPolicy`][`crate::EncodingPolicy`] of
+/// the context converts them.
+///
+/// # Threading
+///
+/// The methods are called synchronously on the thread running the PAM
+/// operation and PAM waits for them to return. Each method must therefore
+/// block until the user answered. Don't run PAM operations on the thread
+/// of the toolkit's event loop, as that would freeze the user interface
+/// during authentication or deadlock when the dialog waits for the event
+/// loop. Instead run them on a worker thread (e.g. with
+/// [`ThreadedAuthenticator`][`crate::ThreadedAuthenticator`]) and let the
+/// implementation hand the dialog over to the event loop and wait for the
+/// result, e.g. over a channel. [`conv_channel`][`crate::conv_channel`]
+/// offers a lower-level alternative for that pattern.
+pub trait GuiPrompter {
+	/// Asks the user for a text input.
+	///
+	/// `echo` is `false` for secrets like passwords, whose input must be
+	/// masked, and `true` for visible input like the username.
+	///
+	/// Returns `None` if the user cancelled the dialog.
+	fn show_password_dialog(&mut self, prompt: &str, echo: bool) -> Option<String>;
+
+	/// Shows a message to the user.
+	///
+	/// `is_error` is `true` for error messages and `false` for
+	/// informational texts.
+	fn show_message(&mut self, msg: &str, is_error: bool);
+
+	/// Asks the user a yes/no question (Linux specific).
+	///
+	/// Returns `None` if the user cancelled the dialog.
+	///
+	/// The default implementation asks with a visible text input and maps
+	/// any answer starting with 'y' or 'j' to "yes" and everything else to
+	/// "no".
+	fn confirm(&mut self, question: &str) -> Option<bool> {
+		self.show_password_dialog(&format!("{} [y/N]", question), true)
+			.map(|s| s.starts_with(&['Y', 'y', 'j', 'J'][..]))
+	}
+}
+
+/// Adapter mapping PAM conversations to a [`GuiPrompter`]
+///
+/// Prompts are passed to [`GuiPrompter::show_password_dialog()`], messages
+/// to [`GuiPrompter::show_message()`] and radio prompts to
+/// [`GuiPrompter::confirm()`]. A cancelled dialog fails the conversation
+/// and is reported as a [user cancellation][`crate::user_cancelled()`].
+/// Binary prompts are not supported.
+///
+/// See [`GuiPrompter`] for the threading requirements.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{GuiConversation, GuiPrompter};
+///
+/// struct Dialogs;
+///
+/// impl GuiPrompter for Dialogs {
+///     fn show_password_dialog(&mut self, prompt: &str, echo: bool) -> Option<String> {
+///         // Show a modal dialog and wait for the input...
+///         # let _ = (prompt, echo);
+///         Some("secret".to_owned())
+///     }
+///     fn show_message(&mut self, msg: &str, is_error: bool) {
+///         // Show a notification...
+///         # let _ = (msg, is_error);
+///     }
+/// }
+///
+/// let handler = GuiConversation::new(Dialogs);
+/// # let context = pam_client::Context::new("test", None, handler).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GuiConversation<P> {
+	prompter: P,
+}
+
+impl<P> GuiConversation<P> {
+	/// Wraps a GUI prompter
+	#[must_use]
+	pub const fn new(prompter: P) -> Self {
+		Self { prompter }
+	}
+
+	/// Returns a reference to the wrapped prompter
+	#[must_use]
+	pub const fn prompter(&self) -> &P {
+		&self.prompter
+	}
+
+	/// Returns a mutable reference to the wrapped prompter
+	pub fn prompter_mut(&mut self) -> &mut P {
+		&mut self.prompter
+	}
+
+	/// Unwraps the wrapped prompter
+	#[must_use]
+	pub fn into_inner(self) -> P {
+		self.prompter
+	}
+}
+
+impl<P: GuiPrompter> GuiConversation<P> {
+	/// Shows a text input dialog and converts the answer.
+	fn ask(&mut self, prompt: &CStr, echo: bool) -> Result<CString, ErrorCode> {
+		match self
+			.prompter
+			.show_password_dialog(&prompt.to_string_lossy(), echo)
+		{
+			Some(answer) => Ok(CString::new(answer)?),
+			None => Err(user_cancelled()),
+		}
+	}
+}
+
+impl<P: GuiPrompter> ConversationHandler for GuiConversation<P> {
+	fn prompt_echo_on(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.ask(prompt, true)
+	}
+
+	fn prompt_echo_off(&mut self, prompt: &CStr) -> Result<CString, ErrorCode> {
+		self.ask(prompt, false)
+	}
+
+	fn text_info(&mut self, msg: &CStr) {
+		self.prompter.show_message(&msg.to_string_lossy(), false);
+	}
+
+	fn error_msg(&mut self, msg: &CStr) {
+		self.prompter.show_message(&msg.to_string_lossy(), true);
+	}
+
+	fn radio_prompt(&mut self, prompt: &CStr) -> Result<bool, ErrorCode> {
+		self.prompter
+			.confirm(&prompt.to_string_lossy())
+			.ok_or_else(user_cancelled)
+	}
+
+	/// Always succeeds, as the dialogs can't be checked without showing
+	/// them to the user.
+	fn self_test(&mut self) -> Result<(), ErrorCode> {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::BinaryType;
+
+	/// Prompter answering from a fixed list and recording messages
+	#[derive(Default)]
+	struct Scripted {
+		answers: Vec<Option<String>>,
+		prompts: Vec<(String, bool)>,
+		messages: Vec<(String, bool)>,
+	}
+
+	impl GuiPrompter for Scripted {
+		fn show_password_dialog(&mut self, prompt: &str, echo: bool) -> Option<String> {
+			self.prompts.push((prompt.to_owned(), echo));
+			self.answers.remove(0)
+		}
+
+		fn show_message(&mut self, msg: &str, is_error: bool) {
+			self.messages.push((msg.to_owned(), is_error));
+		}
+	}
+
+	#[test]
+	fn test_adapter() {
+		let text = CString::new("test").unwrap();
+		let answers = vec![
+			Some("user".to_owned()),
+			Some("secret".to_owned()),
+			Some("nul\0".to_owned()),
+			None,
+			Some("yes".to_owned()),
+			Some("no".to_owned()),
+			None,
+		];
+		let mut c = GuiConversation::new(Scripted {
+			answers,
+			..Scripted::default()
+		});
+
+		assert_eq!(c.prompt_echo_on(&text).unwrap().to_str(), Ok("user"));
+		assert_eq!(c.prompt_echo_off(&text).unwrap().to_str(), Ok("secret"));
+		assert_eq!(c.prompt_echo_off(&text), Err(ErrorCode::CONV_ERR));
+		assert!(!crate::ffi::take_user_cancelled());
+		assert_eq!(c.prompt_echo_off(&text), Err(ErrorCode::CONV_ERR));
+		assert!(crate::ffi::take_user_cancelled());
+		assert_eq!(c.radio_prompt(&text), Ok(true));
+		assert_eq!(c.radio_prompt(&text), Ok(false));
+		assert_eq!(c.radio_prompt(&text), Err(ErrorCode::CONV_ERR));
+		assert!(crate::ffi::take_user_cancelled());
+		assert!(c.binary_prompt(BinaryType::Ok, &[]).is_err());
+		assert_eq!(c.self_test(), Ok(()));
+		c.text_info(&text);
+		c.error_msg(&text);
+
+		let prompter = c.into_inner();
+		assert!(prompter.answers.is_empty());
+		assert_eq!(prompter.prompts[0], ("test".to_owned(), true));
+		assert_eq!(prompter.prompts[1], ("test".to_owned(), false));
+		assert_eq!(prompter.prompts[4], ("test [y/N]".to_owned(), true));
+		assert_eq!(prompter.prompts.len(), 7);
+		assert_eq!(
+			prompter.messages,
+			vec![("test".to_owned(), false), ("test".to_owned(), true)]
+		);
+	}
+}
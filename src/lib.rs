@@ -113,28 +113,64 @@
  * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
  ***********************************************************************/
 
+mod async_conversation;
+mod audit;
+mod auth_cache;
 mod c_box;
 mod context;
+pub mod conv_channel;
 #[cfg(feature = "cli")]
 pub mod conv_cli;
+pub mod conv_fd;
+pub mod conv_foreign;
+pub mod conv_map;
 pub mod conv_mock;
 pub mod conv_null;
+pub mod conv_raw;
 mod conversation;
+mod encoding;
 pub mod env_list;
 mod error;
+mod fail_delay;
 mod ffi;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod fixtures;
+mod gui_conversation;
+mod privileges;
 mod resp_buf;
+mod rhost;
+mod services;
 mod session;
+mod snapshot;
+mod threaded;
+mod tty;
+mod verify;
 
 #[macro_use]
 extern crate bitflags;
 use libc::{c_char, c_int};
+use std::convert::TryFrom;
 use std::ffi::CStr;
 
-pub use context::Context;
-pub use conversation::ConversationHandler;
-pub use error::{Error, ErrorWith};
-pub use session::{Session, SessionToken};
+pub use async_conversation::{AsyncConversationHandler, BlockingAdapter, ConvFuture};
+pub use audit::{AuditRecord, AuthAttempt};
+pub use auth_cache::AuthCache;
+pub use context::{AccountStatus, AuthOutcome, AuthStep, Context, Item};
+pub use conversation::{
+	user_cancelled, BinaryType, BoundedConversation, CancelableConversation, ConversationCanceller,
+	ConversationHandler, FallbackConversation, LoggingConversation, MsgStyle, PromptContext,
+	RateLimitedConversation, RecordingConversation, StyleFilterConversation,
+	DEFAULT_MAX_BINARY_LEN,
+};
+pub use encoding::{Encoding, EncodingPolicy};
+pub use error::{clear_message_resolver, set_message_resolver, Error, ErrorWith};
+pub use fail_delay::{DelayJitter, DelaySink, RecordingDelay, SeededJitter, SleepingDelay};
+pub use gui_conversation::{GuiConversation, GuiPrompter};
+pub use services::{list_services, list_services_in, validate_service};
+pub use session::{Session, SessionToken, SessionTransaction};
+pub use snapshot::ItemsSnapshot;
+pub use threaded::{AuthHandle, AuthResult, ThreadedAuthenticator};
+pub use verify::{authenticate_multi, verify_password, verify_password_attempt};
 
 use enum_repr::EnumRepr;
 use pam_sys::*;
@@ -156,7 +192,7 @@ bitflags! {
 	#[allow(clippy::upper_case_acronyms)]
 	#[repr(transparent)]
 	#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
-	#[derive(Copy, Clone)]
+	#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 	pub struct Flag: c_int {
 		/// Don't generate any messages
 		const SILENT = PAM_SILENT as c_int;
@@ -213,6 +249,20 @@ pub enum ErrorCode {
 	AUTHTOK_RECOVERY_ERR = PAM_AUTHTOK_RECOVERY_ERR as c_int,
 	AUTHTOK_LOCK_BUSY = PAM_AUTHTOK_LOCK_BUSY as c_int,
 	AUTHTOK_DISABLE_AGING = PAM_AUTHTOK_DISABLE_AGING as c_int,
+	/// No module of the stack had an opinion on the request.
+	///
+	/// Modules return `PAM_IGNORE` to be skipped, and PAM libraries usually
+	/// turn a stack where every module was skipped into `PERM_DENIED`.
+	/// Some implementations and configurations (e.g. OpenPAM, or stacks
+	/// consisting only of `optional` modules) may pass it on to the
+	/// application instead.
+	///
+	/// Operations that don't make a security decision – refreshing,
+	/// reinitializing or deleting credentials and closing sessions – treat
+	/// it as success, as there was simply nothing to do. All others, most
+	/// importantly [`Context::authenticate()`] and
+	/// [`Context::acct_mgmt()`], fail closed and return it as an error.
+	IGNORE = PAM_IGNORE as c_int,
 	ABORT = PAM_ABORT as c_int,
 	AUTHTOK_EXPIRED = PAM_AUTHTOK_EXPIRED as c_int,
 	MODULE_UNKNOWN = PAM_MODULE_UNKNOWN as c_int,
@@ -221,6 +271,66 @@ pub enum ErrorCode {
 	INCOMPLETE = PAM_INCOMPLETE as c_int,
 }
 
+/// Conversion of a raw PAM return code into an [`ErrorCode`].
+///
+/// Fails with the unchanged value for unknown codes and `PAM_SUCCESS`.
+///
+/// ```rust
+/// # use std::convert::TryFrom;
+/// # use pam_client::ErrorCode;
+/// let code = ErrorCode::ABORT as libc::c_int;
+/// assert_eq!(ErrorCode::try_from(code), Ok(ErrorCode::ABORT));
+/// assert_eq!(ErrorCode::try_from(0), Err(0));
+/// ```
+impl TryFrom<c_int> for ErrorCode {
+	type Error = c_int;
+
+	fn try_from(code: c_int) -> std::result::Result<Self, c_int> {
+		Self::from_repr(code).ok_or(code)
+	}
+}
+
+impl ErrorCode {
+	/// Returns the symbolic name of the code, e.g. `"AUTH_ERR"`.
+	///
+	/// Unlike the messages of [`Error`], the name doesn't depend on the PAM
+	/// library or locale, so it is suitable for machine-parseable logs.
+	#[must_use]
+	pub const fn code_name(&self) -> &'static str {
+		match self {
+			ErrorCode::OPEN_ERR => "OPEN_ERR",
+			ErrorCode::SYMBOL_ERR => "SYMBOL_ERR",
+			ErrorCode::SERVICE_ERR => "SERVICE_ERR",
+			ErrorCode::SYSTEM_ERR => "SYSTEM_ERR",
+			ErrorCode::BUF_ERR => "BUF_ERR",
+			ErrorCode::PERM_DENIED => "PERM_DENIED",
+			ErrorCode::AUTH_ERR => "AUTH_ERR",
+			ErrorCode::CRED_INSUFFICIENT => "CRED_INSUFFICIENT",
+			ErrorCode::AUTHINFO_UNAVAIL => "AUTHINFO_UNAVAIL",
+			ErrorCode::USER_UNKNOWN => "USER_UNKNOWN",
+			ErrorCode::MAXTRIES => "MAXTRIES",
+			ErrorCode::NEW_AUTHTOK_REQD => "NEW_AUTHTOK_REQD",
+			ErrorCode::ACCT_EXPIRED => "ACCT_EXPIRED",
+			ErrorCode::SESSION_ERR => "SESSION_ERR",
+			ErrorCode::CRED_UNAVAIL => "CRED_UNAVAIL",
+			ErrorCode::CRED_EXPIRED => "CRED_EXPIRED",
+			ErrorCode::CRED_ERR => "CRED_ERR",
+			ErrorCode::CONV_ERR => "CONV_ERR",
+			ErrorCode::AUTHTOK_ERR => "AUTHTOK_ERR",
+			ErrorCode::AUTHTOK_RECOVERY_ERR => "AUTHTOK_RECOVERY_ERR",
+			ErrorCode::AUTHTOK_LOCK_BUSY => "AUTHTOK_LOCK_BUSY",
+			ErrorCode::AUTHTOK_DISABLE_AGING => "AUTHTOK_DISABLE_AGING",
+			ErrorCode::IGNORE => "IGNORE",
+			ErrorCode::ABORT => "ABORT",
+			ErrorCode::AUTHTOK_EXPIRED => "AUTHTOK_EXPIRED",
+			ErrorCode::MODULE_UNKNOWN => "MODULE_UNKNOWN",
+			ErrorCode::BAD_ITEM => "BAD_ITEM",
+			ErrorCode::CONV_AGAIN => "CONV_AGAIN",
+			ErrorCode::INCOMPLETE => "INCOMPLETE",
+		}
+	}
+}
+
 /// Type alias for the result of most PAM methods.
 pub type Result<T> = std::result::Result<T, Error>;
 /// Type alias for the result of PAM methods that pass back a consumed struct
@@ -0,0 +1,189 @@
+//! Temporary dropping of process privileges
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+use crate::error::{Error, ErrorCode};
+use crate::Result;
+
+use libc::{gid_t, uid_t};
+use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::{process, ptr};
+
+/// Looks up the numeric user and primary group id of a user.
+pub(crate) fn lookup_user(name: &str) -> Result<(uid_t, gid_t)> {
+	let c_name = CString::new(name)?;
+	let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+	let mut result: *mut libc::passwd = ptr::null_mut();
+	let mut buffer = vec![0 as libc::c_char; 1024];
+	loop {
+		let status = unsafe {
+			libc::getpwnam_r(
+				c_name.as_ptr(),
+				passwd.as_mut_ptr(),
+				buffer.as_mut_ptr(),
+				buffer.len(),
+				&mut result,
+			)
+		};
+		if status == libc::ERANGE && buffer.len() < 1024 * 1024 {
+			buffer.resize(buffer.len() * 2, 0);
+			continue;
+		}
+		if status != 0 {
+			// `getpwnam_r` returns the error number instead of setting `errno`
+			let code = if status == libc::ERANGE {
+				ErrorCode::BUF_ERR
+			} else {
+				ErrorCode::SYSTEM_ERR
+			};
+			return Err(Error::with_message(
+				code,
+				format!(
+					"looking up user \"{}\" failed: {}",
+					name,
+					io::Error::from_raw_os_error(status)
+				),
+			));
+		}
+		if result.is_null() {
+			return Err(Error::with_message(
+				ErrorCode::USER_UNKNOWN,
+				format!("user \"{}\" not found in the password database", name),
+			));
+		}
+		// Safety: `getpwnam_r` succeeded, so `passwd` is initialized
+		let passwd = unsafe { passwd.assume_init() };
+		return Ok((passwd.pw_uid, passwd.pw_gid));
+	}
+}
+
+/// Builds an error for a failed privilege change from `errno`.
+fn privilege_error(action: &str) -> Error {
+	Error::with_message(
+		ErrorCode::PERM_DENIED,
+		format!("{} failed: {}", action, io::Error::last_os_error()),
+	)
+}
+
+/// Guard holding temporarily dropped privileges.
+///
+/// Changes the effective user and group id and the supplementary groups
+/// on creation and restores the previous ones on drop. If restoring fails,
+/// the process is aborted, as continuing with the wrong privileges isn't
+/// safe.
+///
+/// The ids are process-wide, so this affects all threads of the process.
+pub(crate) struct PrivilegeGuard {
+	uid: uid_t,
+	gid: gid_t,
+	groups: Vec<gid_t>,
+}
+
+impl PrivilegeGuard {
+	/// Switches the effective ids to `uid` and `gid` and the supplementary
+	/// groups to the groups of `user`, or only to `gid` if `init_groups` is
+	/// false.
+	pub(crate) fn drop_to(user: &str, uid: uid_t, gid: gid_t, init_groups: bool) -> Result<Self> {
+		let c_user = CString::new(user)?;
+		let guard = Self {
+			uid: unsafe { libc::geteuid() },
+			gid: unsafe { libc::getegid() },
+			groups: current_groups()?,
+		};
+		// Order matters: groups can only be changed while still privileged.
+		// On errors, dropping `guard` restores the partially changed state.
+		if init_groups {
+			if unsafe { libc::initgroups(c_user.as_ptr(), gid as _) } != 0 {
+				return Err(privilege_error("initgroups"));
+			}
+		} else if unsafe { libc::setgroups(1, &gid) } != 0 {
+			return Err(privilege_error("setgroups"));
+		}
+		if unsafe { libc::setegid(gid) } != 0 {
+			return Err(privilege_error("setegid"));
+		}
+		if unsafe { libc::seteuid(uid) } != 0 {
+			return Err(privilege_error("seteuid"));
+		}
+		Ok(guard)
+	}
+
+	/// Restores the saved ids and groups, aborting on failure.
+	fn restore(&self) {
+		let restored = unsafe {
+			libc::seteuid(self.uid) == 0
+				&& libc::setegid(self.gid) == 0
+				&& libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) == 0
+		};
+		if !restored {
+			eprintln!(
+				"pam-client: restoring privileges failed ({}), aborting",
+				io::Error::last_os_error()
+			);
+			process::abort();
+		}
+	}
+}
+
+impl Drop for PrivilegeGuard {
+	fn drop(&mut self) {
+		self.restore();
+	}
+}
+
+/// Returns the supplementary groups of the process.
+fn current_groups() -> Result<Vec<gid_t>> {
+	let count = unsafe { libc::getgroups(0, ptr::null_mut()) };
+	if count < 0 {
+		return Err(privilege_error("getgroups"));
+	}
+	let mut groups = vec![0; count as usize];
+	let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+	if count < 0 {
+		return Err(privilege_error("getgroups"));
+	}
+	groups.truncate(count as usize);
+	Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_lookup() {
+		let (uid, gid) = lookup_user("root").unwrap();
+		assert_eq!((uid, gid), (0, 0));
+		let error = lookup_user("pam-client-nonexistent-user").unwrap_err();
+		assert_eq!(error.code(), ErrorCode::USER_UNKNOWN);
+		assert!(lookup_user("ro\0ot").is_err());
+	}
+
+	#[test]
+	fn test_guard() {
+		let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+		let groups = current_groups().unwrap();
+		for &init_groups in &[true, false] {
+			match PrivilegeGuard::drop_to("root", 0, 0, init_groups) {
+				Ok(guard) => {
+					assert_eq!(unsafe { libc::geteuid() }, 0);
+					if !init_groups {
+						assert_eq!(current_groups().unwrap(), vec![0]);
+					}
+					drop(guard);
+				}
+				Err(e) => assert_eq!(e.code(), ErrorCode::PERM_DENIED),
+			}
+		}
+		assert_eq!(unsafe { (libc::geteuid(), libc::getegid()) }, (uid, gid));
+		assert_eq!(current_groups().unwrap(), groups);
+	}
+}
@@ -27,6 +27,15 @@ pub(crate) struct ResponseBuffer {
 	items: CBox<[PamResponse]>,
 }
 
+/// Overwrites a buffer with zeros.
+///
+/// Volatile writes keep the compiler from optimizing the overwrite away.
+fn zero_bytes(bytes: &mut [u8]) {
+	for byte in bytes {
+		unsafe { ptr::write_volatile(byte, 0) };
+	}
+}
+
 impl ResponseBuffer {
 	/// Creates a new buffer for `len` PAM conversation responses.
 	///
@@ -89,10 +98,15 @@ impl ResponseBuffer {
 		// here, because `CString::as_ptr()` guarantees to point to a valid
 		// NULL-terminated string.
 		*dest = match response {
-			Some(text) => PamResponse {
-				resp: unsafe { strdup(text.as_ptr()) },
-				resp_retcode: 0,
-			},
+			Some(text) => {
+				let resp = unsafe { strdup(text.as_ptr()) };
+				// The response may be a secret, so don't leave it behind
+				zero_bytes(&mut text.into_bytes());
+				PamResponse {
+					resp,
+					resp_retcode: 0,
+				}
+			}
 			None => PamResponse {
 				resp: ptr::null_mut(),
 				resp_retcode: 0,
@@ -244,4 +258,11 @@ mod tests {
 		assert!(buffer[1].resp.is_null());
 		assert!(!buffer[2].resp.is_null());
 	}
+
+	#[test]
+	fn test_zero_bytes() {
+		let mut bytes = b"secret".to_vec();
+		zero_bytes(&mut bytes);
+		assert_eq!(bytes, [0; 6]);
+	}
 }
@@ -0,0 +1,130 @@
+//! Remote host names for `PAM_RHOST`
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+use libc::{c_char, sockaddr, socklen_t};
+use std::ffi::CStr;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::ptr;
+
+/// Size of the host name buffer (`NI_MAXHOST`)
+const MAX_HOST_LEN: usize = 1025;
+
+/// Determines the remote host name for a peer address.
+///
+/// With `resolve_dns` the name is looked up by reverse DNS; if that is
+/// disabled or fails, the textual IP address is returned. See
+/// [`Context::set_rhost_from_addr()`][`crate::Context::set_rhost_from_addr()`].
+pub(crate) fn host_from_addr(addr: &SocketAddr, resolve_dns: bool) -> String {
+	let ip = match addr.ip() {
+		// IPv4 clients on dual-stack sockets have IPv4-mapped addresses
+		IpAddr::V6(ip) if ip.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => {
+			ip.to_ipv4().map_or(IpAddr::V6(ip), IpAddr::V4)
+		}
+		ip => ip,
+	};
+	if resolve_dns {
+		if let Some(name) = reverse_lookup(&SocketAddr::new(ip, addr.port())) {
+			return name;
+		}
+	}
+	ip.to_string()
+}
+
+/// Looks up the host name of an address with `getnameinfo`.
+///
+/// Returns `None` if the address has no name.
+#[allow(clippy::cast_possible_truncation)]
+fn reverse_lookup(addr: &SocketAddr) -> Option<String> {
+	let mut buffer = [0 as c_char; MAX_HOST_LEN];
+	let status = match addr {
+		SocketAddr::V4(addr) => {
+			// Safety: all-zero is a valid `sockaddr_in`
+			let mut raw: libc::sockaddr_in = unsafe { mem::zeroed() };
+			#[cfg(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "freebsd",
+				target_os = "dragonfly",
+				target_os = "openbsd",
+				target_os = "netbsd"
+			))]
+			{
+				// BSD socket addresses carry their own length
+				raw.sin_len = mem::size_of::<libc::sockaddr_in>() as u8;
+			}
+			raw.sin_family = libc::AF_INET as libc::sa_family_t;
+			raw.sin_port = addr.port().to_be();
+			raw.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+			unsafe { name_info(&raw, &mut buffer) }
+		}
+		SocketAddr::V6(addr) => {
+			// Safety: all-zero is a valid `sockaddr_in6`
+			let mut raw: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+			#[cfg(any(
+				target_os = "macos",
+				target_os = "ios",
+				target_os = "freebsd",
+				target_os = "dragonfly",
+				target_os = "openbsd",
+				target_os = "netbsd"
+			))]
+			{
+				raw.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+			}
+			raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+			raw.sin6_port = addr.port().to_be();
+			raw.sin6_flowinfo = addr.flowinfo();
+			raw.sin6_addr.s6_addr = addr.ip().octets();
+			raw.sin6_scope_id = addr.scope_id();
+			unsafe { name_info(&raw, &mut buffer) }
+		}
+	};
+	if status != 0 {
+		return None;
+	}
+	// Safety: `getnameinfo` succeeded, so the buffer is null-terminated
+	let name = unsafe { CStr::from_ptr(buffer.as_ptr()) };
+	Some(name.to_string_lossy().into_owned())
+}
+
+/// Calls `getnameinfo` requiring a name for the socket address `raw`.
+///
+/// # Safety
+/// `T` must be a `sockaddr_*` struct matching its address family.
+#[allow(clippy::cast_possible_truncation)]
+unsafe fn name_info<T>(raw: &T, buffer: &mut [c_char]) -> libc::c_int {
+	libc::getnameinfo(
+		(raw as *const T).cast::<sockaddr>(),
+		mem::size_of::<T>() as socklen_t,
+		buffer.as_mut_ptr(),
+		buffer.len() as socklen_t,
+		ptr::null_mut(),
+		0,
+		libc::NI_NAMEREQD,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_host_from_addr() {
+		let v4: SocketAddr = "192.0.2.1:22".parse().unwrap();
+		assert_eq!(host_from_addr(&v4, false), "192.0.2.1");
+		let v6: SocketAddr = "[2001:db8::1]:22".parse().unwrap();
+		assert_eq!(host_from_addr(&v6, false), "2001:db8::1");
+		let mapped: SocketAddr = "[::ffff:192.0.2.1]:22".parse().unwrap();
+		assert_eq!(host_from_addr(&mapped, false), "192.0.2.1");
+		let loopback: SocketAddr = "[::1]:22".parse().unwrap();
+		assert_eq!(host_from_addr(&loopback, false), "::1");
+	}
+}
@@ -0,0 +1,224 @@
+//! Helpers for discovering configured PAM services
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::error::{Error, ErrorCode};
+use crate::Result;
+
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+/// Default directory containing the per-service PAM policy files.
+pub(crate) const DEFAULT_CONFDIR: &str = "/etc/pam.d";
+
+/// Directory with distribution defaults for the policy files, used by
+/// Linux-PAM if a service has no file in [`DEFAULT_CONFDIR`].
+const VENDOR_CONFDIR: &str = "/usr/lib/pam.d";
+
+/// Service whose policy is used for services without one.
+const FALLBACK_SERVICE: &str = "other";
+
+/// Legacy single PAM configuration file, used if [`DEFAULT_CONFDIR`]
+/// doesn't exist.
+pub(crate) const DEFAULT_CONFFILE: &str = "/etc/pam.conf";
+
+/// Lists the names of the PAM services configured in `/etc/pam.d`.
+///
+/// This is a convenience helper based on the filesystem, not on a PAM API
+/// call. Each regular file (or symlink to one) in the directory is reported
+/// as a service; directories and other entries are skipped. The names are
+/// returned in sorted order.
+///
+/// If `/etc/pam.d` doesn't exist (e.g. on systems only using
+/// `/etc/pam.conf`), an empty list is returned.
+///
+/// # Errors
+/// Returns the underlying I/O error if the directory exists but cannot be
+/// read.
+pub fn list_services() -> io::Result<Vec<String>> {
+	list_services_in(DEFAULT_CONFDIR)
+}
+
+/// Lists the names of the PAM services configured in `confdir`.
+///
+/// Like [`list_services()`], but for an alternative configuration
+/// directory.
+///
+/// # Errors
+/// Returns the underlying I/O error if the directory exists but cannot be
+/// read.
+pub fn list_services_in(confdir: impl AsRef<Path>) -> io::Result<Vec<String>> {
+	let entries = match fs::read_dir(confdir) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(e),
+	};
+
+	let mut result = Vec::new();
+	for entry in entries {
+		let entry = entry?;
+		// Follow symlinks, as distributions commonly link service files
+		let is_file = matches!(fs::metadata(entry.path()), Ok(m) if m.is_file());
+		if !is_file {
+			continue;
+		}
+		if let Ok(name) = entry.file_name().into_string() {
+			result.push(name);
+		}
+	}
+	result.sort();
+	Ok(result)
+}
+
+/// Checks if the legacy configuration file `conffile` has entries for
+/// `service`.
+fn conffile_has(service: &str, conffile: &Path) -> bool {
+	let file = match fs::File::open(conffile) {
+		Ok(file) => file,
+		Err(_) => return false,
+	};
+	BufReader::new(file).lines().any(|line| match line {
+		Ok(line) => line.split_whitespace().next() == Some(service),
+		Err(_) => false,
+	})
+}
+
+/// Determines the configuration file PAM reads for `service`.
+///
+/// Mirrors the lookup of Linux-PAM: with an explicit `confdir` only
+/// *confdir*`/`*service* and *confdir*`/other` are considered. Otherwise
+/// the service and then the `other` policy are looked up in `/etc/pam.d`
+/// and `/usr/lib/pam.d`, or in `/etc/pam.conf` if `/etc/pam.d` doesn't
+/// exist.
+pub(crate) fn resolve_config(service: &str, confdir: Option<&Path>) -> Option<PathBuf> {
+	find_config(&[service, FALLBACK_SERVICE], confdir)
+}
+
+/// Finds the configuration file for the first of `services` that has one,
+/// using the lookup of [`resolve_config()`].
+fn find_config(services: &[&str], confdir: Option<&Path>) -> Option<PathBuf> {
+	let dirs = match confdir {
+		Some(confdir) => vec![confdir],
+		None if Path::new(DEFAULT_CONFDIR).is_dir() => {
+			vec![Path::new(DEFAULT_CONFDIR), Path::new(VENDOR_CONFDIR)]
+		}
+		None => {
+			let conffile = Path::new(DEFAULT_CONFFILE);
+			return if services.iter().any(|name| conffile_has(name, conffile)) {
+				Some(conffile.to_path_buf())
+			} else {
+				None
+			};
+		}
+	};
+	services
+		.iter()
+		.flat_map(|name| dirs.iter().map(move |dir| dir.join(name)))
+		.find(|path| path.is_file())
+}
+
+/// Checks whether a policy for a PAM service is configured.
+///
+/// PAM usually doesn't fail on unknown services in
+/// [`Context::new()`][`crate::Context::new()`], but silently falls back to
+/// the `other` policy, so a typo in the service name only shows up later as
+/// a confusing authentication failure. Calling this function beforehand
+/// allows detecting a missing configuration early.
+///
+/// Looks for the file *service* in `/etc/pam.d` and in the distribution
+/// defaults in `/usr/lib/pam.d`, like Linux-PAM does. If `/etc/pam.d`
+/// doesn't exist, `/etc/pam.conf` is searched for entries of the service
+/// instead. Unlike PAM, the `other` policy is not accepted as a fallback.
+///
+/// # Errors
+/// - `ABORT` – No configuration for the service was found or the service
+///   name is invalid (empty or containing `/` or null bytes).
+pub fn validate_service(service: &str) -> Result<()> {
+	let valid_name = !service.is_empty() && !service.contains(|c| c == '/' || c == '\0');
+	if valid_name && find_config(&[service], None).is_some() {
+		Ok(())
+	} else {
+		Err(Error::with_message(
+			ErrorCode::ABORT,
+			format!("no PAM configuration found for service \"{}\"", service),
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_list_in() {
+		let dir = std::env::temp_dir().join(format!("pam-client-test-{}", std::process::id()));
+		fs::create_dir_all(dir.join("subdir")).unwrap();
+		fs::write(dir.join("login"), b"").unwrap();
+		fs::write(dir.join("sshd"), b"").unwrap();
+
+		let services = list_services_in(&dir);
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(services.unwrap(), vec!["login", "sshd"]);
+	}
+
+	#[test]
+	fn test_configured() {
+		let dir = std::env::temp_dir().join(format!("pam-client-test-conf-{}", std::process::id()));
+		let conffile = dir.join("pam.conf");
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("login"), b"").unwrap();
+		fs::write(&conffile, b"# comment\nsshd auth required pam_unix.so\n").unwrap();
+
+		let in_dir = find_config(&["login"], Some(&dir)).is_some();
+		let not_in_dir = find_config(&["sshd"], Some(&dir)).is_some();
+		let in_file = conffile_has("sshd", &conffile);
+		let not_in_file = conffile_has("login", &conffile);
+		fs::remove_dir_all(&dir).unwrap();
+		assert!(in_dir);
+		assert!(!not_in_dir);
+		assert!(in_file);
+		assert!(!not_in_file);
+	}
+
+	#[test]
+	fn test_resolve() {
+		let dir =
+			std::env::temp_dir().join(format!("pam-client-test-resolve-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let missing = resolve_config("login", Some(&dir));
+		fs::write(dir.join("other"), b"").unwrap();
+		let other = resolve_config("login", Some(&dir));
+		fs::write(dir.join("login"), b"").unwrap();
+		let login = resolve_config("login", Some(&dir));
+		fs::remove_dir_all(&dir).unwrap();
+		assert_eq!(missing, None);
+		assert_eq!(other, Some(dir.join("other")));
+		assert_eq!(login, Some(dir.join("login")));
+		let _ = resolve_config("login", None);
+	}
+
+	#[test]
+	fn test_validate() {
+		let error = validate_service("../passwd").unwrap_err();
+		assert_eq!(error.code(), ErrorCode::ABORT);
+		assert!(error.message().unwrap().contains("../passwd"));
+		assert!(validate_service("").is_err());
+		assert!(validate_service("pam-client-nonexistent-service").is_err());
+	}
+
+	#[test]
+	fn test_list_missing() {
+		let services = list_services_in("/nonexistent/pam.d").unwrap();
+		assert!(services.is_empty());
+		let _ = list_services();
+	}
+}
@@ -16,6 +16,7 @@ use crate::{ExtResult, Flag, Result};
 use pam_sys::{pam_close_session, pam_setcred};
 use std::ffi::OsStr;
 use std::mem::drop;
+use std::ops::{Deref, DerefMut};
 
 /// Token type to resume RAII handling of a session that was released with [`Session::leak()`].
 ///
@@ -29,6 +30,23 @@ pub enum SessionToken {
 }
 
 /// An active PAM session or pseudo session
+///
+/// A session mutably borrows the [`Context`] it was opened on, so the borrow
+/// checker prevents using the context in ways incompatible with an open
+/// session (e.g. authenticating again) until the session is closed, dropped
+/// or [leaked][`Self::leak()`]:
+///
+/// ```compile_fail
+/// # use pam_client::{Context, Flag};
+/// # let mut context = Context::new("dummy", None, pam_client::conv_null::Conversation::new()).unwrap();
+/// let session = context.open_session(Flag::NONE).unwrap();
+/// context.authenticate(Flag::NONE); // error: `context` is still borrowed
+/// drop(session);
+/// ```
+///
+/// Methods needed while the session is active are available on the
+/// session itself, read-only access to the PAM items is possible through
+/// [`context()`][`Self::context()`].
 #[must_use]
 pub struct Session<'a, ConvT> {
 	context: &'a mut Context<ConvT>,
@@ -42,6 +60,9 @@ where
 {
 	/// Constructs a `Session` object for a PAM context.
 	pub(crate) fn new(context: &'a mut Context<ConvT>, real: bool) -> Session<'a, ConvT> {
+		if real {
+			context.set_session_open(true);
+		}
 		Self {
 			context,
 			session_active: real,
@@ -64,12 +85,14 @@ where
 	/// - `ReturnCode::CRED_UNAVAIL`: Failed to retrieve credentials
 	/// - `ReturnCode::SYSTEM_ERR`: Other system error
 	/// - `ReturnCode::USER_UNKNOWN`: User not known
+	///
+	/// `IGNORE` from the module stack counts as success.
 	pub fn refresh_credentials(&mut self, flags: Flag) -> Result<()> {
-		self.context.wrap_pam_return(unsafe {
-			pam_setcred(
-				self.context.handle().into(),
-				(Flag::REFRESH_CRED | flags).bits(),
-			)
+		let context = &self.context;
+		context.traced("refresh_credentials", || {
+			context.wrap_pam_return_ignorable(unsafe {
+				pam_setcred(context.handle().into(), (Flag::REFRESH_CRED | flags).bits())
+			})
 		})
 	}
 
@@ -79,12 +102,7 @@ where
 	///
 	/// See [`Context::reinitialize_credentials()`] for more information.
 	pub fn reinitialize_credentials(&mut self, flags: Flag) -> Result<()> {
-		self.context.wrap_pam_return(unsafe {
-			pam_setcred(
-				self.context.handle().into(),
-				(Flag::REINITIALIZE_CRED | flags).bits(),
-			)
-		})
+		self.context.reinitialize_credentials(flags)
 	}
 
 	/// Converts the session into a [`SessionToken`] without closing it.
@@ -111,6 +129,15 @@ where
 		result
 	}
 
+	/// Returns a shared reference to the context of this session.
+	///
+	/// Allows reading PAM items like [`Context::user()`] while the session
+	/// is active.
+	#[must_use]
+	pub fn context(&self) -> &Context<ConvT> {
+		self.context
+	}
+
 	/// Returns the value of a PAM environment variable.
 	///
 	/// See [`Context::getenv()`].
@@ -157,6 +184,8 @@ where
 	/// - `ReturnCode::CRED_ERR`: Deleting credentials failed
 	/// - `ReturnCode::SYSTEM_ERR`: Other system error
 	///
+	/// `IGNORE` from the module stack counts as success.
+	///
 	/// The ownership of `self` is passed back in the error payload.
 	/// On drop the session will once again try to close itself.
 	#[rustversion::attr(since(1.48), doc(alias = "pam_close_session"))]
@@ -164,14 +193,15 @@ where
 		let handle = self.context.handle().as_ptr();
 		if self.session_active {
 			let status = unsafe { pam_close_session(handle, flags.bits()) };
-			if let Err(e) = self.context.wrap_pam_return(status) {
+			if let Err(e) = self.context.wrap_pam_return_ignorable(status) {
 				return Err(e.into_with_payload(self));
 			}
 			self.session_active = false;
+			self.context.set_session_open(false);
 		}
 		if self.credentials_active {
 			let status = unsafe { pam_setcred(handle, (Flag::DELETE_CRED | flags).bits()) };
-			if let Err(e) = self.context.wrap_pam_return(status) {
+			if let Err(e) = self.context.wrap_pam_return_ignorable(status) {
 				return Err(e.into_with_payload(self));
 			}
 			self.credentials_active = false;
@@ -187,6 +217,7 @@ impl<'a, ConvT> Drop for Session<'a, ConvT> {
 		if self.session_active {
 			let status = unsafe { pam_close_session(handle, Flag::NONE.bits()) };
 			self.session_active = false;
+			self.context.set_session_open(false);
 			drop(self.context.wrap_pam_return(status));
 		}
 		if self.credentials_active {
@@ -197,6 +228,62 @@ impl<'a, ConvT> Drop for Session<'a, ConvT> {
 	}
 }
 
+/// A session opened together with the account validation in one step.
+///
+/// Created by [`Context::open_session_transaction()`], which validates the
+/// account, establishes the credentials and opens the session. If any of
+/// these steps fails, the previous steps are rolled back before the error
+/// is returned.
+///
+/// On drop the session is closed and the credentials are deleted, like with
+/// a plain [`Session`], which this type dereferences to.
+#[must_use]
+pub struct SessionTransaction<'a, ConvT>(Session<'a, ConvT>);
+
+impl<'a, ConvT> SessionTransaction<'a, ConvT>
+where
+	ConvT: ConversationHandler,
+{
+	/// Performs the setup sequence of a `SessionTransaction`.
+	pub(crate) fn begin(
+		context: &'a mut Context<ConvT>,
+		flags: Flag,
+	) -> Result<SessionTransaction<'a, ConvT>> {
+		context.acct_mgmt(flags)?;
+		// Only `SILENT` is relevant for session opening
+		let session = context.open_session(flags & Flag::SILENT)?;
+		Ok(Self(session))
+	}
+
+	/// Converts the transaction into the underlying [`Session`].
+	pub fn into_session(self) -> Session<'a, ConvT> {
+		self.0
+	}
+
+	/// Manually closes the session and deletes the credentials.
+	///
+	/// See [`Session::close()`].
+	pub fn close(self, flags: Flag) -> ExtResult<(), Self> {
+		self.0.close(flags).map_err(|e| e.map(Self))
+	}
+}
+
+impl<'a, ConvT> Deref for SessionTransaction<'a, ConvT> {
+	type Target = Session<'a, ConvT>;
+
+	#[inline]
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl<'a, ConvT> DerefMut for SessionTransaction<'a, ConvT> {
+	#[inline]
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		&mut self.0
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -212,6 +299,7 @@ mod tests {
 		)
 		.unwrap();
 		let mut session = context.unleak_session(token);
+		assert_eq!(session.context().user().unwrap(), "user");
 		let _ = session.putenv("TEST=1");
 		let _ = session.getenv("TEST");
 		let _ = session.envlist();
@@ -0,0 +1,36 @@
+//! Snapshots of PAM items for diagnostics
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+/// The readable string items of a PAM context at one point in time
+///
+/// Created with [`Context::items_snapshot()`][`crate::Context::items_snapshot()`].
+/// Authentication tokens are never included. Items that are not set or
+/// cannot be read are `None`.
+///
+/// With the `"serde"` feature this struct is serializable, e.g. to dump
+/// the context state as JSON when troubleshooting.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ItemsSnapshot {
+	/// The service name
+	pub service: Option<String>,
+	/// The target user
+	pub user: Option<String>,
+	/// The prompt for the username
+	pub user_prompt: Option<String>,
+	/// The terminal name
+	pub tty: Option<String>,
+	/// The requesting user name
+	pub ruser: Option<String>,
+	/// The requesting hostname
+	pub rhost: Option<String>,
+}
@@ -0,0 +1,298 @@
+//! Authentication on a bounded pool of worker threads
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::error::{Error, ErrorCode};
+use crate::{Context, ConversationHandler, ExtResult, Flag};
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Type of the jobs run by the worker threads
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Result of an authentication on a [`ThreadedAuthenticator`]
+pub type AuthResult<ConvT> = ExtResult<Context<ConvT>, Context<ConvT>>;
+
+/// Pool of worker threads running [`Context::authenticate()`]
+///
+/// PAM calls block and may be heavy (e.g. password hashing or network
+/// requests), so servers usually want to run them outside of their main
+/// threads while capping how many run concurrently. This pool runs at most
+/// `threads` authentications at once and queues up to `queue_len` more.
+///
+/// # Backpressure
+///
+/// When all threads are busy and the queue is full,
+/// [`submit()`][`Self::submit()`] blocks until a slot becomes free, while
+/// [`try_submit()`][`Self::try_submit()`] returns the context immediately,
+/// e.g. to reject the request.
+///
+/// Dropping the pool waits for all submitted authentications to finish.
+///
+/// # Examples
+/// ```rust
+/// use pam_client::{Context, Flag, ThreadedAuthenticator};
+/// use pam_client::conv_mock::Conversation;
+///
+/// let pool = ThreadedAuthenticator::new(4, 16);
+/// let context = Context::new("test", None, Conversation::with_credentials("user", "pass")).unwrap();
+/// let handle = pool.submit(context, Flag::NONE);
+/// // ... do something else ...
+/// match handle.join() {
+///     Ok(context) => println!("Authenticated {:?}", context.user()),
+///     Err(e) => println!("Authentication failed: {}", e),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ThreadedAuthenticator {
+	sender: Option<SyncSender<Job>>,
+	workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl ThreadedAuthenticator {
+	/// Creates a pool of `threads` worker threads with room for `queue_len`
+	/// waiting authentications.
+	///
+	/// # Panics
+	/// Panics if `threads` is zero or a thread cannot be spawned.
+	#[must_use]
+	pub fn new(threads: usize, queue_len: usize) -> Self {
+		assert!(threads > 0, "a thread pool needs at least one thread");
+		let (sender, receiver) = mpsc::sync_channel::<Job>(queue_len);
+		let receiver = Arc::new(Mutex::new(receiver));
+		let workers = (0..threads)
+			.map(|i| {
+				let receiver = Arc::clone(&receiver);
+				thread::Builder::new()
+					.name(format!("pam-auth-{}", i))
+					.spawn(move || worker(&receiver))
+					.expect("Spawning a PAM worker thread failed")
+			})
+			.collect();
+		Self {
+			sender: Some(sender),
+			workers,
+		}
+	}
+
+	/// The number of worker threads
+	#[must_use]
+	pub fn threads(&self) -> usize {
+		self.workers.len()
+	}
+
+	/// Queues an authentication, blocking while the queue is full.
+	pub fn submit<ConvT>(&self, context: Context<ConvT>, flags: Flag) -> AuthHandle<ConvT>
+	where
+		ConvT: ConversationHandler + Send + 'static,
+	{
+		let (job, handle, _) = make_job(context, flags);
+		// The receiver lives as long as the workers, which outlive `self`
+		let _ = self.sender().send(job);
+		handle
+	}
+
+	/// Queues an authentication if there is room in the queue.
+	///
+	/// # Errors
+	/// Returns the context unchanged if the queue is full.
+	pub fn try_submit<ConvT>(
+		&self,
+		context: Context<ConvT>,
+		flags: Flag,
+	) -> Result<AuthHandle<ConvT>, Context<ConvT>>
+	where
+		ConvT: ConversationHandler + Send + 'static,
+	{
+		let (job, handle, slot) = make_job(context, flags);
+		match self.sender().try_send(job) {
+			Ok(()) => Ok(handle),
+			// The rejected job never ran, so the context is still there
+			Err(_) => match take_slot(&slot) {
+				Some(context) => Err(context),
+				None => unreachable!("rejected jobs never run"),
+			},
+		}
+	}
+
+	/// Internal: The sending end of the job queue
+	fn sender(&self) -> &SyncSender<Job> {
+		self.sender
+			.as_ref()
+			.expect("Invalid state: sender is only taken on drop")
+	}
+}
+
+impl Drop for ThreadedAuthenticator {
+	fn drop(&mut self) {
+		// Closing the queue makes the workers exit after the remaining jobs
+		drop(self.sender.take());
+		for worker in self.workers.drain(..) {
+			let _ = worker.join();
+		}
+	}
+}
+
+/// Handle to a queued authentication
+///
+/// Returned by [`ThreadedAuthenticator::submit()`]. The context is passed
+/// back on completion, in the error payload on failure.
+#[derive(Debug)]
+pub struct AuthHandle<ConvT> {
+	receiver: Receiver<AuthResult<ConvT>>,
+}
+
+impl<ConvT> AuthHandle<ConvT> {
+	/// Waits for the authentication to finish.
+	///
+	/// # Errors
+	/// Same as [`Context::authenticate()`] with the context in the error
+	/// payload. If the authentication panicked, `ABORT` is returned without
+	/// payload.
+	pub fn join(self) -> AuthResult<ConvT> {
+		self.receiver.recv().unwrap_or_else(|_| Err(aborted()))
+	}
+
+	/// Returns the result if the authentication finished, without waiting.
+	pub fn try_join(&self) -> Option<AuthResult<ConvT>> {
+		match self.receiver.try_recv() {
+			Ok(result) => Some(result),
+			Err(TryRecvError::Empty) => None,
+			Err(TryRecvError::Disconnected) => Some(Err(aborted())),
+		}
+	}
+}
+
+/// Internal: Error for authentications that panicked
+fn aborted<T>() -> crate::ErrorWith<T> {
+	Error::with_message(ErrorCode::ABORT, "the authentication panicked").into()
+}
+
+/// Internal: Authenticates on `context`, passing the context back.
+fn authenticate<ConvT>(mut context: Context<ConvT>, flags: Flag) -> AuthResult<ConvT>
+where
+	ConvT: ConversationHandler,
+{
+	match context.authenticate(flags) {
+		Ok(()) => Ok(context),
+		Err(e) => Err(e.into_with_payload(context)),
+	}
+}
+
+/// Internal: Shared storage of a context until its job runs
+type Slot<ConvT> = Arc<Mutex<Option<Context<ConvT>>>>;
+
+/// Internal: Takes the context out of a slot.
+fn take_slot<ConvT>(slot: &Slot<ConvT>) -> Option<Context<ConvT>> {
+	match slot.lock() {
+		Ok(mut guard) => guard.take(),
+		Err(poisoned) => poisoned.into_inner().take(),
+	}
+}
+
+/// Internal: Creates a job authenticating `context`, its handle and the
+/// slot holding the context until the job runs.
+fn make_job<ConvT>(context: Context<ConvT>, flags: Flag) -> (Job, AuthHandle<ConvT>, Slot<ConvT>)
+where
+	ConvT: ConversationHandler + Send + 'static,
+{
+	let slot = Arc::new(Mutex::new(Some(context)));
+	let job_slot = Arc::clone(&slot);
+	let (sender, receiver) = mpsc::channel();
+	let job = Box::new(move || {
+		if let Some(context) = take_slot(&job_slot) {
+			let _ = sender.send(authenticate(context, flags));
+		}
+	});
+	(job, AuthHandle { receiver }, slot)
+}
+
+/// Internal: Main loop of the worker threads
+fn worker(receiver: &Mutex<Receiver<Job>>) {
+	loop {
+		let job = match receiver.lock() {
+			Ok(receiver) => receiver.recv(),
+			Err(poisoned) => poisoned.into_inner().recv(),
+		};
+		match job {
+			// A panic drops the result sender, so the handle reports it
+			Ok(job) => drop(panic::catch_unwind(AssertUnwindSafe(job))),
+			Err(_) => return,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::conv_mock::Conversation;
+
+	fn new_context() -> Context<Conversation> {
+		Context::new("test", None, Conversation::with_credentials("user", "pass")).unwrap()
+	}
+
+	#[test]
+	fn test_submit() {
+		let pool = ThreadedAuthenticator::new(2, 1);
+		assert_eq!(pool.threads(), 2);
+		assert!(format!("{:?}", pool).contains("ThreadedAuthenticator"));
+		let handles: Vec<_> = (0..4)
+			.map(|_| pool.submit(new_context(), Flag::SILENT))
+			.collect();
+		for handle in handles {
+			let context = match handle.join() {
+				Ok(context) => context,
+				Err(mut e) => e.take_payload().unwrap(),
+			};
+			assert_eq!(context.user().unwrap(), "user");
+		}
+	}
+
+	#[test]
+	fn test_try_submit() {
+		let pool = ThreadedAuthenticator::new(1, 0);
+		let mut handles = Vec::new();
+		let mut rejected = 0;
+		for _ in 0..8 {
+			match pool.try_submit(new_context(), Flag::SILENT) {
+				Ok(handle) => handles.push(handle),
+				Err(context) => {
+					assert_eq!(context.service().unwrap(), "test");
+					rejected += 1;
+				}
+			}
+		}
+		assert_eq!(handles.len() + rejected, 8);
+		for handle in handles {
+			while handle.try_join().is_none() {
+				thread::yield_now();
+			}
+		}
+	}
+
+	#[test]
+	fn test_aborted() {
+		let (sender, receiver) = mpsc::channel::<AuthResult<Conversation>>();
+		drop(sender);
+		let handle = AuthHandle { receiver };
+		match handle.try_join() {
+			Some(Err(e)) => assert_eq!(e.code(), ErrorCode::ABORT),
+			_ => panic!("expected an error"),
+		}
+		match handle.join() {
+			Err(e) => assert_eq!(e.code(), ErrorCode::ABORT),
+			Ok(_) => panic!("expected an error"),
+		}
+	}
+}
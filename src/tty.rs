@@ -0,0 +1,85 @@
+//! Normalization of terminal names for `PAM_TTY`
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::error::{Error, ErrorCode};
+use crate::Result;
+
+/// Prefix of terminal device paths
+const DEV_PREFIX: &str = "/dev/";
+
+/// Normalizes a terminal name to the form `pam_securetty` expects.
+///
+/// See [`Context::set_tty_device()`][`crate::Context::set_tty_device()`]
+/// for the rules.
+pub(crate) fn normalize_tty(tty: &str) -> Result<&str> {
+	let invalid = |reason: &str| {
+		Err(Error::with_message(
+			ErrorCode::BAD_ITEM,
+			format!(
+				"invalid terminal name \"{}\": {}",
+				tty.escape_debug(),
+				reason
+			),
+		))
+	};
+	// X displays like ":0" or "host:0.0" are passed on unchanged
+	if tty.contains(':') && !tty.starts_with('/') {
+		return Ok(tty);
+	}
+	let name = if let Some(name) = tty.strip_prefix(DEV_PREFIX) {
+		name
+	} else if tty.starts_with('/') {
+		return invalid("not a path below /dev");
+	} else {
+		tty
+	};
+	if name.is_empty() {
+		invalid("empty name")
+	} else if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+		invalid("contains whitespace or control characters")
+	} else if name
+		.split('/')
+		.any(|part| part.is_empty() || part == "." || part == "..")
+	{
+		invalid("malformed path")
+	} else {
+		Ok(name)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_normalize() {
+		assert_eq!(normalize_tty("tty1").unwrap(), "tty1");
+		assert_eq!(normalize_tty("/dev/tty1").unwrap(), "tty1");
+		assert_eq!(normalize_tty("/dev/pts/3").unwrap(), "pts/3");
+		assert_eq!(normalize_tty("pts/3").unwrap(), "pts/3");
+		assert_eq!(normalize_tty(":0").unwrap(), ":0");
+		assert_eq!(normalize_tty("host:0.0").unwrap(), "host:0.0");
+		for invalid in &[
+			"",
+			"/dev/",
+			"/tmp/tty1",
+			"tty 1",
+			"tty1\n",
+			"/dev/../etc/passwd",
+			"pts//3",
+			"pts/",
+		] {
+			let error = normalize_tty(invalid).unwrap_err();
+			assert_eq!(error.code(), ErrorCode::BAD_ITEM);
+		}
+	}
+}
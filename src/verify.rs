@@ -0,0 +1,167 @@
+//! Simple password verification
+
+/***********************************************************************
+ * (c) 2021-2022 Christoph Grenz <christophg+gitorious @ grenz-bonn.de>*
+ *                                                                     *
+ * This Source Code Form is subject to the terms of the Mozilla Public *
+ * License, v. 2.0. If a copy of the MPL was not distributed with this *
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.            *
+ ***********************************************************************/
+
+#![forbid(unsafe_code)]
+
+use crate::conv_mock::Conversation;
+use crate::error::ErrorCode;
+use crate::{AuthAttempt, Context, Flag, Result};
+
+/// Checks a user's password.
+///
+/// The simplest entry point of this library: starts a PAM transaction for
+/// `service`, authenticates `user` with `password` and ends the transaction
+/// again. No account validation is done and no session is opened, which
+/// makes this suitable for password checks like unlocking a screensaver.
+///
+/// Returns `Ok(false)` if the password is wrong or the user is unknown.
+/// Messages of the PAM modules are discarded.
+///
+/// ```no_run
+/// if pam_client::verify_password("login", "user", "password")? {
+///     println!("Welcome back!");
+/// }
+/// # Ok::<(), pam_client::Error>(())
+/// ```
+///
+/// # Errors
+/// All errors of [`Context::new()`] and [`Context::authenticate()`] except
+/// `AUTH_ERR` and `USER_UNKNOWN`, e.g. `MAXTRIES` or `AUTHINFO_UNAVAIL`.
+/// Additionally `BUF_ERR` is returned if an argument contains a null byte.
+pub fn verify_password(service: &str, user: &str, password: &str) -> Result<bool> {
+	let mut context = Context::new(
+		service,
+		Some(user),
+		Conversation::with_credentials(user, password),
+	)?;
+	match context.authenticate(Flag::SILENT) {
+		Ok(()) => Ok(true),
+		Err(e) if matches!(e.code(), ErrorCode::AUTH_ERR | ErrorCode::USER_UNKNOWN) => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+/// Checks a user's password and records the attempt.
+///
+/// Variant of [`verify_password()`] for audit pipelines: instead of a
+/// plain yes/no answer an [`AuthAttempt`] with the outcome, the duration
+/// and the messages of the PAM modules is returned. The password is not
+/// part of the record.
+///
+/// ```no_run
+/// let attempt = pam_client::verify_password_attempt("login", "user", "password")?;
+/// if !attempt.is_success() {
+///     eprintln!("Failed attempt for {:?}: {:?}", attempt.user, attempt.outcome);
+/// }
+/// # Ok::<(), pam_client::Error>(())
+/// ```
+///
+/// # Errors
+/// All errors of [`Context::new()`]. Failures of the authentication itself
+/// are recorded in [`AuthAttempt::outcome`].
+pub fn verify_password_attempt(service: &str, user: &str, password: &str) -> Result<AuthAttempt> {
+	let mut context = Context::new(
+		service,
+		Some(user),
+		Conversation::with_credentials(user, password),
+	)?;
+	Ok(context.authenticate_attempt(Flag::SILENT))
+}
+
+/// Authenticates a user against several services independently.
+///
+/// Runs a separate PAM transaction for each of `services`, authenticating
+/// `user` with `password` like [`verify_password()`], and returns the
+/// result per service in the same order. Each context is ended before the
+/// next service is tried, and a failure for one service doesn't affect the
+/// others. As with `verify_password()`, no account validation is done and
+/// messages of the PAM modules are discarded.
+///
+/// Useful for tools aggregating policies, e.g. to check whether a user
+/// could log in to several services.
+///
+/// ```no_run
+/// for (service, result) in pam_client::authenticate_multi(&["login", "sshd"], "user", "password") {
+///     match result {
+///         Ok(()) => println!("{}: ok", service),
+///         Err(e) => println!("{}: {}", service, e),
+///     }
+/// }
+/// ```
+#[must_use]
+pub fn authenticate_multi(
+	services: &[&str],
+	user: &str,
+	password: &str,
+) -> Vec<(String, Result<()>)> {
+	services
+		.iter()
+		.map(|service| {
+			let result = Context::new(
+				service,
+				Some(user),
+				Conversation::with_credentials(user, password),
+			)
+			.and_then(|mut context| context.authenticate(Flag::SILENT));
+			((*service).to_owned(), result)
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_verify_password() {
+		match verify_password("test", "pam-client-nonexistent-user", "password") {
+			Ok(valid) => assert!(!valid),
+			Err(e) => assert!(!matches!(
+				e.code(),
+				ErrorCode::AUTH_ERR | ErrorCode::USER_UNKNOWN
+			)),
+		}
+		assert_eq!(
+			verify_password("test", "us\0er", "password")
+				.unwrap_err()
+				.code(),
+			ErrorCode::BUF_ERR
+		);
+	}
+
+	#[test]
+	fn test_verify_password_attempt() {
+		let attempt =
+			verify_password_attempt("test", "pam-client-nonexistent-user", "password").unwrap();
+		assert_eq!(attempt.service, "test");
+		assert_eq!(attempt.user.as_deref(), Some("pam-client-nonexistent-user"));
+		assert_eq!(attempt.flags, Flag::SILENT);
+		assert!(!attempt.is_success());
+		assert_eq!(
+			verify_password_attempt("test", "us\0er", "password")
+				.unwrap_err()
+				.code(),
+			ErrorCode::BUF_ERR
+		);
+	}
+
+	#[test]
+	fn test_authenticate_multi() {
+		let results = authenticate_multi(&["test", "te\0st"], "pam-client-nonexistent-user", "");
+		assert_eq!(results.len(), 2);
+		assert_eq!(results[0].0, "test");
+		assert!(results[0].1.is_err());
+		assert_eq!(
+			results[1].1.as_ref().unwrap_err().code(),
+			ErrorCode::BUF_ERR
+		);
+		assert!(authenticate_multi(&[], "user", "").is_empty());
+	}
+}